@@ -2,7 +2,15 @@
 //! Contient les gestionnaires pour les routes, les modèles de données, 
 //! le routeur, et les middlewares.
 pub mod handlers_auth;
+mod handlers_admin;
+#[cfg(feature = "test-auth")]
+mod handlers_test_auth;
 mod models;
 mod middlewares;
+mod pages;
 pub mod router;
+mod session_store;
+#[cfg(test)]
+pub(crate) mod test_app;
+pub mod tls;
 pub mod handlers_unauth;
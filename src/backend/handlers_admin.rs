@@ -0,0 +1,155 @@
+//! Gestion des routes réservées aux administrateurs.
+
+use axum::{extract::Query, http::StatusCode, response::ErrorResponse, Json};
+use serde::Deserialize;
+use serde_json::json;
+use validator::Validate;
+use crate::backend::handlers_auth::{find_post, hide_post, remove_post};
+use crate::backend::handlers_unauth::{pending_challenges, recovery_link};
+use crate::backend::middlewares::{ApiJson, PreferredLocale, RecentlyAuthenticatedUser};
+use crate::backend::models::{AdminRecoveryRequest, FlagAction, FlagResolution, MaintenanceRequest, RecoveryDelivery};
+use crate::database::token::{self, TokenKind};
+use crate::database::{flag, invite, user};
+use crate::email::send_mail;
+use crate::config;
+use crate::utils::i18n::{Locale, Text};
+use crate::utils::input::MailValidation;
+
+/// Génère un nouveau code d'invitation à usage unique
+pub async fn create_invite() -> axum::response::Result<Json<serde_json::Value>> {
+    let code = invite::generate()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create invite"))?;
+
+    Ok(Json(json!({ "code": code })))
+}
+
+/// Paramètres de la vérification de disponibilité d'un email
+#[derive(Deserialize)]
+pub struct EmailQuery {
+    pub email: String,
+}
+
+/// Indique si un email est encore libre. Réservé aux administrateurs, car il permettrait
+/// sinon d'énumérer les comptes existants.
+pub async fn email_available(
+    Query(query): Query<EmailQuery>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let validation_email = MailValidation { email: query.email };
+    validation_email.validate().map_err(|e| {
+        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()}))))
+    })?;
+
+    let exists = user::exists(&validation_email.email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))?;
+
+    Ok(Json(json!({ "available": !exists })))
+}
+
+/// Émet un lien de récupération pour un utilisateur qui a perdu toutes ses passkeys.
+/// Le lien est envoyé au compte, ou retourné pour être transmis par un autre canal.
+/// L'administrateur doit s'être authentifié récemment ; chaque émission est tracée.
+pub async fn issue_recovery(
+    RecentlyAuthenticatedUser { email: admin }: RecentlyAuthenticatedUser,
+    ApiJson(request): ApiJson<AdminRecoveryRequest>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let email = request.email;
+    if !user::exists(&email).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read users"))? {
+        return Err((StatusCode::NOT_FOUND, "User not found").into());
+    }
+
+    let recovery_token = token::generate(&email, TokenKind::Recovery)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create recovery token"))?;
+    let link = recovery_link(&recovery_token);
+    tracing::info!(target: "audit", admin, user = email, delivery = ?request.delivery, "Recovery link issued");
+
+    match request.delivery {
+        RecoveryDelivery::Email => {
+            let locale = PreferredLocale(Locale::default()).for_recipient(&email);
+            let (subject, body) = locale.mail(Text::RecoverySubject, Text::RecoveryBody, &link);
+            send_mail(&email, subject, &body)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send recovery email"))?;
+            Ok(Json(json!({ "message": "Recovery email sent" })))
+        }
+        RecoveryDelivery::OutOfBand => Ok(Json(json!({ "link": link }))),
+    }
+}
+
+/// Envoie un email de test à l'adresse indiquée, par le même chemin que les autres emails,
+/// pour vérifier la configuration d'envoi sans créer de compte
+pub async fn send_test_email(
+    ApiJson(request): ApiJson<MailValidation>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    request.validate().map_err(|e| {
+        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()}))))
+    })?;
+
+    let body = "This is a test message. Email delivery is configured correctly.";
+    send_mail(&request.email, "Test email", body).map_err(|err| {
+        log::warn!("Test email failed: {:#}", err);
+        ErrorResponse::from((StatusCode::BAD_GATEWAY, Json(json!({ "sent": false, "error": err.to_string() }))))
+    })?;
+
+    Ok(Json(json!({ "sent": true })))
+}
+
+/// Active ou désactive le mode maintenance sans redémarrer le serveur.
+/// La valeur n'est pas persistée : au redémarrage, `MAINTENANCE_MODE` s'applique à nouveau.
+pub async fn set_maintenance(ApiJson(request): ApiJson<MaintenanceRequest>) -> Json<serde_json::Value> {
+    let mut updated = (*config::get()).clone();
+    updated.maintenance_mode = request.enabled;
+    config::set(updated);
+    log::warn!("Maintenance mode {}", if request.enabled { "enabled" } else { "disabled" });
+
+    Json(json!({ "maintenance": request.enabled }))
+}
+
+/// Métriques internes : taille des stockages de challenges WebAuthn et leur limite
+pub async fn metrics() -> Json<serde_json::Value> {
+    let (registration, authentication) = pending_challenges().await;
+    Json(json!({
+        "challenge_states": {
+            "registration": registration,
+            "authentication": authentication,
+            "cap": config::get().max_pending_challenges,
+        },
+    }))
+}
+
+/// Liste les posts signalés, les plus signalés en premier
+pub async fn list_flags() -> axum::response::Result<Json<serde_json::Value>> {
+    let pending = flag::pending()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read flags"))?;
+
+    let mut entries: Vec<_> = pending.into_iter().filter(|(_, flags)| !flags.is_empty()).collect();
+    entries.sort_by_key(|(_, flags)| std::cmp::Reverse(flags.len()));
+
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|(post_id, flags)| json!({ "post_id": post_id, "post": find_post(&post_id), "flags": flags }))
+        .collect();
+    Ok(Json(json!(entries)))
+}
+
+/// Traite les signalements d'un post : le masquer, le supprimer ou rejeter les signalements
+pub async fn resolve_flag(
+    ApiJson(resolution): ApiJson<FlagResolution>,
+) -> axum::response::Result<StatusCode> {
+    let post_id = resolution.post_id;
+    match resolution.action {
+        FlagAction::Hide => {
+            if !hide_post(&post_id) {
+                return Err((StatusCode::NOT_FOUND, "Post not found").into());
+            }
+        }
+        // Les signalements sont retirés avec le post
+        FlagAction::Delete => {
+            remove_post(post_id, None)?;
+            return Ok(StatusCode::OK);
+        }
+        FlagAction::Dismiss => {}
+    }
+
+    flag::clear(&post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to clear flags"))?;
+    Ok(StatusCode::OK)
+}
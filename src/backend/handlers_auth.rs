@@ -1,8 +1,9 @@
 //! Gestion des routes nécessitant une authentification utilisateur.
 
 use axum::{
-    extract::{Multipart, Query},
-    response::{Html, IntoResponse},
+    body::{Body, Bytes},
+    extract::{multipart::Field, Multipart, Path, Query},
+    response::{IntoResponse, Response},
     Json, Extension,
 };
 use anyhow::anyhow;
@@ -11,19 +12,38 @@ use http::StatusCode;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::{
     collections::HashMap,
-    fs::{create_dir_all, File},
-    io::Write,
-    path::Path,
+    path::PathBuf,
     sync::{Arc, RwLock},
 };
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
 use axum::response::ErrorResponse;
 use image::ImageFormat;
 use uuid::Uuid;
+use tower_sessions::Session;
 use validator::Validate;
-use crate::consts;
-use crate::utils::input::{PostValidation};
+use webauthn_rs::prelude::PasskeyAuthentication;
+use crate::backend::handlers_unauth::{
+    ceremony_error, record_discoverable, registration_display_name, render_page_with, response_error,
+};
+use crate::backend::middlewares::{
+    mark_reauthenticated, ApiJson, PreferredLocale, RecentlyAuthenticatedUser, SessionUser, ValidatedJson,
+};
+use crate::backend::models::{
+    FlagRequest, PasskeyAddRequest, PasskeyVerifyRequest, ProfileUpdate, SettingsUpdate, WebAuthnChallenge,
+};
+use crate::backend::pages::HomePage;
+use crate::{config, consts, database};
+use crate::utils::ceremony::{self, Ceremony};
+use crate::utils::challenge_store::ChallengeStore;
+use crate::utils::input::{validate_description, validate_filename, PostValidation};
+use crate::utils::webauthn::{
+    begin_authentication_with, begin_registration, complete_authentication, complete_registration, decode_challenge,
+    parse_authentication_response, parse_registration_response, RegistrationOptions, StoredRegistrationState,
+};
 
 /// Modèle représentant un post avec des likes
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -32,6 +52,39 @@ pub struct Post {
     pub content: String,
     pub image_path: Option<String>,
     pub likes: i32,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Nom d'origine de l'image, validé, uniquement pour l'affichage
+    #[serde(default)]
+    pub image_name: Option<String>,
+    /// Upload associé au post, supprimé avec lui
+    #[serde(default)]
+    pub attachment: Option<Uuid>,
+    /// Date de création, en secondes depuis l'epoch Unix (0 pour les anciens posts)
+    #[serde(default)]
+    pub created_at: u64,
+    /// Masqué par un administrateur après signalement
+    #[serde(default)]
+    pub hidden: bool,
+    /// Un post privé (brouillon) n'est visible que par son auteur et les administrateurs
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Visibilité d'un post
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+}
+
+impl Post {
+    /// Le post apparaît-il dans les listes de `viewer` ? Les posts masqués n'apparaissent pour personne.
+    fn visible_to(&self, viewer: &str, admin: bool) -> bool {
+        !self.hidden && (self.visibility == Visibility::Public || admin || self.author.as_deref() == Some(viewer))
+    }
 }
 
 /// Base de données statique pour les posts (simulée en mémoire)
@@ -41,25 +94,162 @@ static POSTS: Lazy<RwLock<Vec<Post>>> = Lazy::new(|| {
 
 /// Affiche la page principale avec la liste des posts
 pub async fn home(
+    SessionUser { email }: SessionUser,
     Extension(hbs): Extension<Arc<Handlebars<'_>>>,
     Query(params): Query<HashMap<String, String>>,
+    PreferredLocale(locale): PreferredLocale,
 ) -> impl IntoResponse {
     let user = params.get("user").cloned().unwrap_or_else(|| "Guest".to_string());
-    let data = json!({
-        "user": user,
-        "posts": *POSTS.read().unwrap(),
+    let admin = database::user::is_admin(&email);
+    let posts: Vec<Post> = POSTS.read().unwrap().iter().filter(|post| post.visible_to(&email, admin)).cloned().collect();
+    let data = HomePage {
+        user,
+        posts,
+        lang: locale.code(),
+        t: locale.page_texts(),
+    };
+
+    render_page_with(&hbs, "home", &data)
+}
+
+/// Paramètres de pagination de la liste des posts.
+/// `cursor` (valeur de `X-Next-Cursor` de la page précédente) est prioritaire sur `offset`.
+#[derive(Deserialize)]
+pub struct PostsQuery {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_page_size")]
+    pub limit: usize,
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: PostSort,
+}
+
+/// Ordre de la liste des posts ; une valeur inconnue est refusée (400)
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Les plus likés d'abord, puis les plus récents
+    Popular,
+}
+
+impl PostSort {
+    /// Position d'un post pour cet ordre ; les likes ne comptent que pour `Popular`
+    fn key(self, post: &Post) -> SortKey {
+        let likes = if self == PostSort::Popular { post.likes } else { 0 };
+        (likes, post_key(post))
+    }
+
+    /// Place de `a` par rapport à `b` dans la liste
+    fn compare(self, a: &SortKey, b: &SortKey) -> std::cmp::Ordering {
+        match self {
+            PostSort::Oldest => a.cmp(b),
+            PostSort::Newest | PostSort::Popular => b.cmp(a),
+        }
+    }
+}
+
+fn default_page_size() -> usize {
+    consts::MAX_PAGE_SIZE
+}
+
+/// Position d'un post dans la liste : date de création, puis identifiant pour départager
+type PostKey = (u64, Uuid);
+
+fn post_key(post: &Post) -> PostKey {
+    (post.created_at, post.id)
+}
+
+/// Position d'un post selon l'ordre demandé : likes, puis date de création et identifiant
+type SortKey = (i32, PostKey);
+
+/// Curseur opaque désignant le dernier post d'une page
+fn encode_cursor((likes, (created_at, id)): SortKey) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", likes, created_at, id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<SortKey> {
+    use base64::Engine;
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let mut parts = std::str::from_utf8(&bytes).ok()?.splitn(3, ':');
+    let (likes, created_at, id) = (parts.next()?, parts.next()?, parts.next()?);
+    Some((likes.parse().ok()?, (created_at.parse().ok()?, Uuid::parse_str(id).ok()?)))
+}
+
+/// Liste les posts en JSON dans l'ordre demandé par `sort` (les plus récents par défaut) ;
+/// le tableau est sérialisé post par post pendant l'envoi. S'il reste des posts, l'en-tête
+/// `X-Next-Cursor` permet de continuer sans doublon ni saut, même si des posts sont ajoutés
+/// ou supprimés entre deux pages.
+pub async fn list_posts(
+    SessionUser { email }: SessionUser,
+    Query(query): Query<PostsQuery>,
+) -> axum::response::Result<Response> {
+    let limit = query.limit.min(consts::MAX_PAGE_SIZE);
+    let admin = database::user::is_admin(&email);
+    let sort = query.sort;
+    let after = match query.cursor.as_deref() {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or((StatusCode::BAD_REQUEST, "Invalid cursor"))?),
+        None => None,
+    };
+
+    let mut posts: Vec<Post> = POSTS
+        .read()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .iter()
+        .filter(|post| {
+            post.visible_to(&email, admin)
+                && after.is_none_or(|after| sort.compare(&after, &sort.key(post)).is_lt())
+        })
+        .cloned()
+        .collect();
+    posts.sort_by(|a, b| sort.compare(&sort.key(a), &sort.key(b)));
+
+    let skip = if after.is_some() { 0 } else { query.offset };
+    let remaining = posts.len().saturating_sub(skip);
+    let page: Vec<Post> = posts.into_iter().skip(skip).take(limit).collect();
+    let next_cursor = page
+        .last()
+        .filter(|_| remaining > page.len())
+        .map(|last| encode_cursor(sort.key(last)));
+
+    let items = page.into_iter().enumerate().map(|(i, post)| {
+        let mut chunk = if i == 0 { Vec::new() } else { b",".to_vec() };
+        serde_json::to_writer(&mut chunk, &post)?;
+        Ok::<_, serde_json::Error>(Bytes::from(chunk))
     });
+    let chunks = std::iter::once(Ok(Bytes::from_static(b"[")))
+        .chain(items)
+        .chain(std::iter::once(Ok(Bytes::from_static(b"]"))));
 
-    match hbs.render("home", &data) {
-        Ok(body) => Html(body),
-        Err(_) => Html("<h1>Internal Server Error</h1>".to_string()),
+    let mut response = (
+        [(http::header::CONTENT_TYPE, "application/json")],
+        Body::from_stream(futures::stream::iter(chunks)),
+    )
+        .into_response();
+    if let Some(cursor) = next_cursor.and_then(|cursor| http::HeaderValue::from_str(&cursor).ok()) {
+        response.headers_mut().insert("x-next-cursor", cursor);
     }
+    Ok(response)
 }
 
 /// Crée un nouveau post avec texte et image
-pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Json<serde_json::Value>> {
+pub async fn create_post(
+    SessionUser { email }: SessionUser,
+    mut multipart: Multipart,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    // Vérifier le quota avant de traiter l'upload
+    if count_posts_by(&email) >= config::get().max_posts_per_user {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Post quota exceeded").into());
+    }
+
     let mut text_content = None;
-    let mut uploaded_file_path = None;
+    let mut attachment = None;
+    let mut visibility = Visibility::Public;
 
     while let Some(field) = multipart.next_field().await? {
         let field_name = field.name().unwrap_or_default().to_string();
@@ -79,41 +269,17 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
             text_content = Some(text);
             
         } else if field_name == "file" {
-            
-            //Valider le content-type
-            let content_type = field.content_type()
-                .ok_or((StatusCode::BAD_REQUEST, "Content-Type required"))?;
-            if !consts::ALLOWED_MIME_TYPES.contains(&content_type) {
-                return Err((StatusCode::BAD_REQUEST, "Invalid file type - only JPEG allowed").into());
-            }
-            
-            let filename = field.file_name().unwrap_or_default().to_string();
-            let file_bytes = field.bytes().await?;
-            
-            //Valider la taille du fichier
-            if file_bytes.len() > consts::MAX_FILE_SIZE as usize {
-                return Err((StatusCode::BAD_REQUEST, "File too large - max 5MB").into());
-            }
-
-            //Valider l'image en utilisant crate
-            let format = image::guess_format(&file_bytes).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid image format"))?;
-            if format != ImageFormat::Jpeg {
-                return Err((StatusCode::BAD_REQUEST, "Invalid format - JPEG required").into());
-            }
-
-            let uploads_dir = consts::UPLOADS_DIR;
-            if !Path::new(uploads_dir).exists() {
-                create_dir_all(uploads_dir).unwrap();
-            }
-
-            let file_path = format!("{}/{}", uploads_dir, filename);
-            let mut file = File::create(&file_path).unwrap();
-
-
-            file.write_all(&file_bytes).unwrap();
-
-            // Chemin relatif utilisé par le frontend
-            uploaded_file_path = Some(format!("{}/{}", consts::UPLOADS_DIR, filename));
+            attachment = Some(store_upload(&email, field).await?);
+        } else if field_name == "attachment" {
+            // Référence à une image envoyée au préalable via `/upload`
+            let text = field.text().await.unwrap_or_default();
+            let id = Uuid::parse_str(text.trim()).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid attachment"))?;
+            check_attachment(&email, &id)?;
+            attachment = Some(id);
+        } else if field_name == "visibility" {
+            let text = field.text().await.unwrap_or_default();
+            visibility = serde_json::from_value(json!(text.trim()))
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid visibility"))?;
         }
     }
 
@@ -128,50 +294,359 @@ pub async fn create_post(mut multipart: Multipart) -> axum::response::Result<Jso
         )
     })?;
     
-    let image_path = uploaded_file_path;
-
-    let post_id = save_post(&text, image_path.as_deref());
+    let post_id = save_post(&email, &text, attachment, visibility);
 
     Ok(Json(json!({ "post_id": post_id })))
 }
 
-/// Sauvegarde des posts dans un fichier YAML
-pub fn save_posts_to_file() -> Result<(), anyhow::Error> {
-    let posts = POSTS.read().map_err(|_| anyhow!("Failed to read posts"))?; // Lecture des posts existants
-    let file_path = consts::POSTS_DB_PATH;
-    let file_dir = Path::new(file_path).parent().unwrap();
+/// Envoie une image sans créer de post ; elle pourra être associée à un post via son identifiant
+pub async fn upload_image(
+    SessionUser { email }: SessionUser,
+    mut multipart: Multipart,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("file") {
+            let upload_id = store_upload(&email, field).await?;
+            return Ok(Json(json!({ "upload_id": upload_id })));
+        }
+    }
+
+    Err((StatusCode::BAD_REQUEST, "File is required").into())
+}
+
+/// Paramètres de pagination de la liste des uploads du compte
+#[derive(Deserialize)]
+pub struct UploadsQuery {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_page_size")]
+    pub limit: usize,
+}
+
+/// Liste les uploads du compte connecté, du plus ancien au plus récent, sans leur contenu
+pub async fn list_uploads(
+    SessionUser { email }: SessionUser,
+    Query(query): Query<UploadsQuery>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    let mut uploads = database::upload::owned_by(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read uploads"))?;
+    uploads.sort_by_key(|(id, upload)| (upload.created_at, *id));
+
+    let total = uploads.len();
+    let page: Vec<serde_json::Value> = uploads
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.min(consts::MAX_PAGE_SIZE))
+        .map(|(id, upload)| {
+            json!({
+                "id": id,
+                "name": upload.name,
+                "size": upload.size,
+                // Seules des images JPEG sont acceptées à l'upload
+                "mime": "image/jpeg",
+                "created_at": upload.created_at,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({ "uploads": page, "total": total })))
+}
+
+/// Limite le nombre d'uploads reçus en parallèle, pour borner l'usage du disque
+static UPLOAD_SLOTS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(config::get().max_concurrent_uploads.max(1)));
+
+// Octets conservés en mémoire pour reconnaître le format de l'image
+const IMAGE_HEADER_LEN: usize = 64;
+
+/// Fichier d'un upload en cours de réception ; supprimé s'il n'a pas été déplacé à sa place définitive
+struct TempUpload {
+    path: PathBuf,
+    header: Vec<u8>,
+    /// SHA-256 du contenu, en hexadécimal
+    digest: String,
+    size: u64,
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Écrit le contenu du champ dans `path` au fil de sa réception, sans le garder en mémoire.
+/// La réception s'interrompt dès que `max_size` est dépassé.
+async fn receive_file(mut field: Field<'_>, path: PathBuf, max_size: u64) -> axum::response::Result<TempUpload> {
+    let store_error = || (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store upload");
+    let mut upload = TempUpload { path, header: Vec::new(), digest: String::new(), size: 0 };
+    let mut file = tokio::fs::File::create(&upload.path).await.map_err(|_| store_error())?;
+    let mut hasher = Sha256::new();
+
+    let mut size = 0u64;
+    while let Some(chunk) = field.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err((StatusCode::BAD_REQUEST, "File too large - max 5MB").into());
+        }
 
-    if !file_dir.exists() {
-        create_dir_all(file_dir).or(Err(anyhow!("Failed to create directory for posts.")))?;
+        let missing = IMAGE_HEADER_LEN.saturating_sub(upload.header.len()).min(chunk.len());
+        upload.header.extend_from_slice(&chunk[..missing]);
+        hasher.update(&chunk);
+        file.write_all(&chunk).await.map_err(|_| store_error())?;
     }
+    file.flush().await.map_err(|_| store_error())?;
+    upload.digest = format!("{:x}", hasher.finalize());
+    upload.size = size;
 
-    let file = File::create(file_path).or(Err(anyhow!("Failed to create posts.yaml.")))?;
-    serde_yaml::to_writer(file, &*posts).or(Err(anyhow!("Failed to serialize posts to YAML.")))?;
+    Ok(upload)
+}
+
+/// Valide et enregistre une image uploadée pour `email` ; retourne l'identifiant de l'upload
+async fn store_upload(email: &str, field: Field<'_>) -> axum::response::Result<Uuid> {
+    // Quota du compte, vérifié avant la réception puis avec la taille réelle du fichier
+    let quota = config::get().max_upload_bytes_per_user;
+    let used: u64 = database::upload::owned_by(email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read uploads"))?
+        .iter()
+        .map(|(_, upload)| upload.size)
+        .sum();
+    if used >= quota {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Upload quota exceeded").into());
+    }
+
+    //Valider le content-type
+    let content_type = field.content_type()
+        .ok_or((StatusCode::BAD_REQUEST, "Content-Type required"))?;
+    if !consts::ALLOWED_MIME_TYPES.contains(&content_type) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid file type - only JPEG allowed").into());
+    }
+
+    // Le nom fourni par le client n'est gardé que comme métadonnée
+    let original_name = validate_filename(field.file_name().unwrap_or_default()).map_err(|e| {
+        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.code}))))
+    })?;
+
+    // Au-delà de la limite, l'upload attend qu'une place se libère plutôt que d'échouer
+    let _slot = UPLOAD_SLOTS
+        .acquire()
+        .await
+        .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "Uploads unavailable"))?;
+
+    let store_error = || (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store upload");
+    let tmp_dir = database::resolve(consts::UPLOADS_TMP_DIR);
+    let uploads_dir = database::resolve(consts::UPLOADS_DIR);
+    for dir in [&tmp_dir, &uploads_dir] {
+        tokio::fs::create_dir_all(dir).await.map_err(|_| store_error())?;
+    }
+
+    // La taille est vérifiée pendant la réception
+    let received = receive_file(field, tmp_dir.join(format!("{}.part", Uuid::new_v4())), consts::MAX_FILE_SIZE).await?;
+    if used + received.size > quota {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "Upload quota exceeded").into());
+    }
+
+    //Valider l'image en utilisant crate
+    let format = image::guess_format(&received.header).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid image format"))?;
+    if format != ImageFormat::Jpeg {
+        return Err((StatusCode::BAD_REQUEST, "Invalid format - JPEG required").into());
+    }
+
+    // Un contenu déjà stocké n'est pas dupliqué : le fichier temporaire est alors simplement supprimé
+    let (id, _) = database::upload::create(email, original_name, &received.digest, received.size, &received.path)
+        .map_err(|_| store_error())?;
+
+    Ok(id)
+}
+
+/// Vérifie qu'un upload existe, appartient à `email` et n'est pas déjà associé à un post
+fn check_attachment(email: &str, id: &Uuid) -> Result<(), (StatusCode, &'static str)> {
+    let upload = database::upload::get(id).ok_or((StatusCode::BAD_REQUEST, "Invalid attachment"))?;
+    if upload.owner != email {
+        return Err((StatusCode::FORBIDDEN, "Invalid attachment"));
+    }
+
+    let posts = POSTS.read().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?;
+    if posts.iter().any(|post| post.attachment.as_ref() == Some(id)) {
+        return Err((StatusCode::BAD_REQUEST, "Attachment already used"));
+    }
     Ok(())
 }
 
-/// Charge les posts depuis un fichier YAML
-pub fn load_posts_from_file() -> Result<(), anyhow::Error> {
-    let file_path = consts::POSTS_DB_PATH;
+/// Supprime un post de l'utilisateur connecté, ainsi que son image
+pub async fn delete_post(
+    SessionUser { email }: SessionUser,
+    ApiJson(body): ApiJson<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let post_id = body
+        .get("post_id")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Post ID is required"))?;
+    let post_id = Uuid::parse_str(post_id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
+
+    remove_post(post_id, Some(&email))?;
+    Ok(StatusCode::OK)
+}
+
+/// Supprime un post, son image et ses signalements ; si `author` est donné, seul ce compte
+/// peut supprimer le post
+pub(crate) fn remove_post(post_id: Uuid, author: Option<&str>) -> Result<Post, (StatusCode, &'static str)> {
+    let removed = {
+        let mut posts = POSTS.write().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to write posts"))?;
+        let index = posts
+            .iter()
+            .position(|post| post.id == post_id)
+            .ok_or((StatusCode::NOT_FOUND, "Post not found"))?;
+        if author.is_some_and(|author| posts[index].author.as_deref() != Some(author)) {
+            return Err((StatusCode::FORBIDDEN, "Forbidden"));
+        }
+        posts.remove(index)
+    };
+
+    if let Err(e) = save_posts_to_file() {
+        eprintln!("Failed to save posts: {}", e);
+    }
+
+    if let Some(attachment) = removed.attachment {
+        database::upload::remove(&attachment)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete attachment"))?;
+    }
+    database::flag::clear(&post_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to clear flags"))?;
+
+    Ok(removed)
+}
+
+/// Retourne une copie du post
+pub(crate) fn find_post(post_id: &Uuid) -> Option<Post> {
+    POSTS.read().ok()?.iter().find(|post| post.id == *post_id).cloned()
+}
+
+/// Masque un post ; retourne `false` s'il n'existe pas
+pub(crate) fn hide_post(post_id: &Uuid) -> bool {
+    let found = match POSTS.write() {
+        Ok(mut posts) => posts
+            .iter_mut()
+            .find(|post| post.id == *post_id)
+            .map(|post| post.hidden = true)
+            .is_some(),
+        Err(_) => false,
+    };
+
+    if found {
+        if let Err(e) = save_posts_to_file() {
+            eprintln!("Failed to save posts: {}", e);
+        }
+    }
+    found
+}
+
+/// Signale un post comme inapproprié ; un second signalement du même compte est ignoré.
+/// Un post que le compte ne peut pas voir est traité comme inexistant.
+pub async fn flag_post(
+    SessionUser { email }: SessionUser,
+    ValidatedJson(request): ValidatedJson<FlagRequest>,
+) -> axum::response::Result<StatusCode> {
+    let admin = database::user::is_admin(&email);
+    if !find_post(&request.post_id).is_some_and(|post| post.visible_to(&email, admin)) {
+        return Err((StatusCode::NOT_FOUND, "Post not found").into());
+    }
+
+    database::flag::add(request.post_id, &email, &request.reason)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to flag post"))?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Sauvegarde des posts dans un fichier YAML, écrit de façon atomique comme les autres bases
+pub fn save_posts_to_file() -> Result<(), anyhow::Error> {
+    let posts = POSTS.read().map_err(|_| anyhow!("Failed to read posts"))?; // Lecture des posts existants
+    database::save(&*posts, &database::resolve(consts::POSTS_DB_PATH))
+}
+
+/// Charge les posts depuis un fichier YAML ; un fichier corrompu est mis de côté
+pub fn load_posts_from_file() -> Result<(), database::LoadError> {
+    let file_path = database::resolve(consts::POSTS_DB_PATH);
+    let (loaded_posts, result) = match database::read_yaml::<Vec<Post>>(&file_path) {
+        Ok(loaded_posts) => (loaded_posts, Ok(())),
+        Err(err @ database::LoadError::Corrupt { .. }) => (Vec::new(), Err(err)),
+        Err(err) => return Err(err),
+    };
+
+    let mut posts = POSTS.write().map_err(|_| database::LoadError::Poisoned)?;
+    *posts = loaded_posts;
+    result
+}
 
-    if Path::new(file_path).exists() {
-        let file = File::open(file_path).or(Err(anyhow!("Failed to open posts.yaml.")))?;
-        let loaded_posts: Vec<Post> = serde_yaml::from_reader(file).unwrap_or_default();
+/// Auteur des signalements créés par la vérification des anciens posts
+const READ_REPAIR_REPORTER: &str = "system:read-repair";
 
+/// Supprime les uploads restés sans post au-delà du délai configuré ; retourne le nombre de suppressions
+pub fn purge_unattached_uploads() -> Result<usize, anyhow::Error> {
+    let cutoff = database::now().saturating_sub(config::get().unattached_upload_ttl_secs);
+    let attached: Vec<Uuid> = POSTS
+        .read()
+        .map_err(|_| anyhow!("Failed to read posts"))?
+        .iter()
+        .filter_map(|post| post.attachment)
+        .collect();
+
+    let mut removed = 0;
+    for id in database::upload::created_before(cutoff)? {
+        if !attached.contains(&id) && database::upload::remove(&id)?.is_some() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Vérifie les posts antérieurs à `validate_description` : ceux dont le contenu n'est plus valide
+/// sont masqués et signalés pour revue par un administrateur, sans être supprimés.
+/// Les posts déjà masqués ne sont pas repris. Retourne le nombre de posts signalés.
+pub fn repair_legacy_posts() -> Result<usize, anyhow::Error> {
+    let invalid: Vec<Uuid> = {
         let mut posts = POSTS.write().map_err(|_| anyhow!("Failed to write posts"))?;
-        *posts = loaded_posts;
+        posts
+            .iter_mut()
+            .filter(|post| !post.hidden && validate_description(&post.content).is_err())
+            .map(|post| {
+                post.hidden = true;
+                post.id
+            })
+            .collect()
+    };
+    if invalid.is_empty() {
+        return Ok(0);
     }
 
-    Ok(())
+    for post_id in &invalid {
+        database::flag::add(*post_id, READ_REPAIR_REPORTER, "Legacy content failed validation")?;
+    }
+    save_posts_to_file()?;
+    Ok(invalid.len())
+}
+
+/// Compte les posts publiés par un utilisateur
+fn count_posts_by(email: &str) -> usize {
+    POSTS
+        .read()
+        .map(|posts| posts.iter().filter(|post| post.author.as_deref() == Some(email)).count())
+        .unwrap_or(0)
 }
 
 /// Simule la sauvegarde d'un post dans une base de données
-fn save_post(text: &str, image_path: Option<&str>) -> String {
+fn save_post(author: &str, text: &str, attachment: Option<Uuid>, visibility: Visibility) -> String {
+    let upload = attachment.as_ref().and_then(database::upload::get);
     let new_post = Post {
         id: Uuid::new_v4(),
         content: text.to_string(),
-        image_path: image_path.map(|path| path.to_string()),
+        // Chemin relatif utilisé par le frontend
+        image_path: upload.as_ref().map(|upload| format!("{}/{}", consts::UPLOADS_URL, upload.filename)),
         likes: 0,
+        author: Some(author.to_string()),
+        image_name: upload.and_then(|upload| upload.name),
+        attachment,
+        created_at: database::now(),
+        hidden: false,
+        visibility,
     };
 
     let post_id = new_post.id.to_string();
@@ -188,21 +663,25 @@ fn save_post(text: &str, image_path: Option<&str>) -> String {
     post_id
 }
 
-/// Permet de like un post
-pub async fn like_post(Json(body): Json<serde_json::Value>) -> axum::response::Result<StatusCode> {
+/// Permet de like un post visible par le compte connecté
+pub async fn like_post(
+    SessionUser { email }: SessionUser,
+    ApiJson(body): ApiJson<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
     let post_id = body
         .get("post_id")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Post ID is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "Post ID is required"))?;
     let post_id = Uuid::parse_str(post_id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid Post ID"))?;
 
     let action = body
         .get("action")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Action is required"))?;
+        .ok_or((StatusCode::BAD_REQUEST, "Action is required"))?;
 
+    let admin = database::user::is_admin(&email);
     let mut posts = POSTS.write().map_err(|_| (StatusCode::BAD_REQUEST, "Failed to write posts"))?;
-    let post = posts.iter_mut().find(|post| post.id == post_id);
+    let post = posts.iter_mut().find(|post| post.id == post_id && post.visible_to(&email, admin));
 
     if let Some(post) = post {
         match action {
@@ -227,3 +706,1317 @@ pub async fn like_post(Json(body): Json<serde_json::Value>) -> axum::response::R
 
     Err((StatusCode::NOT_FOUND, "Post not found").into())
 }
+
+/// Liste les passkeys du compte connecté, avec leur dernière utilisation
+pub async fn list_passkeys(SessionUser { email }: SessionUser) -> axum::response::Result<Json<serde_json::Value>> {
+    let user = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+
+    Ok(Json(json!(passkey_summaries(&user))))
+}
+
+/// Passkeys du compte sans leurs clés : identifiant et dernière utilisation
+fn passkey_summaries(user: &database::user::User) -> Vec<serde_json::Value> {
+    user.passkeys
+        .iter()
+        .map(|passkey| {
+            let credential_id = database::user::credential_key(passkey.cred_id());
+            json!({
+                "last_used": user.passkey_last_used.get(&credential_id),
+                "discoverable": user.passkey_discoverable.get(&credential_id),
+                "credential_id": credential_id,
+            })
+        })
+        .collect()
+}
+
+/// Met à jour les préférences du profil ; `locale: null` revient à la langue du navigateur
+pub async fn update_profile(
+    SessionUser { email }: SessionUser,
+    ApiJson(update): ApiJson<ProfileUpdate>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    database::user::set_locale(&email, update.locale)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update profile"))?;
+
+    Ok(Json(json!({ "locale": update.locale })))
+}
+
+/// Déconnecte toutes les sessions du compte, y compris la session courante
+pub async fn logout_all(
+    session: Session,
+    SessionUser { email }: SessionUser,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    // Les sessions d'une génération précédente sont refusées par `SessionUser`
+    database::user::bump_session_generation(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke sessions"))?;
+    session.delete();
+
+    Ok(Json(json!({ "message": "All sessions have been logged out" })))
+}
+
+/// Préférences du compte
+pub async fn settings(SessionUser { email }: SessionUser) -> axum::response::Result<Json<database::user::Settings>> {
+    let user = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+
+    Ok(Json(user.settings()))
+}
+
+/// Modifie les préférences fournies et retourne l'ensemble des préférences
+pub async fn update_settings(
+    SessionUser { email }: SessionUser,
+    ApiJson(update): ApiJson<SettingsUpdate>,
+) -> axum::response::Result<Json<database::user::Settings>> {
+    let settings = database::user::update_settings(&email, |settings| {
+        if let Some(locale) = update.locale {
+            settings.locale = locale;
+        }
+        if let Some(login_notifications) = update.login_notifications {
+            settings.login_notifications = login_notifications;
+        }
+        if let Some(recovery_notifications) = update.recovery_notifications {
+            settings.recovery_notifications = recovery_notifications;
+        }
+        if let Some(remember_me_default) = update.remember_me_default {
+            settings.remember_me_default = remember_me_default;
+        }
+    })
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update settings"))?;
+
+    Ok(Json(settings))
+}
+
+/// Exporte les données du compte connecté (portabilité) : profil, passkeys sans leurs clés,
+/// liste des fichiers uploadés et posts. Le document est envoyé au fil de sa sérialisation.
+pub async fn export_data(
+    RecentlyAuthenticatedUser { email }: RecentlyAuthenticatedUser,
+) -> axum::response::Result<Response> {
+    use futures::StreamExt;
+
+    let user = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    let uploads = database::upload::owned_by(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read uploads"))?;
+    let post_ids: Vec<Uuid> = POSTS
+        .read()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read posts"))?
+        .iter()
+        .filter(|post| post.author.as_deref() == Some(email.as_str()))
+        .map(|post| post.id)
+        .collect();
+
+    let uploads_dir = database::resolve(consts::UPLOADS_DIR);
+    let files: Vec<_> = uploads
+        .iter()
+        .map(|(id, upload)| {
+            json!({
+                "id": id,
+                "name": upload.name,
+                "url": format!("{}/{}", consts::UPLOADS_URL, upload.filename),
+                "size": std::fs::metadata(uploads_dir.join(&upload.filename)).ok().map(|metadata| metadata.len()),
+            })
+        })
+        .collect();
+
+    let header = [
+        ("exported_at", json!(database::now())),
+        ("profile", json!({
+            "email": user.email,
+            "first_name": user.first_name,
+            "last_name": user.last_name,
+            "verified": user.verified,
+            "role": user.role,
+            "locale": user.locale,
+            "created_at": user.created_at,
+        })),
+        ("passkeys", json!(passkey_summaries(&user))),
+        ("uploads", json!(files)),
+    ];
+    let mut head = String::from("{");
+    for (key, value) in header {
+        head.push_str(&format!("{}:{},", json!(key), value));
+    }
+    head.push_str("\"posts\":[");
+
+    // Les posts sont relus par lots sous le verrou, sans copier toute la liste ;
+    // un post supprimé entre-temps est simplement omis
+    let chunks: Vec<Vec<Uuid>> = post_ids.chunks(consts::MAX_PAGE_SIZE).map(<[Uuid]>::to_vec).collect();
+    let mut first = true;
+    let posts = futures::stream::iter(chunks).map(move |chunk| {
+        let posts = POSTS.read().map_err(|_| serde_json::Error::io(std::io::Error::other("Failed to read posts")))?;
+        let mut bytes = Vec::new();
+        for post in posts.iter().filter(|post| chunk.contains(&post.id)) {
+            if !std::mem::take(&mut first) {
+                bytes.push(b',');
+            }
+            serde_json::to_writer(&mut bytes, post)?;
+        }
+        Ok::<_, serde_json::Error>(Bytes::from(bytes))
+    });
+    let body = futures::stream::once(async move { Ok(Bytes::from(head)) })
+        .chain(posts)
+        .chain(futures::stream::once(async { Ok(Bytes::from_static(b"]}")) }));
+
+    Response::builder()
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header(http::header::CONTENT_DISPOSITION, "attachment; filename=\"export.json\"")
+        .body(Body::from_stream(body))
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build export").into())
+}
+
+/// Ajouts de passkey en cours, liés au compte qui les a démarrés
+static PASSKEY_STATES: Lazy<RwLock<ChallengeStore<(String, StoredRegistrationState)>>> =
+    Lazy::new(Default::default);
+
+/// Début de l'ajout d'une passkey supplémentaire au compte connecté
+pub async fn passkey_add_begin(
+    SessionUser { email }: SessionUser,
+) -> axum::response::Result<Json<WebAuthnChallenge<RegistrationOptions>>> {
+    let display_name = match database::user::get(&email) {
+        Ok(Some(user)) => {
+            check_passkey_limit(&user)?;
+            registration_display_name(&email, user.first_name.as_deref(), user.last_name.as_deref())
+        }
+        Ok(None) => email.clone(),
+        Err(_) => return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user").into()),
+    };
+    let (public_key, stored_state) = begin_registration(&email, &display_name)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start registration"))?;
+
+    let state_id = Uuid::new_v4().to_string();
+    PASSKEY_STATES
+        .write()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store state"))?
+        .insert(state_id.clone(), (email, stored_state), config::get().max_pending_challenges);
+    ceremony::begin(Ceremony::Registration, &state_id);
+
+    Ok(Json(WebAuthnChallenge {
+        challenge: public_key,
+        state_id,
+    }))
+}
+
+/// Fin de l'ajout d'une passkey : elle s'ajoute à celles déjà enregistrées
+pub async fn passkey_add_complete(
+    SessionUser { email }: SessionUser,
+    ApiJson(request): ApiJson<PasskeyAddRequest>,
+) -> axum::response::Result<StatusCode> {
+    let completion = ceremony::complete(Ceremony::Registration, &request.state_id);
+
+    // L'état doit avoir été créé par le même compte
+    let (owner, stored_state) = PASSKEY_STATES
+        .write()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read state"))?
+        .remove(&request.state_id)
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid state"))?;
+    if owner != email {
+        return Err((StatusCode::BAD_REQUEST, "Invalid state").into());
+    }
+
+    let response = parse_registration_response(request.response).map_err(response_error)?;
+
+    // Un authentificateur déjà enregistré est refusé par la liste d'exclusion
+    let passkey = complete_registration(&response, &stored_state)
+        .await
+        .map_err(|err| ceremony_error(StatusCode::BAD_REQUEST, &err))?;
+
+    // La limite est vérifiée à nouveau : d'autres ajouts ont pu aboutir entre-temps
+    if let Some(user) = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+    {
+        check_passkey_limit(&user)?;
+    }
+    database::user::add_passkey(&email, passkey, config::get().max_passkeys_per_user)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Failed to add passkey"))?;
+    record_discoverable(&email, &response);
+
+    completion.succeed();
+    Ok(StatusCode::OK)
+}
+
+/// Refuse un nouvel ajout si le compte a déjà atteint le nombre maximal de passkeys
+fn check_passkey_limit(user: &database::user::User) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let max = config::get().max_passkeys_per_user;
+    if user.passkeys.len() < max {
+        return Ok(());
+    }
+    Err((
+        StatusCode::CONFLICT,
+        Json(json!({
+            "error": format!("You already have {} passkeys, the maximum allowed. Delete an old passkey before adding a new one.", max),
+            "code": "PASSKEY_LIMIT",
+        })),
+    ))
+}
+
+/// Supprime une passkey du compte. Action sensible : une authentification récente est exigée.
+/// La dernière passkey ne peut pas être supprimée, le compte deviendrait inaccessible.
+pub async fn delete_passkey(
+    RecentlyAuthenticatedUser { email }: RecentlyAuthenticatedUser,
+    Path(credential_id): Path<String>,
+) -> axum::response::Result<StatusCode> {
+    let user = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+        .ok_or((StatusCode::NOT_FOUND, "User not found"))?;
+    let owned = user.passkeys.iter().any(|passkey| database::user::credential_key(passkey.cred_id()) == credential_id);
+    if !owned {
+        return Err((StatusCode::NOT_FOUND, "Passkey not found").into());
+    }
+    if user.passkeys.len() == 1 {
+        return Err((StatusCode::CONFLICT, "Cannot delete your only passkey").into());
+    }
+
+    match database::user::remove_passkey(&email, &credential_id) {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "Passkey not found").into()),
+        Err(_) => Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete passkey").into()),
+    }
+}
+
+/// Vérification de passkey en cours, liée au compte qui l'a démarrée
+struct PendingVerification {
+    email: String,
+    state: PasskeyAuthentication,
+    server_challenge: Vec<u8>,
+}
+
+static VERIFY_STATES: Lazy<RwLock<ChallengeStore<PendingVerification>>> = Lazy::new(Default::default);
+
+/// Début de la vérification d'une passkey précise : seule celle-ci figure dans `allowCredentials`
+pub async fn passkey_verify_begin(
+    SessionUser { email }: SessionUser,
+    ApiJson(request): ApiJson<PasskeyVerifyRequest>,
+) -> axum::response::Result<Json<WebAuthnChallenge>> {
+    let owns_passkey = database::user::get(&email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?
+        .is_some_and(|user| user.passkeys.iter().any(|passkey| passkey.cred_id() == &request.credential_id));
+    if !owns_passkey {
+        return Err((StatusCode::NOT_FOUND, "Passkey not found").into());
+    }
+
+    let (public_key, auth_state) = begin_authentication_with(&email, &request.credential_id)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start authentication"))?;
+    let server_challenge = public_key["challenge"]
+        .as_str()
+        .and_then(decode_challenge)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to start authentication"))?;
+    let pending = PendingVerification {
+        email,
+        state: auth_state,
+        server_challenge,
+    };
+
+    let state_id = Uuid::new_v4().to_string();
+    VERIFY_STATES
+        .write()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store state"))?
+        .insert(state_id.clone(), pending, config::get().max_pending_challenges);
+    ceremony::begin(Ceremony::Authentication, &state_id);
+
+    Ok(Json(WebAuthnChallenge {
+        challenge: public_key,
+        state_id,
+    }))
+}
+
+/// Fin de la vérification : réussit uniquement avec la passkey demandée au début
+pub async fn passkey_verify_complete(
+    SessionUser { email }: SessionUser,
+    session: Session,
+    ApiJson(request): ApiJson<PasskeyAddRequest>,
+) -> axum::response::Result<StatusCode> {
+    let completion = ceremony::complete(Ceremony::Authentication, &request.state_id);
+
+    let pending = VERIFY_STATES
+        .write()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read state"))?
+        .remove(&request.state_id)
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid state"))?;
+    if pending.email != email {
+        return Err((StatusCode::BAD_REQUEST, "Invalid state").into());
+    }
+
+    let response = parse_authentication_response(request.response).map_err(response_error)?;
+
+    complete_authentication(&email, &response, &pending.state, &pending.server_challenge)
+        .await
+        .map_err(|err| ceremony_error(StatusCode::UNAUTHORIZED, &err))?;
+
+    // La vérification vaut réauthentification pour les actions sensibles
+    mark_reauthenticated(&session)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set session"))?;
+
+    completion.succeed();
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::FromRequest, http::Request};
+    use crate::utils::webauthn::tests::{test_passkey, SoftAuthenticator};
+    use webauthn_rs::prelude::Base64UrlSafeData;
+
+    /// Construit un formulaire multipart contenant les champs texte donnés
+    async fn multipart(fields: &[(&str, &str)]) -> Multipart {
+        let boundary = "lab02-boundary";
+        let mut body = String::new();
+        for (name, value) in fields {
+            body.push_str(&format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            ));
+        }
+        body.push_str(&format!("--{boundary}--\r\n"));
+
+        let request = Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, format!("multipart/form-data; boundary={boundary}"))
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    async fn create_text_post(email: &str) -> StatusCode {
+        let session_user = SessionUser { email: email.to_string() };
+        create_post(session_user, multipart(&[("text", "Bonjour !")]).await)
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_post_quota() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let config = config::Config {
+            max_posts_per_user: 2,
+            ..Default::default()
+        };
+
+        config::scope(config, async {
+            assert_eq!(create_text_post(&email).await, StatusCode::OK);
+            assert_eq!(create_text_post(&email).await, StatusCode::OK);
+            assert_eq!(create_text_post(&email).await, StatusCode::TOO_MANY_REQUESTS);
+
+            // Le quota est propre à chaque utilisateur
+            assert_eq!(create_text_post("other@example.com").await, StatusCode::OK);
+        })
+        .await;
+
+        assert_eq!(count_posts_by(&email), 2);
+    }
+
+    /// Formulaire multipart avec un texte et une image JPEG nommée `filename`
+    async fn multipart_with_image(filename: &str) -> Multipart {
+        multipart_with_jpeg(filename, &unique_jpeg()).await
+    }
+
+    /// Image JPEG dont le contenu diffère à chaque appel (données ajoutées après la fin de l'image)
+    fn unique_jpeg() -> Vec<u8> {
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(1, 1).write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+        let mut jpeg = jpeg.into_inner();
+        jpeg.extend_from_slice(Uuid::new_v4().as_bytes());
+        jpeg
+    }
+
+    async fn multipart_with_jpeg(filename: &str, jpeg: &[u8]) -> Multipart {
+        let boundary = "lab02-boundary";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"text\"\r\n\r\nBonjour !\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: image/jpeg\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(jpeg);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let request = Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, format!("multipart/form-data; boundary={boundary}"))
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    /// Ajoute une passkey au compte de `email` avec `authenticator`
+    async fn add_passkey(email: &str, authenticator: &SoftAuthenticator) -> StatusCode {
+        add_passkey_with_extensions(email, authenticator, json!({})).await
+    }
+
+    /// Ajoute une passkey dont la réponse porte les résultats d'extensions `extensions`
+    async fn add_passkey_with_extensions(
+        email: &str,
+        authenticator: &SoftAuthenticator,
+        extensions: serde_json::Value,
+    ) -> StatusCode {
+        let session_user = || SessionUser { email: email.to_string() };
+        let Json(challenge) = passkey_add_begin(session_user()).await.unwrap();
+        let mut response = authenticator.register(&challenge.challenge);
+        response["extensions"] = extensions;
+        let request = PasskeyAddRequest { state_id: challenge.state_id, response };
+        passkey_add_complete(session_user(), ApiJson(request))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_discoverability_follows_cred_props() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        database::user::set_passkey(&email, test_passkey()).unwrap();
+
+        let resident = SoftAuthenticator::new();
+        let non_resident = SoftAuthenticator::new();
+        let silent = SoftAuthenticator::new();
+        for (authenticator, extensions) in [
+            (&resident, json!({ "credProps": { "rk": true } })),
+            (&non_resident, json!({ "credProps": { "rk": false } })),
+            (&silent, json!({})),
+        ] {
+            assert_eq!(add_passkey_with_extensions(&email, authenticator, extensions).await, StatusCode::OK);
+        }
+
+        let user = database::user::get(&email).unwrap().unwrap();
+        let discoverable = |authenticator: &SoftAuthenticator| {
+            user.passkey_discoverable.get(&database::user::credential_key(&authenticator.cred_id)).copied()
+        };
+        assert_eq!(discoverable(&resident), Some(true));
+        assert_eq!(discoverable(&non_resident), Some(false));
+        assert_eq!(discoverable(&silent), None);
+
+        // L'indication est exposée dans la liste des passkeys
+        let Json(passkeys) = list_passkeys(SessionUser { email: email.clone() }).await.unwrap();
+        let resident_id = database::user::credential_key(&resident.cred_id);
+        let summary = passkeys.as_array().unwrap().iter().find(|p| p["credential_id"] == resident_id.as_str()).unwrap();
+        assert_eq!(summary["discoverable"], true);
+    }
+
+    #[tokio::test]
+    async fn test_settings_defaults_and_partial_update() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let session_user = || SessionUser { email: email.clone() };
+        let update = |body: serde_json::Value| async move {
+            let Json(settings) = update_settings(session_user(), ApiJson(serde_json::from_value(body).unwrap()))
+                .await
+                .unwrap();
+            serde_json::to_value(settings).unwrap()
+        };
+
+        let Json(defaults) = settings(session_user()).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(defaults).unwrap(),
+            json!({ "locale": null, "login_notifications": false, "recovery_notifications": true, "remember_me_default": false })
+        );
+
+        // Seuls les champs fournis changent
+        let updated = update(json!({ "login_notifications": true })).await;
+        assert_eq!(updated, json!({ "locale": null, "login_notifications": true, "recovery_notifications": true, "remember_me_default": false }));
+        let updated = update(json!({ "locale": "fr", "remember_me_default": true })).await;
+        assert_eq!(updated, json!({ "locale": "fr", "login_notifications": true, "recovery_notifications": true, "remember_me_default": true }));
+        let updated = update(json!({ "recovery_notifications": false })).await;
+        assert_eq!(updated["recovery_notifications"], false);
+        let updated = update(json!({ "locale": null })).await;
+        assert_eq!(updated["locale"], json!(null));
+        assert_eq!(updated["login_notifications"], true);
+
+        let Json(stored) = settings(session_user()).await.unwrap();
+        assert_eq!(serde_json::to_value(stored).unwrap(), updated);
+
+        // Valeurs et champs inconnus refusés
+        assert!(serde_json::from_value::<SettingsUpdate>(json!({ "locale": "de" })).is_err());
+        assert!(serde_json::from_value::<SettingsUpdate>(json!({ "theme": "dark" })).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_second_passkey() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        database::user::set_passkey(&email, test_passkey()).unwrap();
+
+        let authenticator = SoftAuthenticator::new();
+        assert_eq!(add_passkey(&email, &authenticator).await, StatusCode::OK);
+        assert_eq!(database::user::get(&email).unwrap().unwrap().passkeys.len(), 2);
+
+        // Le même authentificateur ne peut pas être enregistré deux fois
+        assert_eq!(add_passkey(&email, &authenticator).await, StatusCode::BAD_REQUEST);
+        assert_eq!(database::user::get(&email).unwrap().unwrap().passkeys.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_passkey_limit() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        database::user::set_passkey(&email, test_passkey()).unwrap();
+
+        let config = config::Config {
+            max_passkeys_per_user: 2,
+            ..Default::default()
+        };
+        config::scope(config, async {
+            let first = SoftAuthenticator::new();
+            assert_eq!(add_passkey(&email, &first).await, StatusCode::OK);
+
+            // Limite atteinte : refus dès le début de la cérémonie, avec un message explicite
+            let response = passkey_add_begin(SessionUser { email: email.clone() }).await.into_response();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["code"], "PASSKEY_LIMIT");
+            assert!(body["error"].as_str().unwrap().contains("Delete an old passkey"));
+            assert_eq!(database::user::get(&email).unwrap().unwrap().passkeys.len(), 2);
+
+            // Une suppression libère une place
+            let credential_id = database::user::credential_key(&first.cred_id);
+            let deleted = delete_passkey(RecentlyAuthenticatedUser { email: email.clone() }, Path(credential_id.clone()))
+                .await
+                .unwrap();
+            assert_eq!(deleted, StatusCode::NO_CONTENT);
+            assert_eq!(add_passkey(&email, &SoftAuthenticator::new()).await, StatusCode::OK);
+            assert_eq!(database::user::get(&email).unwrap().unwrap().passkeys.len(), 2);
+
+            // Passkey inconnue
+            let missing = delete_passkey(RecentlyAuthenticatedUser { email: email.clone() }, Path(credential_id))
+                .await
+                .into_response();
+            assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_last_passkey_cannot_be_deleted() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let passkey = test_passkey();
+        database::user::set_passkey(&email, passkey.clone()).unwrap();
+
+        let credential_id = database::user::credential_key(passkey.cred_id());
+        let response = delete_passkey(RecentlyAuthenticatedUser { email: email.clone() }, Path(credential_id))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(database::user::get(&email).unwrap().unwrap().passkeys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_single_passkey() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        database::user::set_passkey(&email, test_passkey()).unwrap();
+        let authenticator = SoftAuthenticator::new();
+        assert_eq!(add_passkey(&email, &authenticator).await, StatusCode::OK);
+
+        let credential_id = serde_json::to_value(Base64UrlSafeData::from(authenticator.cred_id.clone())).unwrap();
+        let session_user = || SessionUser { email: email.clone() };
+        let verify_begin = || async {
+            let request = serde_json::from_value(json!({ "credential_id": credential_id })).unwrap();
+            passkey_verify_begin(session_user(), ApiJson(request)).await.unwrap().0
+        };
+
+        // Seule la passkey demandée est proposée au navigateur
+        let challenge = verify_begin().await;
+        let allowed = challenge.challenge["allowCredentials"].as_array().unwrap();
+        assert_eq!(allowed.len(), 1);
+        assert_eq!(allowed[0]["id"], credential_id);
+
+        let request = PasskeyAddRequest {
+            state_id: challenge.state_id,
+            response: authenticator.authenticate(&challenge.challenge),
+        };
+        assert_eq!(passkey_verify_complete(session_user(), Session::new(None), ApiJson(request)).await.unwrap(), StatusCode::OK);
+
+        // La liste des passkeys indique laquelle vient d'être utilisée
+        let Json(passkeys) = list_passkeys(session_user()).await.unwrap();
+        let passkeys = passkeys.as_array().unwrap();
+        assert_eq!(passkeys.len(), 2);
+        for passkey in passkeys {
+            assert_eq!(passkey["last_used"].is_u64(), passkey["credential_id"] == credential_id);
+        }
+
+        // Une autre passkey du compte ne permet pas de terminer la vérification
+        let challenge = verify_begin().await;
+        let request = PasskeyAddRequest {
+            state_id: challenge.state_id,
+            response: SoftAuthenticator::new().authenticate(&challenge.challenge),
+        };
+        assert!(passkey_verify_complete(session_user(), Session::new(None), ApiJson(request)).await.is_err());
+
+        // Une passkey inconnue du compte est refusée dès le début
+        let unknown = serde_json::from_value(json!({ "credential_id": serde_json::to_value(test_passkey().cred_id()).unwrap() })).unwrap();
+        let status = passkey_verify_begin(session_user(), ApiJson(unknown)).await.into_response().status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_export_contains_account_data() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let passkey = test_passkey();
+        database::user::set_passkey(&email, passkey.clone()).unwrap();
+        let session_user = SessionUser { email: email.clone() };
+        let _ = create_post(session_user, multipart_with_image("vacances.jpg").await).await.unwrap();
+        save_post(&email, "Au revoir !", None, Visibility::Private);
+
+        let response = export_data(RecentlyAuthenticatedUser { email: email.clone() }).await.unwrap();
+        assert_eq!(
+            response.headers()[http::header::CONTENT_DISPOSITION],
+            "attachment; filename=\"export.json\""
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let export: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(export["profile"]["email"], email.as_str());
+        assert_eq!(export["profile"]["first_name"], "Jean");
+
+        // Les passkeys sont exportées sans leur clé publique
+        let passkeys = export["passkeys"].as_array().unwrap();
+        assert_eq!(passkeys.len(), 1);
+        assert_eq!(passkeys[0]["credential_id"], database::user::credential_key(passkey.cred_id()));
+        assert!(!String::from_utf8_lossy(&bytes).contains("EC_EC2"));
+
+        let uploads = export["uploads"].as_array().unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0]["name"], "vacances.jpg");
+        assert!(uploads[0]["size"].as_u64().unwrap() > 0);
+
+        let posts = export["posts"].as_array().unwrap();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0]["content"], "Bonjour !");
+        assert_eq!(posts[0]["attachment"], uploads[0]["id"]);
+        assert_eq!(posts[1]["content"], "Au revoir !");
+    }
+
+    async fn flag(email: &str, post_id: &str, reason: &str) -> StatusCode {
+        let request = serde_json::from_value(json!({ "post_id": post_id, "reason": reason })).unwrap();
+        flag_post(SessionUser { email: email.to_string() }, ValidatedJson(request))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_flag_post_is_deduplicated() {
+        let author = format!("{}@example.com", Uuid::new_v4().simple());
+        let post_id = save_post(&author, "Bonjour !", None, Visibility::Public);
+
+        assert_eq!(flag(&author, &post_id, "Spam").await, StatusCode::OK);
+        assert_eq!(flag(&author, &post_id, "Toujours du spam").await, StatusCode::OK);
+        assert_eq!(flag("other@example.com", &post_id, "Offensant").await, StatusCode::OK);
+        assert_eq!(flag(&author, &Uuid::new_v4().to_string(), "Spam").await, StatusCode::NOT_FOUND);
+
+        let flags = &database::flag::pending().unwrap()[&Uuid::parse_str(&post_id).unwrap()];
+        assert_eq!(flags.len(), 2);
+        assert_eq!(flags[0].reason, "Spam");
+        assert_eq!(flags[1].reporter, "other@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_admin_reviews_flagged_posts() {
+        use crate::backend::handlers_admin::{list_flags, resolve_flag};
+
+        let author = format!("{}@example.com", Uuid::new_v4().simple());
+        let hidden = save_post(&author, "À masquer", None, Visibility::Public);
+        let deleted = save_post(&author, "À supprimer", None, Visibility::Public);
+        for reporter in ["a@example.com", "b@example.com"] {
+            flag(reporter, &hidden, "Spam").await;
+        }
+        flag("a@example.com", &deleted, "Offensant").await;
+
+        // Chaque post signalé apparaît avec ses signalements
+        let Json(review) = list_flags().await.unwrap();
+        let entry = |post_id: &str| review.as_array().unwrap().iter().find(|entry| entry["post_id"] == post_id).cloned().unwrap();
+        assert_eq!(entry(&hidden)["flags"].as_array().unwrap().len(), 2);
+        assert_eq!(entry(&hidden)["post"]["content"], "À masquer");
+        assert_eq!(entry(&deleted)["flags"][0]["reason"], "Offensant");
+
+        let resolve = |post_id: &str, action: &str| {
+            let resolution = serde_json::from_value(json!({ "post_id": post_id, "action": action })).unwrap();
+            resolve_flag(ApiJson(resolution))
+        };
+        assert_eq!(resolve(&hidden, "hide").await.unwrap(), StatusCode::OK);
+        assert_eq!(resolve(&deleted, "delete").await.unwrap(), StatusCode::OK);
+
+        // Le post masqué n'est plus listé, le post supprimé n'existe plus, et les signalements sont traités
+        let (page, _) = fetch_page(None, consts::MAX_PAGE_SIZE).await;
+        let hidden_id = Uuid::parse_str(&hidden).unwrap();
+        let deleted_id = Uuid::parse_str(&deleted).unwrap();
+        assert!(POSTS.read().unwrap().iter().any(|post| post.id == hidden_id && post.hidden));
+        assert!(!page.iter().any(|post| post.id == hidden_id));
+        assert!(super::find_post(&deleted_id).is_none());
+        let pending = database::flag::pending().unwrap();
+        assert!(!pending.contains_key(&hidden_id) && !pending.contains_key(&deleted_id));
+    }
+
+    #[tokio::test]
+    async fn test_upload_keeps_validated_name_as_metadata() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let session_user = SessionUser { email: email.clone() };
+        let Json(body) = create_post(session_user, multipart_with_image("../vacances.jpg").await)
+            .await
+            .unwrap();
+
+        let post_id = Uuid::parse_str(body["post_id"].as_str().unwrap()).unwrap();
+        let post = POSTS.read().unwrap().iter().find(|post| post.id == post_id).cloned().unwrap();
+        assert_eq!(post.image_name.as_deref(), Some("vacances.jpg"));
+
+        // Le fichier est stocké sous un nom généré par le serveur
+        let image_path = post.image_path.unwrap();
+        assert!(!image_path.contains("vacances"));
+        let stored = image_path.strip_prefix(&format!("{}/", consts::UPLOADS_URL)).unwrap();
+        assert!(database::resolve(consts::UPLOADS_DIR).join(stored).exists());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_beyond_limit_are_queued() {
+        // Toutes les places sont occupées : les uploads doivent attendre, pas échouer
+        let limit = config::get().max_concurrent_uploads.max(1);
+        let held = UPLOAD_SLOTS.acquire_many(limit as u32).await.unwrap();
+
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let mut tasks = Vec::new();
+        for _ in 0..limit + 2 {
+            let session_user = SessionUser { email: email.clone() };
+            let multipart = multipart_with_image("photo.jpg").await;
+            tasks.push(tokio::spawn(upload_image(session_user, multipart)));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(tasks.iter().all(|task| !task.is_finished()));
+
+        drop(held);
+        for task in tasks {
+            let Json(body) = task.await.unwrap().unwrap();
+            let upload_id = Uuid::parse_str(body["upload_id"].as_str().unwrap()).unwrap();
+            assert_eq!(database::upload::get(&upload_id).unwrap().owner, email);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_upload_is_aborted_early() {
+        use futures::StreamExt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Corps de 1 Mo envoyé par morceaux de 8 Ko, dont on compte la lecture
+        let boundary = "lab02-boundary";
+        let head = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"big.jpg\"\r\n\
+             Content-Type: image/jpeg\r\n\r\n"
+        );
+        let read = Arc::new(AtomicUsize::new(0));
+        let counter = read.clone();
+        // Chaque morceau n'est disponible qu'après un passage par l'ordonnanceur, comme sur le réseau
+        let chunks = futures::stream::iter(0..128).then(move |_| {
+            let counter = counter.clone();
+            async move {
+                tokio::task::yield_now().await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, std::io::Error>(Bytes::from(vec![0xFF; 8 * 1024]))
+            }
+        });
+        let body = futures::stream::once(async move { Ok(Bytes::from(head)) }).chain(chunks);
+
+        let request = Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, format!("multipart/form-data; boundary={boundary}"))
+            .body(Body::from_stream(body))
+            .unwrap();
+        let mut multipart = Multipart::from_request(request, &()).await.unwrap();
+        let field = multipart.next_field().await.unwrap().unwrap();
+
+        let dir = database::resolve(consts::UPLOADS_TMP_DIR);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.part", Uuid::new_v4()));
+        let status = receive_file(field, path.clone(), 16 * 1024).await.map(|_| ()).into_response().status();
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(read.load(Ordering::SeqCst) < 16);
+        // Le fichier partiel est supprimé
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_invalid_name() {
+        let session_user = SessionUser { email: "jean@example.com".to_string() };
+        let long_name = format!("{}.jpg", "a".repeat(300));
+        let status = create_post(session_user, multipart_with_image(&long_name).await)
+            .await
+            .into_response()
+            .status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Envoie une image via `/upload` pour `email` ; retourne l'identifiant de l'upload
+    async fn upload_for(email: &str) -> Uuid {
+        let session_user = SessionUser { email: email.to_string() };
+        let Json(body) = upload_image(session_user, multipart_with_image("photo.jpg").await)
+            .await
+            .unwrap();
+        Uuid::parse_str(body["upload_id"].as_str().unwrap()).unwrap()
+    }
+
+    async fn create_post_with_attachment(email: &str, attachment: &str) -> axum::response::Result<Uuid> {
+        let session_user = SessionUser { email: email.to_string() };
+        let fields = [("text", "Bonjour !"), ("attachment", attachment)];
+        let Json(body) = create_post(session_user, multipart(&fields).await).await?;
+        Ok(Uuid::parse_str(body["post_id"].as_str().unwrap()).unwrap())
+    }
+
+    fn status(result: axum::response::Result<Uuid>) -> StatusCode {
+        result.map(|_| ()).into_response().status()
+    }
+
+    fn find_post(post_id: Uuid) -> Post {
+        POSTS.read().unwrap().iter().find(|post| post.id == post_id).cloned().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post_with_valid_attachment() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let upload_id = upload_for(&email).await;
+
+        let post_id = create_post_with_attachment(&email, &upload_id.to_string()).await.unwrap();
+        let post = find_post(post_id);
+        assert_eq!(post.attachment, Some(upload_id));
+        assert_eq!(post.image_name.as_deref(), Some("photo.jpg"));
+        let upload = database::upload::get(&upload_id).unwrap();
+        assert!(post.image_path.unwrap().ends_with(&upload.filename));
+
+        // Un upload ne peut être associé qu'à un seul post
+        let again = create_post_with_attachment(&email, &upload_id.to_string()).await;
+        assert_eq!(status(again), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_post_rejects_dangling_attachment() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let dangling = create_post_with_attachment(&email, &Uuid::new_v4().to_string()).await;
+        assert_eq!(status(dangling), StatusCode::BAD_REQUEST);
+        let malformed = create_post_with_attachment(&email, "../../etc/passwd").await;
+        assert_eq!(status(malformed), StatusCode::BAD_REQUEST);
+        assert_eq!(count_posts_by(&email), 0);
+    }
+
+    #[tokio::test]
+    async fn test_post_rejects_other_users_attachment() {
+        let owner = format!("{}@example.com", Uuid::new_v4().simple());
+        let thief = format!("{}@example.com", Uuid::new_v4().simple());
+        let upload_id = upload_for(&owner).await;
+
+        let result = create_post_with_attachment(&thief, &upload_id.to_string()).await;
+        assert_eq!(status(result), StatusCode::FORBIDDEN);
+        assert_eq!(count_posts_by(&thief), 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_post_removes_attachment() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let upload_id = upload_for(&email).await;
+        let post_id = create_post_with_attachment(&email, &upload_id.to_string()).await.unwrap();
+        let filename = database::upload::get(&upload_id).unwrap().filename;
+        let file = database::resolve(consts::UPLOADS_DIR).join(filename);
+        assert!(file.exists());
+
+        let delete = |email: &str| {
+            let session_user = SessionUser { email: email.to_string() };
+            delete_post(session_user, ApiJson(json!({ "post_id": post_id.to_string() })))
+        };
+
+        // Seul l'auteur peut supprimer son post
+        let status = delete("other@example.com").await.into_response().status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(file.exists());
+
+        assert_eq!(delete(&email).await.unwrap(), StatusCode::OK);
+        assert!(!file.exists());
+        assert!(database::upload::get(&upload_id).is_none());
+        assert!(POSTS.read().unwrap().iter().all(|post| post.id != post_id));
+    }
+
+    #[tokio::test]
+    async fn test_upload_quota_per_user() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let first = upload_for(&email).await;
+        let size = database::upload::get(&first).unwrap().size;
+        let upload = |quota: u64| {
+            let config = config::Config {
+                max_upload_bytes_per_user: quota,
+                ..Default::default()
+            };
+            let email = email.clone();
+            config::scope(config, async move {
+                let session_user = SessionUser { email };
+                upload_image(session_user, multipart_with_image("photo.jpg").await).await
+            })
+        };
+
+        // Quota atteint : refusé avant la réception ; dépassé par le fichier reçu : refusé aussi
+        assert_eq!(upload(size).await.into_response().status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(upload(size + 1).await.into_response().status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(database::upload::owned_by(&email).unwrap().len(), 1);
+
+        assert!(upload(2 * size + 1024).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unattached_uploads_are_purged() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let stale = upload_for(&email).await;
+        let attached = upload_for(&email).await;
+        let recent = upload_for(&email).await;
+        create_post_with_attachment(&email, &attached.to_string()).await.unwrap();
+
+        let long_ago = database::now() - config::get().unattached_upload_ttl_secs - 60;
+        for id in [&stale, &attached] {
+            database::upload::set_created_at(id, long_ago).unwrap();
+        }
+        let file = database::resolve(consts::UPLOADS_DIR).join(database::upload::get(&stale).unwrap().filename);
+
+        assert!(purge_unattached_uploads().unwrap() >= 1);
+        assert!(database::upload::get(&stale).is_none());
+        assert!(!file.exists());
+        assert!(database::upload::get(&attached).is_some());
+        assert!(database::upload::get(&recent).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_identical_uploads_share_storage() {
+        let jpeg = unique_jpeg();
+        let upload = |email: String| {
+            let jpeg = jpeg.clone();
+            async move {
+                let Json(body) = upload_image(SessionUser { email }, multipart_with_jpeg("photo.jpg", &jpeg).await)
+                    .await
+                    .unwrap();
+                Uuid::parse_str(body["upload_id"].as_str().unwrap()).unwrap()
+            }
+        };
+        let first = upload(format!("{}@example.com", Uuid::new_v4().simple())).await;
+        let second = upload(format!("{}@example.com", Uuid::new_v4().simple())).await;
+
+        // Deux uploads distincts, un seul contenu stocké hors du dossier servi
+        let digest = format!("{:x}", Sha256::digest(&jpeg));
+        let blob = database::upload::blob_path(&digest);
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read(&blob).unwrap(), jpeg);
+
+        // Les noms publics sont aléatoires : l'URL ne permet pas de deviner le contenu
+        let public = |id: &Uuid| {
+            let upload = database::upload::get(id).unwrap();
+            assert!(!upload.filename.contains(&digest));
+            database::resolve(consts::UPLOADS_DIR).join(upload.filename)
+        };
+        let (first_file, second_file) = (public(&first), public(&second));
+        assert_ne!(first_file, second_file);
+        assert_eq!(std::fs::read(&first_file).unwrap(), jpeg);
+        assert_eq!(std::fs::read(&second_file).unwrap(), jpeg);
+
+        // Le contenu reste tant qu'une référence existe
+        database::upload::remove(&first).unwrap();
+        assert!(!first_file.exists());
+        assert!(blob.exists() && second_file.exists());
+
+        database::upload::remove(&second).unwrap();
+        assert!(!second_file.exists());
+        assert!(!blob.exists());
+
+        // Un nouvel upload du même contenu recrée le contenu stocké
+        let third = upload(format!("{}@example.com", Uuid::new_v4().simple())).await;
+        assert!(blob.exists());
+        database::upload::remove(&third).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_uploads_only_own_files() {
+        let upload = |email: &str, name: &str| {
+            let session_user = SessionUser { email: email.to_string() };
+            let name = name.to_string();
+            async move {
+                let Json(body) = upload_image(session_user, multipart_with_image(&name).await).await.unwrap();
+                body["upload_id"].as_str().unwrap().to_string()
+            }
+        };
+        let list = |email: &str, offset: usize, limit: usize| {
+            let session_user = SessionUser { email: email.to_string() };
+            async move {
+                let Json(body) = list_uploads(session_user, Query(UploadsQuery { offset, limit })).await.unwrap();
+                body
+            }
+        };
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let other = format!("{}@example.com", Uuid::new_v4().simple());
+        let first = upload(&email, "vacances.jpg").await;
+        let second = upload(&email, "chat.jpg").await;
+        let foreign = upload(&other, "autre.jpg").await;
+
+        let body = list(&email, 0, 10).await;
+        assert_eq!(body["total"], 2);
+        let uploads = body["uploads"].as_array().unwrap();
+        let ids: Vec<&str> = uploads.iter().map(|upload| upload["id"].as_str().unwrap()).collect();
+        assert!(ids.contains(&first.as_str()) && ids.contains(&second.as_str()));
+        assert!(!ids.contains(&foreign.as_str()));
+
+        let names: Vec<&str> = uploads.iter().map(|upload| upload["name"].as_str().unwrap()).collect();
+        assert!(names.contains(&"vacances.jpg") && names.contains(&"chat.jpg"));
+        for upload in uploads {
+            assert!(upload["size"].as_u64().unwrap() > 0);
+            assert_eq!(upload["mime"], "image/jpeg");
+            assert!(upload["created_at"].as_u64().unwrap() > 0);
+        }
+
+        // Pagination
+        let page = list(&email, 1, 1).await;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["uploads"].as_array().unwrap().len(), 1);
+        assert_eq!(page["uploads"][0]["id"], uploads[1]["id"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_posts_streams_page() {
+        use futures::StreamExt;
+
+        {
+            let mut posts = POSTS.write().unwrap();
+            for i in 0..(consts::MAX_PAGE_SIZE * 3) {
+                posts.push(Post {
+                    id: Uuid::new_v4(),
+                    content: format!("Post {}", i),
+                    image_path: None,
+                    likes: 0,
+                    author: None,
+                    image_name: None,
+                    attachment: None,
+                    created_at: 0,
+                    hidden: false,
+                    visibility: Visibility::Public,
+                });
+            }
+        }
+
+        let query = PostsQuery { offset: 10, limit: 1000, cursor: None, sort: PostSort::Newest };
+        let response = list_posts(reader(), Query(query)).await.unwrap();
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "application/json");
+
+        // Le corps est produit morceau par morceau, sans longueur connue à l'avance
+        let mut stream = response.into_body().into_data_stream();
+        let mut body = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+            chunks += 1;
+        }
+        assert!(chunks > 2);
+
+        let page: Vec<Post> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page.len(), consts::MAX_PAGE_SIZE);
+    }
+
+    /// Utilisateur quelconque qui consulte les posts
+    fn reader() -> SessionUser {
+        SessionUser { email: "reader@example.com".to_string() }
+    }
+
+    /// Récupère une page de posts ; retourne les posts et le curseur suivant
+    async fn fetch_page(cursor: Option<String>, limit: usize) -> (Vec<Post>, Option<String>) {
+        fetch_page_as(reader(), cursor, limit).await
+    }
+
+    async fn fetch_page_as(viewer: SessionUser, cursor: Option<String>, limit: usize) -> (Vec<Post>, Option<String>) {
+        let query = PostsQuery { offset: 0, limit, cursor, sort: PostSort::Oldest };
+        let response = list_posts(viewer, Query(query)).await.unwrap();
+        let next = response
+            .headers()
+            .get("x-next-cursor")
+            .map(|cursor| cursor.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (serde_json::from_slice(&body).unwrap(), next)
+    }
+
+    #[tokio::test]
+    async fn test_private_posts_visible_to_author_and_admins() {
+        let author = format!("{}@example.com", Uuid::new_v4().simple());
+        let admin = format!("{}@example.com", Uuid::new_v4().simple());
+        database::user::create(&admin, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        database::user::set_role(&admin, database::user::Role::Admin).unwrap();
+        let draft = Uuid::parse_str(&save_post(&author, "Brouillon", None, Visibility::Private)).unwrap();
+        let public = Uuid::parse_str(&save_post(&author, "Publié", None, Visibility::Public)).unwrap();
+
+        // Parcourt toutes les pages visibles par `viewer`
+        let visible = |viewer: &str| {
+            let viewer = viewer.to_string();
+            async move {
+                let (mut ids, mut cursor) = (Vec::new(), None);
+                loop {
+                    let viewer = SessionUser { email: viewer.clone() };
+                    let (page, next) = fetch_page_as(viewer, cursor, consts::MAX_PAGE_SIZE).await;
+                    ids.extend(page.iter().map(|post| post.id));
+                    match next {
+                        Some(next) => cursor = Some(next),
+                        None => return ids,
+                    }
+                }
+            }
+        };
+
+        let others = visible("someone-else@example.com").await;
+        assert!(others.contains(&public) && !others.contains(&draft));
+        let own = visible(&author).await;
+        assert!(own.contains(&public) && own.contains(&draft));
+        let moderated = visible(&admin).await;
+        assert!(moderated.contains(&public) && moderated.contains(&draft));
+
+        // Un post privé ne peut être ni liké ni signalé par un autre compte
+        let like = |email: &str, post_id: Uuid| {
+            let body = json!({ "post_id": post_id.to_string(), "action": "like" });
+            like_post(SessionUser { email: email.to_string() }, ApiJson(body))
+        };
+        assert_eq!(like("someone-else@example.com", draft).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(flag("someone-else@example.com", &draft.to_string(), "Spam").await, StatusCode::NOT_FOUND);
+        assert_eq!(find_post(draft).likes, 0);
+        assert_eq!(like("someone-else@example.com", public).await.into_response().status(), StatusCode::OK);
+        assert_eq!(like(&author, draft).await.into_response().status(), StatusCode::OK);
+        assert_eq!(flag(&admin, &draft.to_string(), "Spam").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_pagination_is_stable() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        for _ in 0..7 {
+            save_post(&email, "Bonjour !", None, Visibility::Public);
+        }
+        let mine = |posts: &[Post]| -> Vec<Uuid> {
+            posts.iter().filter(|post| post.author.as_deref() == Some(email.as_str())).map(|post| post.id).collect()
+        };
+        let expected = mine(&POSTS.read().unwrap());
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = fetch_page(cursor, 3).await;
+            seen.extend(mine(&page));
+
+            // Des posts sont ajoutés entre deux pages, y compris avant la position courante
+            POSTS.write().unwrap().push(Post {
+                id: Uuid::new_v4(),
+                content: "Ancien post".to_string(),
+                image_path: None,
+                likes: 0,
+                author: None,
+                image_name: None,
+                attachment: None,
+                created_at: 0,
+                hidden: false,
+                visibility: Visibility::Public,
+            });
+            save_post("newcomer@example.com", "Nouveau post", None, Visibility::Public);
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        // Chaque post existant au départ est vu exactement une fois, dans l'ordre
+        let mut sorted = expected.clone();
+        sorted.sort_by_key(|id| POSTS.read().unwrap().iter().find(|post| post.id == *id).map(post_key));
+        assert_eq!(seen, sorted);
+    }
+
+    #[test]
+    fn test_legacy_posts_are_flagged() {
+        // posts.yaml écrit avant la validation du contenu
+        let fixture = format!(
+            r#"
+- id: {valid}
+  content: Ancien post valide
+  image_path: null
+  likes: 3
+- id: {invalid}
+  content: <img src=x onerror=alert(1)>
+  image_path: null
+  likes: 1
+"#,
+            valid = Uuid::new_v4(),
+            invalid = Uuid::new_v4(),
+        );
+        let legacy: Vec<Post> = serde_yaml::from_str(&fixture).unwrap();
+        let (valid, invalid) = (legacy[0].id, legacy[1].id);
+        POSTS.write().unwrap().extend(legacy);
+
+        assert!(repair_legacy_posts().unwrap() >= 1);
+
+        // Le post invalide est masqué et signalé, sans être supprimé
+        let post = find_post(invalid);
+        assert!(post.hidden);
+        assert_eq!(post.content, "<img src=x onerror=alert(1)>");
+        let flags = database::flag::pending().unwrap();
+        assert_eq!(flags[&invalid][0].reporter, READ_REPAIR_REPORTER);
+        assert!(!find_post(valid).hidden);
+        assert!(!flags.contains_key(&valid));
+
+        // La vérification ne reprend pas un post déjà traité
+        repair_legacy_posts().unwrap();
+        assert_eq!(database::flag::pending().unwrap()[&invalid].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sort_orders() {
+        let author = format!("{}@example.com", Uuid::new_v4().simple());
+        // (date de création, likes)
+        let ids: Vec<Uuid> = [(100, 0), (200, 1), (300, -1), (400, 1)]
+            .into_iter()
+            .map(|(created_at, likes)| {
+                let id = Uuid::parse_str(&save_post(&author, "Bonjour !", None, Visibility::Public)).unwrap();
+                let mut posts = POSTS.write().unwrap();
+                let post = posts.iter_mut().find(|post| post.id == id).unwrap();
+                (post.created_at, post.likes) = (created_at, likes);
+                id
+            })
+            .collect();
+
+        // Tous les posts de l'auteur, dans l'ordre de la liste, en suivant les curseurs
+        let listed = |sort: PostSort| {
+            let author = author.clone();
+            async move {
+                let (mut listed, mut cursor) = (Vec::new(), None);
+                loop {
+                    let query = PostsQuery { offset: 0, limit: 2, cursor, sort };
+                    let response = list_posts(reader(), Query(query)).await.unwrap();
+                    let next = response.headers().get("x-next-cursor").map(|c| c.to_str().unwrap().to_string());
+                    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                    let page: Vec<Post> = serde_json::from_slice(&body).unwrap();
+                    let own = page.into_iter().filter(|post| post.author.as_deref() == Some(author.as_str()));
+                    listed.extend(own.map(|post| post.id));
+                    match next {
+                        Some(next) => cursor = Some(next),
+                        None => return listed,
+                    }
+                }
+            }
+        };
+
+        assert_eq!(listed(PostSort::Newest).await, [ids[3], ids[2], ids[1], ids[0]]);
+        assert_eq!(listed(PostSort::Oldest).await, [ids[0], ids[1], ids[2], ids[3]]);
+        assert_eq!(listed(PostSort::Popular).await, [ids[3], ids[1], ids[0], ids[2]]);
+
+        // Valeur par défaut et valeur hors liste
+        let parse = |uri: &str| Query::<PostsQuery>::try_from_uri(&uri.parse().unwrap());
+        assert_eq!(parse("/api/posts").unwrap().sort, PostSort::Newest);
+        assert_eq!(parse("/api/posts?sort=popular").unwrap().sort, PostSort::Popular);
+        assert_eq!(parse("/api/posts?sort=random").err().unwrap().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_cursor_is_rejected() {
+        let query = PostsQuery { offset: 0, limit: 10, cursor: Some("not-a-cursor".to_string()), sort: PostSort::Newest };
+        let status = list_posts(reader(), Query(query)).await.into_response().status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(decode_cursor(&encode_cursor((-3, (42, Uuid::nil())))), Some((-3, (42, Uuid::nil()))));
+    }
+}
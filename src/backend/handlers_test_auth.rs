@@ -0,0 +1,111 @@
+//! Connexion simulée pour les tests end-to-end qui ne peuvent pas effectuer de cérémonie WebAuthn.
+//! Ce module n'existe que si la feature `test-auth` est activée à la compilation.
+
+use axum::{http::StatusCode, Json};
+use subtle::ConstantTimeEq;
+use tower_sessions::Session;
+use crate::config;
+use crate::backend::middlewares::start_session;
+use crate::database::user;
+
+/// Crée une session pour l'email donné si le secret partagé est correct
+pub async fn test_login(
+    session: Session,
+    Json(payload): Json<serde_json::Value>,
+) -> axum::response::Result<StatusCode> {
+    let expected = config::get()
+        .test_auth_secret
+        .clone()
+        .ok_or((StatusCode::NOT_FOUND, "Not found"))?;
+
+    let secret = payload
+        .get("secret")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Secret is required"))?;
+    // Comparaison en temps constant : la durée ne révèle pas le préfixe correct du secret
+    if !bool::from(secret.as_bytes().ct_eq(expected.as_bytes())) {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid secret").into());
+    }
+
+    let email = payload
+        .get("email")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
+    if !user::exists(email).unwrap_or(false) {
+        return Err((StatusCode::BAD_REQUEST, "User not found").into());
+    }
+
+    start_session(&session, email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set session"))?;
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use serde_json::json;
+
+    fn config_with_secret() -> config::Config {
+        config::Config {
+            test_auth_secret: Some("s3cret".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stub_login_creates_session() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        let session = Session::new(None);
+
+        let payload = json!({ "email": email, "secret": "s3cret" });
+        let status = config::scope(config_with_secret(), test_login(session.clone(), Json(payload)))
+            .await
+            .into_response()
+            .status();
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(session.get::<bool>("isAuthenticated").unwrap(), Some(true));
+        assert_eq!(session.get::<String>("email").unwrap(), Some(email));
+    }
+
+    #[tokio::test]
+    async fn test_stub_login_rejects_wrong_secret() {
+        let session = Session::new(None);
+        let payload = json!({ "email": "jean@example.com", "secret": "wrong" });
+
+        let status = config::scope(config_with_secret(), test_login(session.clone(), Json(payload)))
+            .await
+            .into_response()
+            .status();
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(session.get::<bool>("isAuthenticated").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stub_login_disabled_without_secret() {
+        let payload = json!({ "email": "jean@example.com", "secret": "" });
+        let status = test_login(Session::new(None), Json(payload))
+            .await
+            .into_response()
+            .status();
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_secret_is_refused_in_strict_mode() {
+        let strict = config::Config {
+            rp_id: "example.com".to_string(),
+            rp_origin: "https://example.com".to_string(),
+            strict_security: true,
+            ..config_with_secret()
+        };
+        assert!(strict.check_security().unwrap_err().contains("TEST_AUTH_SECRET"));
+        let without_secret = config::Config { test_auth_secret: None, ..strict };
+        assert!(without_secret.check_security().is_ok());
+    }
+}
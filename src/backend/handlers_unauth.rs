@@ -5,44 +5,154 @@
 use axum::{
     extract::{Json, Path, Query},
     http::StatusCode,
-    response::{ErrorResponse, Html, IntoResponse, Redirect},
+    response::{ErrorResponse, Html, IntoResponse, Redirect, Response},
 };
 
-use crate::database::{token, user};
+use crate::backend::middlewares::{
+    remember_session, start_session, ApiJson, ClientIp, PreferredLocale, ResponseFormat, ValidatedJson,
+};
+use crate::backend::models::{LoginCompleteRequest, RegisterCompleteRequest, WebAuthnChallenge};
+use crate::backend::pages::{IndexPage, LoginPage, RecoverPage, RegisterPage, RequiredFields};
+use crate::database::{invite, token, user};
+use crate::database::token::{TokenError, TokenKind};
 use crate::email::{send_mail};
+use crate::utils::abuse::{self, AbuseEvent};
+use crate::utils::ceremony::{self, Ceremony};
+use crate::utils::challenge_store::ChallengeStore;
+use crate::utils::i18n::{Locale, Text};
+use crate::utils::pow::{self, PowChallenge, PowSolution};
+use crate::config::{BotProtection, DisplayNamePolicy, ProfileField};
 use crate::utils::webauthn::{
-    begin_authentication, begin_registration, complete_authentication, complete_registration,
-    StoredRegistrationState, CREDENTIAL_STORE,
+    begin_authentication, begin_registration, complete_authentication, complete_registration, decode_challenge,
+    discoverable, simulate_authentication, parse_authentication_response, parse_registration_response, CeremonyFailure, ResponseError,
+    RegistrationOptions, StoredRegistrationState,
 };
-use crate::{consts, HBS};
+use crate::{config, HBS};
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 use serde_json::json;
 use std::collections::HashMap;
 use tokio::sync::RwLock;
 use tower_sessions::Session;
 use validator::{Validate};
 use webauthn_rs::prelude::{
-    PasskeyAuthentication, PublicKeyCredential, RegisterPublicKeyCredential,
+    PasskeyAuthentication, RegisterPublicKeyCredential,
 };
-use crate::utils::input::{MailValidation, UserRegistration};
+use crate::utils::input::{display_name, validate_redirect_path, MailValidation, UserRegistration};
 
 /// Structure pour gérer un état temporaire avec un challenge
 struct TimedStoredState<T> {
     state: T,
-    server_challenge: String,
+    server_challenge: Vec<u8>,
+    email: String,
 }
 
-/// Stockage des états d'enregistrement et d'authentification
-pub(crate) static REGISTRATION_STATES: Lazy<RwLock<HashMap<String, StoredRegistrationState>>> =
+/// Stockage des états d'enregistrement et d'authentification, bornés par `max_pending_challenges`
+static REGISTRATION_STATES: Lazy<RwLock<ChallengeStore<StoredRegistrationState>>> =
     Lazy::new(Default::default);
 static AUTHENTICATION_STATES: Lazy<
-    RwLock<HashMap<String, TimedStoredState<PasskeyAuthentication>>>,
+    RwLock<ChallengeStore<TimedStoredState<PasskeyAuthentication>>>,
 > = Lazy::new(Default::default);
 
+/// Nombre de cérémonies d'enregistrement et d'authentification en attente
+pub(crate) async fn pending_challenges() -> (usize, usize) {
+    (REGISTRATION_STATES.read().await.len(), AUTHENTICATION_STATES.read().await.len())
+}
+
+/// Retient si la passkey qui vient d'être enregistrée sur le compte est découvrable.
+/// Une indication perdue n'empêche pas l'enregistrement.
+pub(crate) fn record_discoverable(email: &str, response: &RegisterPublicKeyCredential) {
+    if let Some(discoverable) = discoverable(response) {
+        if let Err(err) = user::set_discoverable(email, response.raw_id.as_ref(), discoverable) {
+            log::warn!("Failed to record passkey discoverability: {}", err);
+        }
+    }
+}
+
+/// Erreur d'une cérémonie WebAuthn pour le client : un code stable et un message, sans détail interne
+pub(crate) fn ceremony_error(status: StatusCode, err: &anyhow::Error) -> ErrorResponse {
+    let failure = CeremonyFailure::of(err);
+    tracing::debug!(target: "webauthn", code = failure.code(), error = %format!("{:#}", err), "Ceremony failed");
+    ErrorResponse::from((
+        status,
+        Json(json!({"error": failure.message(), "code": failure.code()})),
+    ))
+}
+
+/// Réponse WebAuthn mal formée : le message désigne le champ en cause
+pub(crate) fn response_error(err: ResponseError) -> ErrorResponse {
+    ErrorResponse::from((
+        StatusCode::BAD_REQUEST,
+        Json(json!({
+            "error": err.to_string(),
+            "code": CeremonyFailure::InvalidResponse.code(),
+            "field": err.field(),
+        })),
+    ))
+}
+
+/// Nom affiché de la future passkey selon la politique configurée ; l'email à défaut
+pub(crate) fn registration_display_name(email: &str, first_name: Option<&str>, last_name: Option<&str>) -> String {
+    match config::get().display_name_policy {
+        DisplayNamePolicy::FullName => display_name(first_name, last_name).unwrap_or_else(|| email.to_string()),
+        DisplayNamePolicy::Email => email.to_string(),
+    }
+}
+
+/// Vérifie le code d'invitation lorsque l'inscription libre est désactivée
+fn check_invite(code: Option<&str>) -> Result<(), (StatusCode, &'static str)> {
+    if config::get().open_registration {
+        return Ok(());
+    }
+
+    match code {
+        Some(code) if invite::is_valid(code) => Ok(()),
+        _ => Err((StatusCode::FORBIDDEN, "A valid invite code is required")),
+    }
+}
+
+/// Vérifie la protection anti-robot configurée pour l'inscription
+fn check_bot_protection(proof: Option<&serde_json::Value>) -> Result<(), (StatusCode, &'static str)> {
+    match config::get().bot_protection {
+        BotProtection::Disabled => Ok(()),
+        BotProtection::ProofOfWork { difficulty } => {
+            let solution = proof.and_then(|proof| PowSolution::deserialize(proof).ok());
+            match solution {
+                Some(solution) if pow::verify(&solution, difficulty) => Ok(()),
+                _ => Err((StatusCode::FORBIDDEN, "Invalid proof of work")),
+            }
+        }
+    }
+}
+
+/// Émet un challenge de preuve de travail, si ce mécanisme est activé
+pub async fn pow_challenge() -> Result<Json<PowChallenge>, StatusCode> {
+    match config::get().bot_protection {
+        BotProtection::ProofOfWork { difficulty } => Ok(Json(pow::issue(difficulty))),
+        BotProtection::Disabled => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Vérifie que le token de récupération est valide et a été émis pour ce compte
+fn check_recovery_token<'a>(
+    recovery_token: Option<&'a str>,
+    email: &str,
+) -> Result<&'a str, (StatusCode, &'static str)> {
+    match recovery_token {
+        Some(recovery_token)
+            if token::peek(recovery_token, TokenKind::Recovery).is_ok_and(|owner| owner == email)
+                && user::exists(email).unwrap_or(false) =>
+        {
+            Ok(recovery_token)
+        }
+        _ => Err((StatusCode::FORBIDDEN, "Invalid recovery token")),
+    }
+}
+
 /// Début du processus d'enregistrement WebAuthn
 pub async fn register_begin(
-    Json(payload): Json<serde_json::Value>,
-) -> axum::response::Result<Json<serde_json::Value>> {
+    ApiJson(payload): ApiJson<serde_json::Value>,
+) -> axum::response::Result<Json<WebAuthnChallenge<RegistrationOptions>>> {
     let email = payload
         .get("email")
         .and_then(|v| v.as_str())
@@ -64,15 +174,24 @@ pub async fn register_begin(
         .unwrap_or(false);
 
     
-    // Vérifier si l'utilisateur existe déjà (sauf en mode reset)
-    if !reset_mode {
+    // En mode reset, seul le détenteur d'un token de récupération valide peut continuer
+    if reset_mode {
+        check_recovery_token(payload.get("recovery_token").and_then(|v| v.as_str()), email)?;
+    } else {
+        check_bot_protection(payload.get("pow"))?;
+
+        // Vérifier si l'utilisateur existe déjà et le code d'invitation
         if user::exists(email).unwrap_or(false) {
             return Err(ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": "There was a problem with your registration"})))));
         }
+
+        check_invite(payload.get("invite_code").and_then(|v| v.as_str()))?;
     }
 
-    //Début de l'enregistrement
-    let (public_key, reg_state) = begin_registration(email, email)
+    // Début de l'enregistrement ; l'email reste le nom du compte côté authentificateur
+    let names = ["first_name", "last_name"].map(|field| payload.get(field).and_then(|v| v.as_str()));
+    let display_name = registration_display_name(email, names[0], names[1]);
+    let (public_key, stored_state) = begin_registration(email, &display_name)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -81,138 +200,133 @@ pub async fn register_begin(
 
     //Stockage de l'état d'enregistrement dans la DB
     let mut states = REGISTRATION_STATES.write().await;
-    states.insert(
-        state_id.clone(),
-        StoredRegistrationState {
-            registration_state: reg_state,
-            challenge: public_key["challenge"].as_str().unwrap().to_string(),
-        },
-    );
+    states.insert(state_id.clone(), stored_state, config::get().max_pending_challenges);
+    ceremony::begin(Ceremony::Registration, &state_id);
 
-    Ok(Json(json!({
-        "publicKey": public_key,
-        "state_id": state_id,
-    })))
+    Ok(Json(WebAuthnChallenge {
+        challenge: public_key,
+        state_id,
+    }))
 }
 
 /// Fin du processus d'enregistrement WebAuthn
 pub async fn register_complete(
-    Json(payload): Json<serde_json::Value>,
+    locale: PreferredLocale,
+    ValidatedJson(request): ValidatedJson<RegisterCompleteRequest>,
 ) -> axum::response::Result<StatusCode> {
-    // Extraire l'email
-    let email = payload
-        .get("email")
-        .and_then(|v| v.as_str())
-        .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
+    let completion = ceremony::complete(Ceremony::Registration, &request.state_id);
 
-    // Extraire les autres champs requis
-    let first_name = payload
-        .get("first_name")
-        .and_then(|v| v.as_str())
-        .ok_or((StatusCode::BAD_REQUEST, "First name is required"))?;
+    // Les champs sont déjà présents et validés par l'extracteur
+    let UserRegistration { email, first_name, last_name } = &request.registration;
+    let (email, first_name, last_name) = (email.as_str(), first_name.as_deref(), last_name.as_deref());
 
-    let last_name = payload
-        .get("last_name")
-        .and_then(|v| v.as_str())
-        .ok_or((StatusCode::BAD_REQUEST, "Last name is required"))?;
-    
-    // Valider les champs 
-    let user_registration = UserRegistration {
-        first_name: first_name.to_string(),
-        last_name: last_name.to_string(),
-        email: email.to_string(),
+    // En mode reset, le token de récupération remplace le code d'invitation
+    let reset_mode = request.reset_mode;
+    let invite_code = request.invite_code.as_deref();
+    let recovery_token = if reset_mode {
+        Some(check_recovery_token(request.recovery_token.as_deref(), email)?)
+    } else {
+        check_invite(invite_code)?;
+        None
     };
-    
-    user_registration.validate().map_err(|e| {
-        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()})))
-        )
-    })?;
 
     // Récupérer l'état d'enregistrement
-    let state_id = payload
-        .get("state_id")
-        .and_then(|v| v.as_str())
-        .ok_or((StatusCode::BAD_REQUEST, "State ID is required"))?;
-
     let mut states = REGISTRATION_STATES.write().await;
     let stored_state = states
-        .remove(state_id)
+        .remove(&request.state_id)
         .ok_or((StatusCode::BAD_REQUEST, "Invalid state"))?;
 
     // Convertir et valider la réponse WebAuthn
-    let response: RegisterPublicKeyCredential = serde_json::from_value(
-        payload
-            .get("response")
-            .ok_or((StatusCode::BAD_REQUEST, "Response is required"))?
-            .clone(),
-    )
-    .map_err(|err| {
-        (
-            StatusCode::BAD_REQUEST,
-            format!("Invalid response format: {}", err),
-        )
-    })?;
+    let response = parse_registration_response(request.response).map_err(response_error)?;
 
     // Compléter l'enregistrement WebAuthn
-    complete_registration(email, &response, &stored_state)
+    let passkey = complete_registration(&response, &stored_state)
         .await
+        .map_err(|err| ceremony_error(StatusCode::BAD_REQUEST, &err))?;
+
+    // Mode reset : remplacer la passkey du compte existant au lieu de le recréer
+    if let Some(recovery_token) = recovery_token {
+        token::consume(recovery_token, TokenKind::Recovery)
+            .map_err(|_| (StatusCode::FORBIDDEN, "Invalid recovery token"))?;
+        user::set_passkey(email, passkey)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey"))?;
+        record_discoverable(email, &response);
+        // Les sessions ouvertes avec l'ancienne passkey ne sont plus valables
+        user::bump_session_generation(email)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke sessions"))?;
+        send_security_notice(email, SecurityNotice::Recovery);
+        completion.succeed();
+        return Ok(StatusCode::OK);
+    }
+
+    // Réserver le code d'invitation avant la création du compte, pour qu'il ne serve qu'une fois ;
+    // il est rendu si le compte n'est finalement pas créé
+    let invite_code = invite_code.filter(|_| !config::get().open_registration);
+    if let Some(code) = invite_code {
+        invite::consume(code, email)
+            .map_err(|_| (StatusCode::FORBIDDEN, "A valid invite code is required"))?;
+    }
+    let release_invite = || {
+        if let Err(err) = invite_code.map_or(Ok(()), |code| invite::release(code, email)) {
+            log::warn!("Failed to release invite code: {}", err);
+        }
+    };
+
+    // Créer l'utilisateur et lui associer la passkey en une seule écriture,
+    // pour ne jamais laisser un compte sans passkey
+    let created = user::create_with_passkey(email, first_name, last_name, stored_state.user_handle, passkey)
         .map_err(|err| {
+            release_invite();
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to complete registration: {}", err),
+                format!("Failed to create user: {}", err),
             )
         })?;
+    if !created {
+        release_invite();
+        return Err((StatusCode::CONFLICT, "User already exists").into());
+    }
+    record_discoverable(email, &response);
 
-    // Récupérer la passkey générée
-    let passkey = CREDENTIAL_STORE
-        .read()
-        .await
-        .get(email)
-        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Passkey not found"))?
-        .clone();
-
-    // Créer l'utilisateur en base de données
-    user::create(email, first_name, last_name).map_err(|err| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create user: {}", err),
-        )
-    })?;
-
-    // Associer la passkey à l'utilisateur
-    user::set_passkey(email, passkey)
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set passkey"))?;
+    // Le compte existe : un échec d'envoi n'annule pas l'inscription, l'email sera renvoyé plus tard
+    if let Err(err) = send_validation_mail(email, locale.for_recipient(email)) {
+        log::warn!("Failed to send validation email, will retry later: {}", err);
+        if let Err(err) = user::set_validation_mail_pending(email, true) {
+            log::warn!("Failed to schedule validation email: {}", err);
+        }
+    }
 
-    // Générer et envoyer le token de validation par email
-    let validation_token = token::generate(email).map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to generate validation token",
-        )
-    })?;
-    
+    completion.succeed();
+    Ok(StatusCode::OK)
+}
 
-    // Envoyer l'email de validation
-    send_mail(email, "Account Validation",
-              &format!(
-            "Click here to validate your account: http://{}:{}/validate/{}",
-            consts::DOMAIN, consts::HTTP_PORT, validation_token
-        ),
-    )
-        .map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to send validation email",
-        )
-    })?;
+/// Génère un token de validation et l'envoie par email
+fn send_validation_mail(email: &str, locale: Locale) -> anyhow::Result<()> {
+    let validation_token = token::generate(email, TokenKind::Validation)?;
+    let link = format!("{}/validate/{}", config::get().link_base(), validation_token);
+    let (subject, body) = locale.mail(Text::ValidationSubject, Text::ValidationBody, &link);
+    send_mail(email, subject, &body)
+}
 
-    Ok(StatusCode::OK)
+/// Renvoie les emails de validation qui n'ont pas pu partir à l'inscription ; retourne le nombre d'envois
+pub fn retry_validation_mails() -> anyhow::Result<usize> {
+    let mut sent = 0;
+    for email in user::validation_mail_pending()? {
+        // Hors requête, seule la préférence du compte est connue
+        let locale = PreferredLocale(Locale::default()).for_recipient(&email);
+        if send_validation_mail(&email, locale).is_ok() {
+            user::set_validation_mail_pending(&email, false)?;
+            sent += 1;
+        }
+    }
+    Ok(sent)
 }
 
 /// Début du processus d'authentification WebAuthn
 pub async fn login_begin(
-    Json(payload): Json<serde_json::Value>,
-) -> axum::response::Result<Json<serde_json::Value>> {
+    session: Session,
+    ApiJson(payload): ApiJson<serde_json::Value>,
+) -> axum::response::Result<Json<WebAuthnChallenge>> {
     let email = payload
         .get("email")
         .and_then(|v| v.as_str())
@@ -228,14 +342,42 @@ pub async fn login_begin(
         )
     })?;
 
+    let account = user::get(email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?;
+
+    // Compte absent ou non vérifié : on fait un travail équivalent avant de répondre,
+    // pour que le temps de réponse ne permette pas de distinguer les cas
+    if !account.as_ref().is_some_and(|account| account.verified) {
+        simulate_authentication(email).await;
+    }
+
     // Check si l'utilisateur existe
-    if !user::exists(email).unwrap_or(false) {
+    let Some(account) = account else {
         return Err((StatusCode::BAD_REQUEST, "User not found").into());
+    };
+
+    // Check si l'utilisateur est vérifié ; le code permet au frontend de proposer un nouvel envoi
+    if !account.verified {
+        return Err(ErrorResponse::from((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "User not verified",
+                "code": "EMAIL_NOT_VERIFIED",
+                "hint": "Check your inbox for the validation email, or request a new one.",
+            })),
+        )));
     }
 
-    // Check si l'utilisateur est vérifié
-    if !user::get(email).unwrap().verified {
-        return Err((StatusCode::BAD_REQUEST, "User not verified").into());
+    // Compte vérifié sans passkey (toutes supprimées ou enregistrement interrompu) : la récupération s'impose
+    if account.passkeys.is_empty() {
+        return Err(ErrorResponse::from((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "No credentials registered for this account",
+                "code": "NO_CREDENTIALS",
+                "hint": "Recover your account to register a new passkey.",
+            })),
+        )));
     }
 
     // Commencer l'authentification
@@ -243,6 +385,10 @@ pub async fn login_begin(
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
+    let server_challenge = public_key["challenge"]
+        .as_str()
+        .and_then(decode_challenge)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to start authentication"))?;
     let state_id = uuid::Uuid::new_v4().to_string();
 
     // Garder l'état d'authentification
@@ -251,30 +397,39 @@ pub async fn login_begin(
         state_id.clone(),
         TimedStoredState {
             state: auth_state,
-            server_challenge: public_key["challenge"].as_str().unwrap().to_string(),
+            server_challenge,
+            email: email.to_string(),
         },
+        config::get().max_pending_challenges,
     );
+    ceremony::begin(Ceremony::Authentication, &state_id);
+
+    // Lier l'état à la session pour que seul ce client puisse terminer l'authentification
+    session
+        .insert("login_state_id", &state_id)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set session"))?;
     
-    Ok(Json(json!({
-        "publicKey": public_key,
-        "state_id": state_id,
-    })))
+    Ok(Json(WebAuthnChallenge {
+        challenge: public_key,
+        state_id,
+    }))
 }
 
 /// Fin du processus d'authentification WebAuthn
 pub async fn login_complete(
     session: Session,
-    Json(payload): Json<serde_json::Value>,
+    ClientIp(ip): ClientIp,
+    ValidatedJson(request): ValidatedJson<LoginCompleteRequest>,
 ) -> axum::response::Result<Redirect> {
+    let state_id = request.state_id.as_str();
+    let completion = ceremony::complete(Ceremony::Authentication, state_id);
 
-    let response = payload
-        .get("response")
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Response is required"))?;
-
-    let state_id = payload
-        .get("state_id")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| (StatusCode::BAD_REQUEST, "State ID is required"))?;
+    // Vérifier que l'état a été créé par cette session
+    let session_state_id = session.get::<String>("login_state_id").unwrap_or_default();
+    if session_state_id.as_deref() != Some(state_id) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid state").into());
+    }
+    session.remove_value("login_state_id");
 
     // Récupérer l'état d'authentification
     let mut states = AUTHENTICATION_STATES.write().await;
@@ -283,24 +438,67 @@ pub async fn login_complete(
         .ok_or((StatusCode::BAD_REQUEST, "Invalid state"))?;
     
     
-    let credential: PublicKeyCredential = serde_json::from_value(response.clone())
-        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid response format"))?;
+    let credential = parse_authentication_response(request.response).map_err(response_error)?;
 
     // Complète l'authentification
     complete_authentication(
+        &stored_state.email,
         &credential,
         &stored_state.state,
         &stored_state.server_challenge,
     )
     .await
-    .map_err(|e| (StatusCode::UNAUTHORIZED, e.to_string()))?;
+    .map_err(|err| {
+        abuse::report(AbuseEvent::LoginFailed, ip);
+        ceremony_error(StatusCode::UNAUTHORIZED, &err)
+    })?;
 
-    // Créer la session utilisateur
-    session
-        .insert("isAuthenticated", true)
+    // Créer la session utilisateur, prolongée si demandé ou, à défaut, selon la préférence du compte
+    let account = user::get(&stored_state.email).ok().flatten();
+    let remember_me = request
+        .remember_me
+        .unwrap_or_else(|| account.as_ref().is_some_and(|account| account.remember_me_default));
+    start_session(&session, &stored_state.email)
+        .and_then(|_| if remember_me { remember_session(&session) } else { Ok(()) })
         .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to set session"))?;
 
-    Ok(Redirect::to("/home"))
+    send_security_notice(&stored_state.email, SecurityNotice::Login);
+
+    completion.succeed();
+
+    // Une cible hors du site est ignorée plutôt que refusée : la connexion a réussi
+    let next = request.next.as_deref().filter(|next| validate_redirect_path(next).is_ok());
+    Ok(Redirect::to(next.unwrap_or("/home")))
+}
+
+/// Notifications de sécurité facultatives ; l'email de validation, obligatoire, n'en fait pas partie
+#[derive(Clone, Copy, Debug)]
+enum SecurityNotice {
+    /// Nouvelle connexion au compte
+    Login,
+    /// Passkey remplacée via une récupération
+    Recovery,
+}
+
+/// Prévient le titulaire du compte si ses préférences le demandent ; un échec d'envoi ne bloque pas l'action
+fn send_security_notice(email: &str, notice: SecurityNotice) {
+    let wanted = user::get(email).ok().flatten().is_some_and(|account| match notice {
+        SecurityNotice::Login => account.login_notifications,
+        SecurityNotice::Recovery => account.recovery_notifications,
+    });
+    if !wanted {
+        return;
+    }
+
+    let link = format!("{}/recover", config::get().link_base());
+    let locale = PreferredLocale(Locale::default()).for_recipient(email);
+    let (subject, body) = match notice {
+        SecurityNotice::Login => locale.mail(Text::LoginNoticeSubject, Text::LoginNoticeBody, &link),
+        SecurityNotice::Recovery => locale.mail(Text::RecoveryNoticeSubject, Text::RecoveryNoticeBody, &link),
+    };
+    if let Err(err) = send_mail(email, subject, &body) {
+        log::warn!("Failed to send {:?} notification: {}", notice, err);
+    }
 }
 
 /// Gère la déconnexion de l'utilisateur
@@ -311,21 +509,97 @@ pub async fn logout(session: Session) -> impl IntoResponse {
 
 /// Valide un compte utilisateur via un token
 pub async fn validate_account(Path(token): Path<String>) -> impl IntoResponse {
-    match token::consume(&token) {
+    match token::consume(&token, TokenKind::Validation) {
         Ok(email) => match user::verify(&email) {
             Ok(_) => Redirect::to("/login?validated=true"),
             Err(_) => Redirect::to("/register?error=validation_failed"),
         },
-        Err(_) => Redirect::to("/register?error=invalid_token"),
+        // Le compte a déjà été validé avec ce lien
+        Err(TokenError::AlreadyConsumed) => Redirect::to("/login?validated=true"),
+        Err(TokenError::Expired) => Redirect::to("/register?error=token_expired"),
+        Err(TokenError::Io(_)) => Redirect::to("/register?error=validation_failed"),
+        Err(TokenError::NotFound | TokenError::KindMismatch) => {
+            Redirect::to("/register?error=invalid_token")
+        }
     }
 }
 
+/// Envoie un lien de connexion à usage unique à un compte vérifié, si l'option est activée.
+/// La réponse ne dépend pas de l'existence du compte.
+pub async fn magic_link_request(
+    locale: PreferredLocale,
+    ApiJson(payload): ApiJson<serde_json::Value>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    if !config::get().magic_link_login {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
+    let email = payload
+        .get("email")
+        .and_then(|v| v.as_str())
+        .ok_or((StatusCode::BAD_REQUEST, "Email is required"))?;
+
+    let validation_email = MailValidation {
+        email: email.to_string(),
+    };
+    validation_email.validate().map_err(|e| {
+        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()}))))
+    })?;
+
+    let account = user::get(email)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user"))?;
+    if account.is_some_and(|user| user.verified) {
+        let login_token = token::generate(email, TokenKind::Login)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create login link"))?;
+
+        let link = format!("{}/login/magic/{}", config::get().link_base(), login_token);
+        let (subject, body) = locale.for_recipient(email).mail(Text::LoginLinkSubject, Text::LoginLinkBody, &link);
+        send_mail(email, subject, &body)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send login link"))?;
+    }
+
+    Ok(Json(json!({ "message": "If this account exists, a login link has been sent." })))
+}
+
+/// Ouvre une session à partir d'un lien de connexion ; le lien ne sert qu'une fois
+pub async fn magic_link_login(session: Session, Path(token): Path<String>) -> Result<Redirect, StatusCode> {
+    if !config::get().magic_link_login {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    match token::consume(&token, TokenKind::Login) {
+        Ok(email) if matches!(user::get(&email), Ok(Some(user)) if user.verified) => {
+            start_session(&session, &email).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            Ok(Redirect::to("/home"))
+        }
+        Err(TokenError::Expired) => Ok(Redirect::to("/login?error=magic_link_expired")),
+        _ => Ok(Redirect::to("/login?error=invalid_magic_link")),
+    }
+}
+
+/// Valide les champs d'inscription sans rien créer ni démarrer de cérémonie.
+/// Ne consulte pas la base : la disponibilité de l'email n'est pas révélée ici.
+pub async fn validate_registration(
+    ApiJson(registration): ApiJson<UserRegistration>,
+) -> axum::response::Result<Json<serde_json::Value>> {
+    registration.validate_profile().map_err(|e| {
+        ErrorResponse::from((StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()}))))
+    })?;
+
+    Ok(Json(json!({"valid": true})))
+}
+
 /// Envoie un email de récupération de compte à l'utilisateur
 pub async fn recover_account(
-    Json(payload): Json<serde_json::Value>,
-) -> axum::response::Result<Html<String>> {
-    let mut data = HashMap::new();
-    
+    ClientIp(ip): ClientIp,
+    format: ResponseFormat,
+    locale: PreferredLocale,
+    ApiJson(payload): ApiJson<serde_json::Value>,
+) -> axum::response::Result<Response> {
+    if !config::get().self_service_recovery {
+        return Err(StatusCode::NOT_FOUND.into());
+    }
+
     let email = payload
         .get("email")
         .and_then(|v| v.as_str())
@@ -343,26 +617,18 @@ pub async fn recover_account(
 
     // Vérifier si l'utilisateur existe
     if !user::exists(email).unwrap_or(false) {
+        abuse::report(AbuseEvent::UnknownRecovery, ip);
         return Err(ErrorResponse::from("User not found"));
     }
 
     // Générer un token de récupération
-    let recovery_token = token::generate(email).map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to create recovery token",
-        )
-    })?;
+    let recovery_token = token::generate(email, TokenKind::Recovery)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create recovery token"))?;
 
     // Envoyer l'email de récupération
-    send_mail(
-        email,
-        "Account Recovery",
-        &format!(
-            "Click here to recover your account: http://{}:{}/recover/{}",
-            consts::DOMAIN, consts::HTTP_PORT, recovery_token
-        ),
-    )
+    let link = recovery_link(&recovery_token);
+    let (subject, body) = locale.for_recipient(email).mail(Text::RecoverySubject, Text::RecoveryBody, &link);
+    send_mail(email, subject, &body)
     .map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -370,77 +636,1306 @@ pub async fn recover_account(
         )
     })?;
 
-    data.insert("message", "Recovery email sent. Please check your inbox.");
+    let message = "Recovery email sent. Please check your inbox.";
+    if format == ResponseFormat::Json {
+        return Ok(Json(json!({ "message": message })).into_response());
+    }
 
-    HBS.render("recover", &data)
-        .map(Html)
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error.").into())
+    Ok(render_page("recover", &RecoverPage { message: Some(message) }))
 }
 
-/// Gère la réinitialisation du compte utilisateur via un token de récupération
-pub async fn reset_account(Path(token): Path<String>) -> Html<String> {
-    match token::consume(&token) {
-        Ok(email) => {
-            let redirect_url = format!("/register?reset_mode=true&email={}&success=true", email);
-            Html(format!(
-                "<meta http-equiv='refresh' content='0;url={}'/>",
-                redirect_url
-            ))
-        }
-        Err(_) => {
-            let redirect_url = "/register?error=recovery_failed";
-            Html(format!(
-                "<meta http-equiv='refresh' content='0;url={}'/>",
-                redirect_url
-            ))
-        }
+/// Lien de récupération envoyé à l'utilisateur pour `recovery_token`
+pub(crate) fn recovery_link(recovery_token: &str) -> String {
+    format!("{}/recover/{}", config::get().link_base(), recovery_token)
+}
+
+/// Gère la réinitialisation du compte utilisateur via un token de récupération.
+/// Le token n'est consommé qu'une fois la nouvelle passkey enregistrée. La route reste ouverte
+/// sans récupération en libre-service, car le lien peut aussi venir d'un administrateur.
+pub async fn reset_account(Path(token): Path<String>) -> Redirect {
+    match token::peek(&token, TokenKind::Recovery) {
+        Ok(email) => match reset_redirect_url(&email, &token) {
+            Some(redirect_url) => {
+                // Le compte est en cours de récupération : fermer les sessions existantes.
+                // La génération est de nouveau incrémentée une fois la passkey remplacée.
+                if let Err(e) = user::bump_session_generation(&email) {
+                    log::warn!("Failed to revoke sessions during recovery: {}", e);
+                }
+                Redirect::to(&redirect_url)
+            }
+            None => Redirect::to("/register?error=recovery_failed"),
+        },
+        Err(TokenError::Expired) => Redirect::to("/register?error=recovery_expired"),
+        Err(_) => Redirect::to("/register?error=recovery_failed"),
+    }
+}
+
+/// Construit l'URL de réinitialisation avec l'email validé et encodé dans la query
+fn reset_redirect_url(email: &str, recovery_token: &str) -> Option<String> {
+    MailValidation {
+        email: email.to_string(),
     }
+    .validate()
+    .ok()?;
+
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("reset_mode", "true")
+        .append_pair("email", email)
+        .append_pair("token", recovery_token)
+        .append_pair("success", "true")
+        .finish();
+
+    Some(format!("/register?{}", query))
 }
 
 /// --- Affichage des pages ---
 ///
+/// Page renvoyée quand un template ne peut pas être rendu
+const ERROR_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Internal Server Error</title>
+    <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/dist/css/bootstrap.min.css">
+</head>
+<body>
+<div class="container mt-5 text-center">
+    <h3>Internal Server Error</h3>
+    <p class="text-muted">Something went wrong on our side. Please try again later.</p>
+    <a href="/" class="btn btn-primary">Back to home</a>
+</div>
+</body>
+</html>
+"#;
+
+/// Rend le template `name`, ou la page d'erreur standard (500) si le rendu échoue
+pub(crate) fn render_page<T: serde::Serialize>(name: &str, data: &T) -> Response {
+    render_page_with(&HBS, name, data)
+}
+
+/// Comme `render_page`, avec un registre de templates donné
+pub(crate) fn render_page_with<T: serde::Serialize>(hbs: &handlebars::Handlebars, name: &str, data: &T) -> Response {
+    match hbs.render(name, data) {
+        Ok(page) => Html(page).into_response(),
+        Err(e) => {
+            log::error!("Failed to render template {}: {}", name, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Html(ERROR_PAGE)).into_response()
+        }
+    }
+}
+
 /// Affiche la page d'accueil
-pub async fn index(session: tower_sessions::Session) -> impl IntoResponse {
-    let is_logged_in = session.get::<String>("email").is_ok();
-    let mut data = HashMap::new();
-    data.insert("logged_in", is_logged_in);
+pub async fn index(session: tower_sessions::Session, PreferredLocale(locale): PreferredLocale) -> impl IntoResponse {
+    let logged_in = session.get::<String>("email").ok().flatten().is_some();
+    render_page("index", &IndexPage::new(logged_in, locale))
+}
+
+/// Indique que le serveur répond ; non soumis à la limite de requêtes simultanées
+pub async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Contenu non sensible de la session courante, pour le développement du frontend.
+/// N'existe qu'en mode développement, jamais en production.
+pub async fn debug_session(session: Session) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !config::get().dev_tools() {
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-    HBS.render("index", &data)
-        .map(Html)
-        .unwrap_or_else(|_| Html("Internal Server Error".to_string()))
+    let expiry = session
+        .expiry_date()
+        .format(&time::format_description::well_known::Rfc3339)
+        .ok();
+    Ok(Json(json!({
+        "authenticated": session.get::<bool>("isAuthenticated").ok().flatten().unwrap_or(false),
+        "email": session.get::<String>("email").ok().flatten(),
+        "remember_me": session.get::<bool>("remember_me").ok().flatten().unwrap_or(false),
+        "expiry": expiry,
+    })))
 }
 
-/// Affiche la page de connexion
-pub async fn login_page() -> impl IntoResponse {
-    Html(include_str!("../../templates/login.hbs"))
+/// Affiche la page de connexion, avec une confirmation si le compte vient d'être validé
+pub async fn login_page(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let error_message = params.get("error").and_then(|error| match error.as_str() {
+        "magic_link_expired" => Some("This login link has expired. Please request a new one."),
+        "invalid_magic_link" => Some("Invalid login link."),
+        _ => None,
+    });
+
+    render_page("login", &LoginPage {
+        validated: params.get("validated").is_some_and(|validated| validated == "true"),
+        magic_link: config::get().magic_link_login,
+        error_message,
+    })
 }
 
 /// Affiche la page d'inscription avec des messages contextuels si présents
 pub async fn register_page(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
-    let mut context = HashMap::new();
-    if let Some(success) = params.get("success") {
-        if success == "true" {
-            context.insert(
-                "success_message",
-                "Account recovery successful. Please reset your passkey.",
-            );
+    let success_message = params
+        .get("success")
+        .filter(|success| *success == "true")
+        .map(|_| "Account recovery successful. Please reset your passkey.");
+    let error_message = params.get("error").and_then(|error| match error.as_str() {
+        "recovery_failed" => Some("Invalid or expired recovery link. Please try again."),
+        "recovery_expired" => Some("This recovery link has expired. Please request a new one."),
+        "token_expired" => Some("This validation link has expired."),
+        "invalid_token" => Some("Invalid validation link."),
+        _ => None,
+    });
+
+    // Champs marqués obligatoires dans le formulaire
+    let required = &config::get().required_fields;
+    render_page("register", &RegisterPage {
+        success_message,
+        error_message,
+        required: RequiredFields {
+            first_name: required.contains(&ProfileField::FirstName),
+            last_name: required.contains(&ProfileField::LastName),
+        },
+    })
+}
+
+/// Affiche la page de récupération de compte
+pub async fn recover_page() -> Response {
+    if !config::get().self_service_recovery {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    render_page("recover", &RecoverPage::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::webauthn::tests::{test_passkey, SoftAuthenticator};
+
+    fn closed_registration() -> config::Config {
+        config::Config {
+            open_registration: false,
+            ..Default::default()
+        }
+    }
+
+    async fn begin_with_invite(code: &str) -> StatusCode {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        register_begin(ApiJson(json!({ "email": email, "invite_code": code })))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_pending_challenges_metric() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let Json(challenge) = register_begin(ApiJson(json!({ "email": email }))).await.unwrap();
+        assert!(REGISTRATION_STATES.read().await.contains_key(&challenge.state_id));
+
+        let Json(metrics) = crate::backend::handlers_admin::metrics().await;
+        assert!(metrics["challenge_states"]["registration"].as_u64().unwrap() >= 1);
+        assert!(metrics["challenge_states"]["authentication"].is_u64());
+        assert_eq!(metrics["challenge_states"]["cap"], 10_000);
+    }
+
+    /// Crée un utilisateur vérifié possédant une passkey de test
+    fn create_verified_user() -> String {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        user::set_passkey(&email, test_passkey()).unwrap();
+        user::verify(&email).unwrap();
+        email
+    }
+
+    #[tokio::test]
+    async fn test_register_requires_proof_of_work() {
+        let config = config::Config {
+            bot_protection: BotProtection::ProofOfWork { difficulty: 8 },
+            ..Default::default()
+        };
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+
+        let statuses = config::scope(config, async {
+            let Json(challenge) = pow_challenge().await.unwrap();
+            let valid = crate::utils::pow::tests::solve(&challenge);
+            let begin = |pow: serde_json::Value| {
+                register_begin(ApiJson(json!({ "email": email, "pow": pow })))
+            };
+
+            [
+                begin(json!(null)).await.into_response().status(),
+                begin(json!({ "challenge": challenge.challenge, "nonce": "wrong" })).await.into_response().status(),
+                begin(json!({ "challenge": valid.challenge, "nonce": valid.nonce })).await.into_response().status(),
+            ]
+        })
+        .await;
+
+        assert_eq!(statuses[0], StatusCode::FORBIDDEN);
+        assert_eq!(statuses[1], StatusCode::FORBIDDEN);
+        // Le challenge a été consommé par la tentative invalide
+        assert_eq!(statuses[2], StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_register_with_valid_proof_of_work() {
+        let config = config::Config {
+            bot_protection: BotProtection::ProofOfWork { difficulty: 8 },
+            ..Default::default()
+        };
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+
+        let status = config::scope(config, async {
+            let Json(challenge) = pow_challenge().await.unwrap();
+            let solution = crate::utils::pow::tests::solve(&challenge);
+            let pow = json!({ "challenge": solution.challenge, "nonce": solution.nonce });
+            register_begin(ApiJson(json!({ "email": email, "pow": pow })))
+                .await
+                .into_response()
+                .status()
+        })
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Désactivée par défaut
+        assert_eq!(pow_challenge().await.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_register_with_valid_invite() {
+        let code = invite::generate().unwrap();
+        let status = config::scope(closed_registration(), begin_with_invite(&code)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(invite::is_valid(&code)); // Le code n'est consommé qu'à la fin de l'inscription
+    }
+
+    #[tokio::test]
+    async fn test_register_with_reused_invite() {
+        let code = invite::generate().unwrap();
+        invite::consume(&code, "first@example.com").unwrap();
+
+        let status = config::scope(closed_registration(), begin_with_invite(&code)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(invite::consume(&code, "second@example.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_register_with_invalid_invite() {
+        let status = config::scope(closed_registration(), begin_with_invite("not-a-code")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Sans restriction, le code est ignoré
+        assert_eq!(begin_with_invite("not-a-code").await, StatusCode::OK);
+    }
+
+    /// Inscription complète de `email` avec `authenticator`
+    async fn register(email: &str, authenticator: &SoftAuthenticator) -> StatusCode {
+        let Json(challenge) = register_begin(ApiJson(json!({ "email": email }))).await.unwrap();
+        let request = RegisterCompleteRequest {
+            registration: UserRegistration {
+                email: email.to_string(),
+                first_name: Some("Jean".to_string()),
+                last_name: Some("Dupont".to_string()),
+            },
+            state_id: challenge.state_id,
+            response: authenticator.register(&challenge.challenge),
+            reset_mode: false,
+            invite_code: None,
+            recovery_token: None,
+        };
+        register_complete(PreferredLocale(Locale::En), ValidatedJson(request)).await.into_response().status()
+    }
+
+    #[tokio::test]
+    async fn test_failed_passkey_association_creates_no_account() {
+        let authenticator = SoftAuthenticator::new();
+        let first = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        assert_eq!(register(&first, &authenticator).await, StatusCode::OK);
+
+        // La même passkey ne peut pas être associée à un second compte : le compte n'est pas créé
+        let second = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        assert_eq!(register(&second, &authenticator).await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(user::get(&second).unwrap().is_none());
+        assert!(crate::database::email::sent_to(&second).is_empty());
+
+        // L'inscription reste possible avec une autre passkey
+        assert_eq!(register(&second, &SoftAuthenticator::new()).await, StatusCode::OK);
+        assert_eq!(user::get(&second).unwrap().unwrap().passkeys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_registration_survives_mail_failure() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let broken_mail = config::Config {
+            mail_from: "not an address".to_string(),
+            ..Default::default()
+        };
+        let status = config::scope(broken_mail, register(&email, &SoftAuthenticator::new())).await;
+
+        // Le compte est créé avec sa passkey, l'email reste à envoyer
+        assert_eq!(status, StatusCode::OK);
+        let account = user::get(&email).unwrap().unwrap();
+        assert_eq!(account.passkeys.len(), 1);
+        assert!(account.validation_mail_pending);
+        assert!(crate::database::email::sent_to(&email).is_empty());
+
+        // Renvoyé au passage suivant
+        assert!(retry_validation_mails().unwrap() >= 1);
+        assert!(!user::get(&email).unwrap().unwrap().validation_mail_pending);
+        assert_eq!(crate::database::email::sent_to(&email).len(), 1);
+    }
+
+    fn location(response: axum::response::Response) -> String {
+        assert!(response.status().is_redirection());
+        response.headers()[http::header::LOCATION].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_reset_account_encodes_email() {
+        let email = "jean+test&co@example.com";
+        let recovery_token = token::generate(email, TokenKind::Recovery).unwrap();
+
+        let response = reset_account(Path(recovery_token.clone())).await.into_response();
+        assert_eq!(
+            location(response),
+            format!(
+                "/register?reset_mode=true&email=jean%2Btest%26co%40example.com&token={}&success=true",
+                recovery_token
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_account_rejects_invalid_email() {
+        let recovery_token = token::generate("not an email&admin=true", TokenKind::Recovery).unwrap();
+
+        let response = reset_account(Path(recovery_token)).await.into_response();
+        assert_eq!(location(response), "/register?error=recovery_failed");
+    }
+
+    #[tokio::test]
+    async fn test_token_errors_map_to_redirects() {
+        // Un token de validation ne peut pas servir à une récupération, et inversement
+        let email = create_verified_user();
+        let validation_token = token::generate(&email, TokenKind::Validation).unwrap();
+        let response = reset_account(Path(validation_token.clone())).await.into_response();
+        assert_eq!(location(response), "/register?error=recovery_failed");
+
+        let recovery_token = token::generate(&email, TokenKind::Recovery).unwrap();
+        let response = validate_account(Path(recovery_token)).await.into_response();
+        assert_eq!(location(response), "/register?error=invalid_token");
+
+        let response = validate_account(Path(validation_token.clone())).await.into_response();
+        assert_eq!(location(response), "/login?validated=true");
+        let response = validate_account(Path(validation_token)).await.into_response();
+        assert_eq!(location(response), "/login?validated=true");
+
+        let expired = config::scope(
+            config::Config { token_ttl_secs: 0, ..Default::default() },
+            async { token::generate(&email, TokenKind::Recovery).unwrap() },
+        )
+        .await;
+        let response = reset_account(Path(expired)).await.into_response();
+        assert_eq!(location(response), "/register?error=recovery_expired");
+    }
+
+    async fn render_login(query: &str) -> String {
+        let params = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        let response = login_page(Query(params)).await.into_response();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_render_failure_returns_error_page() {
+        let response = render_page("missing-template", &json!({}));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8(bytes.to_vec()).unwrap(), ERROR_PAGE);
+
+        // Même page pour un template présent mais dont le rendu échoue
+        let mut hbs = handlebars::Handlebars::new();
+        hbs.set_strict_mode(true);
+        hbs.register_template_string("page", "Hello {{name}}").unwrap();
+        let response = render_page_with(&hbs, "page", &json!({}));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(render_page_with(&hbs, "page", &json!({ "name": "Jean" })).status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_login_page_shows_validation_message() {
+        assert!(render_login("validated=true").await.contains("Your account has been validated"));
+        assert!(!render_login("").await.contains("Your account has been validated"));
+        assert!(!render_login("validated=false").await.contains("Your account has been validated"));
+    }
+
+    async fn recover_with_accept(accept: &str) -> (String, String) {
+        use tower::ServiceExt;
+
+        let email = create_verified_user();
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/recover")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::ACCEPT, accept)
+            .body(axum::body::Body::from(json!({ "email": email }).to_string()))
+            .unwrap();
+
+        let response = crate::backend::router::get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[http::header::CONTENT_TYPE].to_str().unwrap().to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_recover_account_negotiates_json() {
+        let (content_type, body) = recover_with_accept("application/json").await;
+        assert!(content_type.starts_with("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["message"], "Recovery email sent. Please check your inbox.");
+    }
+
+    #[tokio::test]
+    async fn test_recover_account_negotiates_html() {
+        let (content_type, body) = recover_with_accept("text/html").await;
+        assert!(content_type.starts_with("text/html"));
+        assert!(body.contains("<html"));
+    }
+
+    /// Appelle l'endpoint de validation à blanc via le routeur
+    async fn dry_run(body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        use tower::ServiceExt;
+
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/api/validate/registration")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let response = crate::backend::router::get_router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_email_is_rejected_early() {
+        use crate::backend::test_app::{json, TestApp};
+
+        let app = TestApp::new().await;
+        let email = format!("{}@example.com", "a".repeat(1024 * 1024));
+        for uri in ["/register", "/login", "/recover", "/api/validate/registration"] {
+            let started = std::time::Instant::now();
+            let response = app.post_json(uri, json!({ "email": email, "first_name": "Jean", "last_name": "Dupont" })).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(json(response).await["error"]["email"][0]["code"], "email_too_long", "{}", uri);
+            assert!(started.elapsed() < std::time::Duration::from_secs(5), "{}", uri);
         }
     }
-    if let Some(error) = params.get("error") {
-        if error == "recovery_failed" {
-            context.insert(
-                "error_message",
-                "Invalid or expired recovery link. Please try again.",
-            );
+
+    #[tokio::test]
+    async fn test_dry_run_registration_validation() {
+        let (status, body) = dry_run(json!({
+            "email": "jean.dupont@example.com",
+            "first_name": "Jean",
+            "last_name": "Dupont",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["valid"], true);
+
+        let (status, body) = dry_run(json!({
+            "email": "invalid-email",
+            "first_name": "Jean123",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]["email"].is_array());
+        assert!(body["error"]["first_name"].is_array());
+        assert_eq!(body["error"]["last_name"][0]["code"], "required");
+
+        // Un email déjà utilisé n'est pas signalé, et rien n'est créé
+        let email = create_verified_user();
+        let (status, _) = dry_run(json!({ "email": email, "first_name": "Jean", "last_name": "Dupont" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let unknown = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        dry_run(json!({ "email": unknown, "first_name": "Jean", "last_name": "Dupont" })).await;
+        assert!(!user::exists(&unknown).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_registration_display_name() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let begin = json!({ "email": email, "first_name": "Jean", "last_name": " Dupont " });
+
+        let Json(challenge) = register_begin(ApiJson(begin.clone())).await.unwrap();
+        assert_eq!(challenge.challenge.user(), (email.as_str(), "Jean Dupont"));
+
+        // Sans noms, ou si la politique l'impose, l'email est affiché
+        let Json(challenge) = register_begin(ApiJson(json!({ "email": email }))).await.unwrap();
+        assert_eq!(challenge.challenge.user().1, email.as_str());
+
+        let config = config::Config {
+            display_name_policy: DisplayNamePolicy::Email,
+            ..Default::default()
+        };
+        let Json(challenge) = config::scope(config, register_begin(ApiJson(begin))).await.unwrap();
+        assert_eq!(challenge.challenge.user().1, email.as_str());
+    }
+
+    fn magic_link_config() -> config::Config {
+        config::Config {
+            magic_link_login: true,
+            ..Default::default()
         }
     }
 
-    HBS.render("register", &context)
-        .map(Html)
-        .unwrap_or_else(|_| Html("<h1>Internal Server Error</h1>".to_string()))
-}
+    /// Extrait le token du dernier lien de connexion envoyé à `email`
+    fn sent_login_token(email: &str) -> Option<String> {
+        crate::database::email::sent_to(email)
+            .iter()
+            .rev()
+            .find_map(|mail| mail.body.split("/login/magic/").nth(1).map(str::to_string))
+    }
 
-/// Affiche la page de récupération de compte
-pub async fn recover_page() -> impl IntoResponse {
-    Html(include_str!("../../templates/recover.hbs"))
+    #[tokio::test]
+    async fn test_magic_link_creates_session() {
+        let email = create_verified_user();
+        let request = magic_link_request(PreferredLocale(Locale::En), ApiJson(json!({ "email": email })));
+        let _ = config::scope(magic_link_config(), request).await.unwrap();
+        let login_token = sent_login_token(&email).unwrap();
+
+        let session = Session::new(None);
+        let redirect = config::scope(magic_link_config(), magic_link_login(session.clone(), Path(login_token.clone())))
+            .await
+            .unwrap();
+        assert_eq!(location(redirect.into_response()), "/home");
+        assert_eq!(session.get::<String>("email").unwrap(), Some(email));
+
+        // Le lien ne sert qu'une fois
+        let reused = Session::new(None);
+        let redirect = config::scope(magic_link_config(), magic_link_login(reused.clone(), Path(login_token)))
+            .await
+            .unwrap();
+        assert_eq!(location(redirect.into_response()), "/login?error=invalid_magic_link");
+        assert_eq!(reused.get::<String>("email").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_magic_link_expires() {
+        let email = create_verified_user();
+        let config = config::Config {
+            magic_link_ttl_secs: 0,
+            ..magic_link_config()
+        };
+        let _ = config::scope(config.clone(), magic_link_request(PreferredLocale(Locale::En), ApiJson(json!({ "email": email })))).await.unwrap();
+        let login_token = sent_login_token(&email).unwrap();
+
+        let session = Session::new(None);
+        let redirect = config::scope(config, magic_link_login(session.clone(), Path(login_token)))
+            .await
+            .unwrap();
+        assert_eq!(location(redirect.into_response()), "/login?error=magic_link_expired");
+        assert_eq!(session.get::<String>("email").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_magic_link_disabled_by_default() {
+        let email = create_verified_user();
+        let status = magic_link_request(PreferredLocale(Locale::En), ApiJson(json!({ "email": email }))).await.into_response().status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(sent_login_token(&email).is_none());
+
+        // Un compte inconnu reçoit la même réponse qu'un compte existant, sans email
+        let unknown = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let request = magic_link_request(PreferredLocale(Locale::En), ApiJson(json!({ "email": unknown })));
+        assert!(config::scope(magic_link_config(), request).await.is_ok());
+        assert!(sent_login_token(&unknown).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_content_type_is_unsupported() {
+        use tower::ServiceExt;
+
+        for uri in ["/register", "/register/complete", "/login", "/login/complete", "/recover"] {
+            let request = http::Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, "text/plain")
+                .body(axum::body::Body::from(json!({ "email": "jean@example.com" }).to_string()))
+                .unwrap();
+
+            let response = crate::backend::router::get_router().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE, "{}", uri);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert!(body["error"].as_str().unwrap().contains("application/json"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reset_account_unknown_token_redirects() {
+        let response = reset_account(Path("unknown".to_string())).await.into_response();
+        assert_eq!(location(response), "/register?error=recovery_failed");
+    }
+
+    #[tokio::test]
+    async fn test_login_unverified_returns_code() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        user::set_passkey(&email, test_passkey()).unwrap();
+
+        let response = login_begin(Session::new(None), ApiJson(json!({ "email": email })))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "EMAIL_NOT_VERIFIED");
+        assert!(body["hint"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_login_without_credentials_returns_code() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        user::verify(&email).unwrap();
+
+        let response = login_begin(Session::new(None), ApiJson(json!({ "email": email })))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "NO_CREDENTIALS");
+
+        // Les passkeys sont lues dans la base des comptes, sans autre cache
+        user::set_passkey(&email, test_passkey()).unwrap();
+        let response = login_begin(Session::new(None), ApiJson(json!({ "email": email })))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_login_state_bound_to_session() {
+        let email = create_verified_user();
+        let owner = Session::new(None);
+        let attacker = Session::new(None);
+
+        let Json(challenge) = login_begin(owner.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id.clone(),
+            response: json!({}),
+            remember_me: Some(false),
+            next: None,
+        };
+        let status = login_complete(attacker, ClientIp(None), ValidatedJson(request))
+        .await
+        .into_response()
+        .status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // L'état reste disponible pour la session d'origine
+        assert!(AUTHENTICATION_STATES.read().await.contains_key(&challenge.state_id));
+        assert_eq!(
+            owner.get::<String>("login_state_id").unwrap(),
+            Some(challenge.state_id)
+        );
+    }
+
+    /// Redemande une passkey en mode reset, en répondant avec `authenticator`
+    async fn reset_passkey(
+        email: &str,
+        recovery_token: Option<&str>,
+        authenticator: &SoftAuthenticator,
+    ) -> StatusCode {
+        let begin = json!({ "email": email, "reset_mode": true, "recovery_token": recovery_token });
+        let challenge = match register_begin(ApiJson(begin)).await {
+            Ok(Json(challenge)) => challenge,
+            Err(err) => return Err::<(), _>(err).into_response().status(),
+        };
+
+        let request = RegisterCompleteRequest {
+            registration: UserRegistration {
+                email: email.to_string(),
+                first_name: Some("Jean".to_string()),
+                last_name: Some("Dupont".to_string()),
+            },
+            state_id: challenge.state_id,
+            response: authenticator.register(&challenge.challenge),
+            reset_mode: true,
+            invite_code: None,
+            recovery_token: recovery_token.map(str::to_string),
+        };
+        register_complete(PreferredLocale(Locale::En), ValidatedJson(request))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_debug_session_only_in_dev_mode() {
+        let dev = config::Config {
+            dev_mode: true,
+            ..Default::default()
+        };
+        let session = Session::new(None);
+        start_session(&session, "dev@example.com").unwrap();
+
+        let Json(info) = config::scope(dev.clone(), debug_session(session.clone())).await.unwrap();
+        assert_eq!(info["authenticated"], true);
+        assert_eq!(info["email"], "dev@example.com");
+        assert_eq!(info["remember_me"], false);
+        assert!(info["expiry"].is_string());
+        // Rien d'autre n'est exposé
+        assert_eq!(info.as_object().unwrap().len(), 4);
+
+        let Json(info) = config::scope(dev.clone(), debug_session(Session::new(None))).await.unwrap();
+        assert_eq!(info["authenticated"], false);
+        assert!(info["email"].is_null());
+
+        // En production (ou sans mode développement), la route n'existe pas
+        let production = config::Config {
+            strict_security: true,
+            ..dev
+        };
+        assert_eq!(config::scope(production, debug_session(session.clone())).await.unwrap_err(), StatusCode::NOT_FOUND);
+        assert_eq!(debug_session(session).await.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_self_service_recovery_can_be_disabled() {
+        let config = config::Config {
+            self_service_recovery: false,
+            ..Default::default()
+        };
+        let email = create_verified_user();
+
+        let status = config::scope(config.clone(), recover_page()).await.status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let request = recover_account(
+            ClientIp(None),
+            ResponseFormat::Json,
+            PreferredLocale(Locale::En),
+            ApiJson(json!({ "email": email })),
+        );
+        let status = config::scope(config.clone(), request).await.into_response().status();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(token::issued_to(&email).is_empty());
+
+        // Un lien émis par un administrateur mène toujours au remplacement de la passkey
+        let recovery_token = token::generate(&email, TokenKind::Recovery).unwrap();
+        let redirect = location(config::scope(config.clone(), reset_account(Path(recovery_token.clone()))).await.into_response());
+        assert!(redirect.starts_with("/register?reset_mode=true"));
+        let status = config::scope(config, reset_passkey(&email, Some(&recovery_token), &SoftAuthenticator::new())).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_then_reset_flow() {
+        let email = create_verified_user();
+        let recovery_token = token::generate(&email, TokenKind::Recovery).unwrap();
+
+        // Le lien de récupération transmet le token sans le consommer
+        let redirect = location(reset_account(Path(recovery_token.clone())).await.into_response());
+        let redirect = url::Url::parse(&format!("http://localhost{}", redirect)).unwrap();
+        let (_, forwarded) = redirect.query_pairs().find(|(key, _)| key == "token").unwrap();
+        assert_eq!(forwarded, recovery_token);
+        assert!(token::peek(&recovery_token, TokenKind::Recovery).is_ok());
+        let generation = user::get(&email).unwrap().unwrap().session_generation;
+        assert_eq!(generation, 1);
+
+        let authenticator = SoftAuthenticator::new();
+        let status = reset_passkey(&email, Some(&recovery_token), &authenticator).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // La passkey est remplacée sur le compte existant, qui reste vérifié
+        let user = user::get(&email).unwrap().unwrap();
+        assert!(user.verified);
+        assert_eq!(user.session_generation, generation + 1);
+        assert_eq!(user.passkeys[0].clone().cred_id().as_ref(), authenticator.cred_id.as_slice());
+        assert!(token::peek(&recovery_token, TokenKind::Recovery).is_err());
+
+        // La nouvelle passkey permet de se connecter
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: authenticator.authenticate(&challenge.challenge),
+            remember_me: Some(false),
+            next: None,
+        };
+        assert!(login_complete(session, ClientIp(None), ValidatedJson(request)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reset_without_valid_token_is_rejected() {
+        let email = create_verified_user();
+        let original = user::get(&email).unwrap().unwrap().passkeys[0].clone();
+        let authenticator = SoftAuthenticator::new();
+
+        assert_eq!(reset_passkey(&email, None, &authenticator).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            reset_passkey(&email, Some("not-a-token"), &authenticator).await,
+            StatusCode::FORBIDDEN
+        );
+
+        // Un token déjà consommé ne peut pas être réutilisé
+        let used = token::generate(&email, TokenKind::Recovery).unwrap();
+        token::consume(&used, TokenKind::Recovery).unwrap();
+        assert_eq!(reset_passkey(&email, Some(&used), &authenticator).await, StatusCode::FORBIDDEN);
+
+        assert_eq!(user::get(&email).unwrap().unwrap().passkeys[0].clone().cred_id(), original.cred_id());
+    }
+
+    #[tokio::test]
+    async fn test_reset_complete_rechecks_token() {
+        let email = create_verified_user();
+        let recovery_token = token::generate(&email, TokenKind::Recovery).unwrap();
+        let begin = json!({ "email": email, "reset_mode": true, "recovery_token": recovery_token });
+        let Json(challenge) = register_begin(ApiJson(begin)).await.unwrap();
+
+        // Le token ne peut pas être omis à la fin de la cérémonie
+        let request = RegisterCompleteRequest {
+            registration: UserRegistration {
+                email: email.clone(),
+                first_name: Some("Jean".to_string()),
+                last_name: Some("Dupont".to_string()),
+            },
+            state_id: challenge.state_id,
+            response: SoftAuthenticator::new().register(&challenge.challenge),
+            reset_mode: true,
+            invite_code: None,
+            recovery_token: None,
+        };
+        let status = register_complete(PreferredLocale(Locale::En), ValidatedJson(request)).await.into_response().status();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(token::peek(&recovery_token, TokenKind::Recovery).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reset_requires_token_for_same_email() {
+        let email = create_verified_user();
+        let other_token = token::generate(&create_verified_user(), TokenKind::Recovery).unwrap();
+        let original = user::get(&email).unwrap().unwrap().passkeys[0].clone();
+
+        let authenticator = SoftAuthenticator::new();
+        let status = reset_passkey(&email, Some(&other_token), &authenticator).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Rien n'a changé : ni la passkey, ni le token de l'autre compte
+        assert_eq!(user::get(&email).unwrap().unwrap().passkeys[0].clone().cred_id(), original.cred_id());
+        assert!(token::peek(&other_token, TokenKind::Recovery).is_ok());
+    }
+
+    /// Enregistre les événements de cérémonie WebAuthn émis sur le thread courant
+    #[derive(Clone, Default)]
+    struct CeremonyEvents(std::sync::Arc<std::sync::Mutex<Vec<HashMap<String, String>>>>);
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CeremonyEvents {
+        fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+            if event.metadata().target() == "webauthn" {
+                let mut fields = HashMap::new();
+                event.record(&mut Fields(&mut fields));
+                self.0.lock().unwrap().push(fields);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registration_ceremony_is_traced() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let events = CeremonyEvents::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let Json(challenge) = register_begin(ApiJson(json!({ "email": email }))).await.unwrap();
+        let state_id = challenge.state_id.clone();
+        let response = SoftAuthenticator::new().register(&challenge.challenge);
+        let request = RegisterCompleteRequest {
+            registration: UserRegistration {
+                email: email.clone(),
+                first_name: Some("Jean".to_string()),
+                last_name: Some("Dupont".to_string()),
+            },
+            state_id: challenge.state_id,
+            response: response.clone(),
+            reset_mode: false,
+            invite_code: None,
+            recovery_token: None,
+        };
+        assert!(register_complete(PreferredLocale(Locale::En), ValidatedJson(request)).await.is_ok());
+
+        let events = events.0.lock().unwrap().clone();
+        let messages: Vec<_> = events.iter().map(|event| event["message"].as_str()).collect();
+        assert_eq!(messages, vec!["Ceremony started", "Ceremony finished"]);
+        assert!(events.iter().all(|event| event["state_id"] == state_id && event["ceremony"] == "registration"));
+        assert_eq!(events[1]["outcome"], "success");
+        assert!(events[1].contains_key("duration_ms"));
+
+        // Ni l'email ni la réponse de l'authentificateur ne sont journalisés
+        let logged = format!("{:?}", events);
+        assert!(!logged.contains(&email));
+        assert!(!logged.contains(response["response"]["attestationObject"].as_str().unwrap()));
+    }
+
+    /// Compte les avertissements de détection d'abus émis sur le thread courant
+    struct AbuseWarnings(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for AbuseWarnings {
+        fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+            let metadata = event.metadata();
+            if metadata.target() == "abuse" && *metadata.level() == tracing::Level::WARN {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Compte vérifié dont la passkey appartient à l'authentificateur renvoyé
+    async fn create_user_with_authenticator() -> (String, SoftAuthenticator) {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let authenticator = SoftAuthenticator::new();
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        user::create(&email, Some("Jean"), Some("Dupont"), state.user_handle).unwrap();
+        user::set_passkey(&email, passkey).unwrap();
+        user::verify(&email).unwrap();
+        (email, authenticator)
+    }
+
+    /// Connexion complète, avec ou sans « se souvenir de cet appareil »
+    async fn login_session(remember_me: bool) -> Session {
+        let (email, authenticator) = create_user_with_authenticator().await;
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: authenticator.authenticate(&challenge.challenge),
+            remember_me: Some(remember_me),
+            next: None,
+        };
+        let _ = login_complete(session.clone(), ClientIp(None), ValidatedJson(request)).await.unwrap();
+        session
+    }
+
+    /// Connexion de `email` ; retourne l'option « se souvenir de cet appareil » de la session
+    async fn login_remembered(email: &str, authenticator: &SoftAuthenticator, remember_me: Option<bool>) -> Option<bool> {
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: authenticator.authenticate(&challenge.challenge),
+            remember_me,
+            next: None,
+        };
+        let _ = login_complete(session.clone(), ClientIp(None), ValidatedJson(request)).await.unwrap();
+        session.get::<bool>("remember_me").unwrap()
+    }
+
+    /// Connexion de `email` avec la cible `next` ; retourne la redirection obtenue
+    async fn login_redirect(email: &str, authenticator: &SoftAuthenticator, next: &str) -> String {
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: authenticator.authenticate(&challenge.challenge),
+            remember_me: Some(false),
+            next: Some(next.to_string()),
+        };
+        let redirect = login_complete(session.clone(), ClientIp(None), ValidatedJson(request)).await.unwrap();
+        assert!(session.get::<String>("email").unwrap().is_some());
+        location(redirect.into_response())
+    }
+
+    #[tokio::test]
+    async fn test_login_next_stays_on_site() {
+        let (email, authenticator) = create_user_with_authenticator().await;
+        assert_eq!(login_redirect(&email, &authenticator, "/passkeys?tab=all").await, "/passkeys?tab=all");
+
+        // Une autre origine est ignorée : retour à /home, la connexion reste valide
+        assert_eq!(login_redirect(&email, &authenticator, "https://evil.example/home").await, "/home");
+        assert_eq!(login_redirect(&email, &authenticator, "//evil.example/home").await, "/home");
+    }
+
+    #[tokio::test]
+    async fn test_login_follows_account_settings() {
+        use crate::database::email;
+
+        // Par défaut : session courte et aucun email
+        let (address, authenticator) = create_user_with_authenticator().await;
+        assert_eq!(login_remembered(&address, &authenticator, None).await, Some(false));
+        assert!(email::sent_to(&address).is_empty());
+
+        user::update_settings(&address, |settings| {
+            settings.remember_me_default = true;
+            settings.login_notifications = true;
+        })
+        .unwrap();
+        assert_eq!(login_remembered(&address, &authenticator, None).await, Some(true));
+        let notices = email::sent_to(&address);
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].subject, "New sign-in to your account");
+
+        // Le choix fait à la connexion l'emporte sur la préférence
+        assert_eq!(login_remembered(&address, &authenticator, Some(false)).await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_opted_out_notices_are_not_sent() {
+        use crate::database::email;
+
+        let (address, authenticator) = create_user_with_authenticator().await;
+        user::update_settings(&address, |settings| {
+            settings.login_notifications = false;
+            settings.recovery_notifications = false;
+        })
+        .unwrap();
+
+        // Ni connexion ni récupération ne donnent lieu à un email
+        login_remembered(&address, &authenticator, None).await;
+        let recovery_token = token::generate(&address, TokenKind::Recovery).unwrap();
+        let status = reset_passkey(&address, Some(&recovery_token), &SoftAuthenticator::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(email::sent_to(&address).is_empty());
+
+        // L'email de validation part quelles que soient les préférences
+        send_validation_mail(&address, Locale::En).unwrap();
+        let sent = email::sent_to(&address);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "Account Validation");
+    }
+
+    #[tokio::test]
+    async fn test_recovery_notice_is_on_by_default() {
+        use crate::database::email;
+
+        let address = create_verified_user();
+        let recovery_token = token::generate(&address, TokenKind::Recovery).unwrap();
+        let status = reset_passkey(&address, Some(&recovery_token), &SoftAuthenticator::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let sent = email::sent_to(&address);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "Your account was recovered");
+    }
+
+    #[tokio::test]
+    async fn test_remember_me_extends_session() {
+        let config = config::Config {
+            session_idle_secs: 30 * 60,
+            remember_me_secs: 7 * 24 * 60 * 60,
+            ..Default::default()
+        };
+        let (short, remembered) = config::scope(config, async {
+            (login_session(false).await, login_session(true).await)
+        })
+        .await;
+
+        assert_eq!(short.get::<bool>("remember_me").unwrap(), Some(false));
+        assert_eq!(remembered.get::<bool>("remember_me").unwrap(), Some(true));
+        let short_age = short.expiry_age().whole_seconds();
+        let remembered_age = remembered.expiry_age().whole_seconds();
+        assert!((30 * 60 - 5..=30 * 60).contains(&short_age));
+        assert!((7 * 24 * 60 * 60 - 5..=7 * 24 * 60 * 60).contains(&remembered_age));
+    }
+
+    #[tokio::test]
+    async fn test_remember_me_is_capped_by_max_lifetime() {
+        let config = config::Config {
+            remember_me_secs: 7 * 24 * 60 * 60,
+            session_max_lifetime_secs: 24 * 60 * 60,
+            ..Default::default()
+        };
+        let session = config::scope(config, login_session(true)).await;
+        assert!(session.expiry_age().whole_seconds() <= 24 * 60 * 60);
+    }
+
+    #[tokio::test]
+    async fn test_failed_login_logs_one_warning() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let warnings = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(AbuseWarnings(warnings.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let email = create_verified_user();
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+
+        // Réponse d'un authentificateur qui ne correspond pas à la passkey du compte
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: SoftAuthenticator::new().authenticate(&challenge.challenge),
+            remember_me: Some(false),
+            next: None,
+        };
+        let config = config::Config {
+            abuse_log_per_minute: u32::MAX,
+            ..Default::default()
+        };
+        let ip = Some(std::net::IpAddr::from([203, 0, 113, 7]));
+        let result = config::scope(config, login_complete(session, ClientIp(ip), ValidatedJson(request))).await;
+
+        assert_eq!(result.into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(warnings.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    /// Durée médiane de `login_begin` pour `email`, en microsecondes
+    async fn median_login_begin_micros(email: &str) -> u128 {
+        let mut durations = Vec::new();
+        for _ in 0..21 {
+            let start = std::time::Instant::now();
+            let _ = login_begin(Session::new(None), ApiJson(json!({ "email": email }))).await;
+            durations.push(start.elapsed().as_micros());
+        }
+        durations.sort_unstable();
+        durations[durations.len() / 2]
+    }
+
+    #[tokio::test]
+    async fn test_login_begin_timing_does_not_reveal_account() {
+        let existing = create_verified_user();
+        let unknown = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let unverified = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&unverified, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+
+        // Les erreurs restent distinctes
+        let status = login_begin(Session::new(None), ApiJson(json!({ "email": unknown }))).await.into_response().status();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Mesure indicative : seul un écart important est signalé
+        let existing = median_login_begin_micros(&existing).await;
+        for email in [&unknown, &unverified] {
+            let other = median_login_begin_micros(email).await;
+            assert!(existing.abs_diff(other) < 20_000, "{existing}µs vs {other}µs");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_failure_returns_code() {
+        let email = create_verified_user();
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+        let Json(other) = login_begin(Session::new(None), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+
+        // Réponse signée pour un autre challenge
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response: SoftAuthenticator::new().authenticate(&other.challenge),
+            remember_me: Some(false),
+            next: None,
+        };
+        let response = login_complete(session, ClientIp(None), ValidatedJson(request))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "CHALLENGE_MISMATCH");
+        assert!(!body["error"].as_str().unwrap().contains("challenge"));
+    }
+
+    #[tokio::test]
+    async fn test_response_without_client_data_is_rejected() {
+        let email = create_verified_user();
+        let session = Session::new(None);
+        let Json(challenge) = login_begin(session.clone(), ApiJson(json!({ "email": email })))
+            .await
+            .unwrap();
+
+        let mut response = SoftAuthenticator::new().authenticate(&challenge.challenge);
+        response["response"].as_object_mut().unwrap().remove("clientDataJSON");
+        let request = LoginCompleteRequest {
+            state_id: challenge.state_id,
+            response,
+            remember_me: Some(false),
+            next: None,
+        };
+        let response = login_complete(session, ClientIp(None), ValidatedJson(request))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "INVALID_RESPONSE");
+        assert_eq!(body["field"], "response.clientDataJSON");
+        assert_eq!(body["error"], "Missing field `response.clientDataJSON`");
+    }
+
+    #[tokio::test]
+    async fn test_locale_preference_wins_over_accept_language() {
+        use crate::backend::handlers_auth::update_profile;
+        use crate::backend::middlewares::SessionUser;
+        use crate::backend::models::ProfileUpdate;
+        use tower::ServiceExt;
+
+        let email = create_verified_user();
+        let session = Session::new(None);
+        start_session(&session, &email).unwrap();
+        let update = ProfileUpdate { locale: Some(Locale::Fr) };
+        let _ = update_profile(SessionUser { email: email.clone() }, ApiJson(update)).await.unwrap();
+
+        let render = |session: Option<Session>, accept_language: &str| {
+            let mut request = axum::http::Request::builder()
+                .uri("/")
+                .header("Accept-Language", accept_language)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(session.unwrap_or_else(|| Session::new(None)));
+            async {
+                let response = axum::Router::new().route("/", axum::routing::get(index)).oneshot(request).await.unwrap();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        // La préférence du compte l'emporte sur le navigateur
+        let page = render(Some(session), "en-US, en;q=0.9").await;
+        assert!(page.contains(r#"<html lang="fr">"#));
+        assert!(page.contains("Bienvenue"));
+
+        // Sans compte, le navigateur décide, puis l'anglais par défaut
+        assert!(render(None, "fr-CH").await.contains("Bienvenue"));
+        assert!(render(None, "en").await.contains("Welcome"));
+        assert!(render(None, "de").await.contains("Welcome"));
+
+        // Les emails suivent aussi la préférence du destinataire
+        let request = magic_link_request(PreferredLocale(Locale::En), ApiJson(json!({ "email": email })));
+        let _ = config::scope(magic_link_config(), request).await.unwrap();
+        let mail = crate::database::email::sent_to(&email).pop().unwrap();
+        assert_eq!(mail.subject, "Lien de connexion");
+        assert!(mail.body.starts_with("Cliquez ici pour vous connecter"));
+    }
 }
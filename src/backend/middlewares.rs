@@ -1,12 +1,31 @@
 //! Middleware pour gérer les sessions utilisateur.
 //! Vérifie la validité d'une session utilisateur et rejette les requêtes non autorisées.
+//! Fournit aussi un extracteur JSON qui valide le contenu de la requête.
 
-use axum::extract::FromRequestParts;
-use axum::http::{request::Parts, StatusCode};
-use tower_sessions::Session;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use axum::body::Bytes;
+use axum::extract::rejection::{JsonRejection, MissingJsonContentType};
+use axum::extract::{ConnectInfo, FromRequest, FromRequestParts, Request};
+use axum::http::{header, request::Parts, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Response};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::time::Instant;
+use tower_sessions::{Expiry, Session};
+use tracing::Instrument;
+use validator::Validate;
+use crate::config;
+use crate::database::{now, user};
+use crate::utils::i18n::Locale;
+use crate::utils::rate_limit::{BLOCKED_RETRY_AFTER, RATE_LIMITER};
 
 /// Middleware pour valider une session utilisateur
-pub struct SessionUser;
+pub struct SessionUser {
+    pub email: String,
+}
 
 #[async_trait::async_trait]
 impl <S> FromRequestParts<S> for SessionUser
@@ -18,10 +37,866 @@ where
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
         if let Some(session) = parts.extensions.get::<Session>() {
             if session.get::<bool>("isAuthenticated").unwrap_or_default().is_some() {
-                return Ok(SessionUser);
+                if let Some(email) = session.get::<String>("email").unwrap_or_default() {
+                    // La session doit appartenir à la génération courante du compte
+                    let generation = session.get::<u64>("session_generation").unwrap_or_default();
+                    let current = user::get(&email)
+                        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read user".to_string()))?
+                        .map(|user| user.session_generation);
+                    let authenticated_at = session.get::<u64>("authenticated_at").unwrap_or_default();
+                    if current.is_some()
+                        && current == Some(generation.unwrap_or_default())
+                        && authenticated_at.is_some_and(within_lifetime)
+                    {
+                        return Ok(SessionUser { email });
+                    }
+                }
             }
         }
 
         Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string()))
     }
 }
+
+/// Authentifie la session pour `email`, en l'associant à la génération courante du compte.
+/// La session est courte tant que `remember_session` n'est pas appelé.
+pub fn start_session(session: &Session, email: &str) -> Result<(), tower_sessions::session::Error> {
+    let generation = user::get(email).ok().flatten().map(|user| user.session_generation).unwrap_or_default();
+    let authenticated_at = now();
+    session.insert("isAuthenticated", true)?;
+    session.insert("email", email)?;
+    session.insert("session_generation", generation)?;
+    session.insert("authenticated_at", authenticated_at)?;
+    session.insert("remember_me", false)?;
+    session.set_expiry(Some(session_expiry(false, authenticated_at)));
+    Ok(())
+}
+
+/// Prolonge la session authentifiée : « se souvenir de cet appareil »
+pub fn remember_session(session: &Session) -> Result<(), tower_sessions::session::Error> {
+    let authenticated_at = session.get::<u64>("authenticated_at")?.unwrap_or_else(now);
+    session.insert("remember_me", true)?;
+    session.set_expiry(Some(session_expiry(true, authenticated_at)));
+    Ok(())
+}
+
+/// Expiration d'une session ouverte à `authenticated_at` : courte et prolongée par l'activité par défaut,
+/// persistante si l'utilisateur l'a demandé. La durée de vie maximale s'applique dans les deux cas.
+pub fn session_expiry(remember: bool, authenticated_at: u64) -> Expiry {
+    let config = config::get();
+    let max_lifetime = config.session_max_lifetime_secs;
+    if remember {
+        let expires_at = authenticated_at.saturating_add(config.remember_me_secs.min(max_lifetime));
+        let expires_at = time::OffsetDateTime::from_unix_timestamp(expires_at as i64)
+            .unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+        Expiry::AtDateTime(expires_at)
+    } else {
+        Expiry::OnInactivity(time::Duration::seconds(config.session_idle_secs.min(max_lifetime) as i64))
+    }
+}
+
+/// Une session d'inactivité peut être prolongée indéfiniment : la durée de vie maximale est vérifiée ici
+fn within_lifetime(authenticated_at: u64) -> bool {
+    now() < authenticated_at.saturating_add(config::get().session_max_lifetime_secs)
+}
+
+/// Enregistre une nouvelle preuve de possession d'une passkey pour la session
+pub fn mark_reauthenticated(session: &Session) -> Result<(), tower_sessions::session::Error> {
+    session.insert("reauthenticated_at", now())
+}
+
+/// Middleware pour les actions sensibles : la session doit s'être authentifiée récemment,
+/// à la connexion ou en vérifiant une passkey
+pub struct RecentlyAuthenticatedUser {
+    pub email: String,
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for RecentlyAuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SessionUser { email } = SessionUser::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let last_authentication = parts.extensions.get::<Session>().and_then(|session| {
+            let authenticated_at = session.get::<u64>("authenticated_at").ok().flatten();
+            let reauthenticated_at = session.get::<u64>("reauthenticated_at").ok().flatten();
+            authenticated_at.max(reauthenticated_at)
+        });
+        let window = config::get().reauth_window_secs;
+        if last_authentication.is_some_and(|at| now() <= at.saturating_add(window)) {
+            return Ok(RecentlyAuthenticatedUser { email });
+        }
+
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Please confirm with your passkey", "code": "REAUTH_REQUIRED"})),
+        )
+            .into_response())
+    }
+}
+
+/// Middleware pour restreindre une route aux administrateurs
+pub struct AdminUser;
+
+#[async_trait::async_trait]
+impl <S> FromRequestParts<S> for AdminUser
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SessionUser { email } = SessionUser::from_request_parts(parts, state).await?;
+
+        if !user::is_admin(&email) {
+            return Err((StatusCode::FORBIDDEN, "Forbidden".to_string()));
+        }
+
+        Ok(AdminUser)
+    }
+}
+
+/// Adresse IP du client, si le serveur la fournit.
+/// `X-Forwarded-For` n'est lu que si la connexion vient d'un proxy de confiance.
+pub struct ClientIp(pub Option<IpAddr>);
+
+/// Parcourt la chaîne `X-Forwarded-For` de droite à gauche en sautant les proxies de confiance :
+/// la première adresse non fiable est celle du client. Les entrées plus à gauche peuvent être
+/// forgées par le client et sont ignorées.
+fn forwarded_client(peer: IpAddr, forwarded: &[&str], trusted: &[config::IpNet]) -> IpAddr {
+    let mut client = peer;
+    if !trusted.iter().any(|net| net.contains(peer)) {
+        return client;
+    }
+
+    for hop in forwarded.iter().rev().flat_map(|header| header.rsplit(',')) {
+        // Une entrée illisible arrête le parcours : on garde le dernier proxy de confiance
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !trusted.iter().any(|net| net.contains(client)) {
+            break;
+        }
+    }
+    client
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+            return Ok(ClientIp(None));
+        };
+
+        let forwarded: Vec<&str> = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        let trusted = &config::get().trusted_proxies;
+        Ok(ClientIp(Some(forwarded_client(peer.ip().to_canonical(), &forwarded, trusted))))
+    }
+}
+
+/// Format de réponse préféré par le client d'après l'en-tête `Accept` (HTML par défaut)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Html,
+    Json,
+}
+
+impl ResponseFormat {
+    /// Compare les préférences (`q`) accordées à HTML et à JSON
+    fn from_accept(accept: &str) -> Self {
+        let (mut html, mut json) = (0.0_f32, 0.0_f32);
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next().unwrap_or_default().to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+
+            match media.as_str() {
+                "application/json" => json = json.max(quality),
+                "text/html" | "*/*" => html = html.max(quality),
+                _ => {}
+            }
+        }
+
+        if json > html {
+            ResponseFormat::Json
+        } else {
+            ResponseFormat::Html
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for ResponseFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .headers
+            .get(http::header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(ResponseFormat::Html, ResponseFormat::from_accept))
+    }
+}
+
+/// Langue de la réponse : préférence du compte connecté, sinon `Accept-Language`, sinon la langue par défaut
+pub struct PreferredLocale(pub Locale);
+
+impl PreferredLocale {
+    /// Langue d'un email destiné à `email`, qui n'est pas forcément l'utilisateur connecté
+    pub fn for_recipient(self, email: &str) -> Locale {
+        user::get(email).ok().flatten().and_then(|user| user.locale).unwrap_or(self.0)
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for PreferredLocale
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Ok(SessionUser { email }) = SessionUser::from_request_parts(parts, state).await {
+            if let Some(locale) = user::get(&email).ok().flatten().and_then(|user| user.locale) {
+                return Ok(PreferredLocale(locale));
+            }
+        }
+
+        Ok(PreferredLocale(
+            parts
+                .headers
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Locale::from_accept_language)
+                .unwrap_or_default(),
+        ))
+    }
+}
+
+/// Middleware limitant le nombre de requêtes par IP ; répond 429 avec `Retry-After`
+pub struct IpRateLimit;
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for IpRateLimit
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Ok(ClientIp(Some(ip))) = ClientIp::from_request_parts(parts, state).await else {
+            return Ok(IpRateLimit);
+        };
+
+        // Un limiteur empoisonné refuse plutôt que de laisser tout passer
+        let per_minute = config::get().rate_limit_per_minute;
+        let result = RATE_LIMITER
+            .lock()
+            .map(|mut limiter| limiter.check(ip, Instant::now(), per_minute))
+            .unwrap_or(Err(BLOCKED_RETRY_AFTER));
+
+        result.map(|_| IpRateLimit).map_err(|retry_after| {
+            let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(http::header::RETRY_AFTER, seconds.to_string())],
+                Json(json!({"error": "Too many requests"})),
+            )
+                .into_response()
+        })
+    }
+}
+
+/// Convertit un rejet de l'extracteur JSON en erreur JSON explicite
+fn json_rejection(rejection: JsonRejection) -> Response {
+    let (status, message) = match rejection {
+        JsonRejection::MissingJsonContentType(_) => (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected request with `Content-Type: application/json`".to_string(),
+        ),
+        JsonRejection::JsonDataError(_) => (StatusCode::BAD_REQUEST, rejection.body_text()),
+        ref other => (other.status(), other.body_text()),
+    };
+    (status, Json(json!({"error": message}))).into_response()
+}
+
+/// Type de contenu `application/json` ou `application/*+json`, comme l'exige `Json`
+fn json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    media_type
+        .strip_prefix("application/")
+        .is_some_and(|subtype| subtype == "json" || subtype.ends_with("+json"))
+}
+
+/// Middleware du mode maintenance : répond 503 avec une page d'information, sauf aux
+/// administrateurs. La connexion reste ouverte pour qu'un administrateur puisse s'authentifier.
+pub async fn maintenance(request: Request, next: Next) -> Response {
+    if !config::get().maintenance_mode {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let path = parts.uri.path();
+    let login = path == "/login" || path.starts_with("/login/");
+    if login || AdminUser::from_request_parts(&mut parts, &()).await.is_ok() {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    let unavailable = match ResponseFormat::from_request_parts(&mut parts, &()).await {
+        Ok(ResponseFormat::Json) => Json(json!({
+            "error": "Service under maintenance",
+            "code": "MAINTENANCE",
+        }))
+        .into_response(),
+        _ => crate::HBS
+            .render("maintenance", &json!({}))
+            .map(Html)
+            .unwrap_or_else(|_| Html("<h1>Service under maintenance</h1>".to_string()))
+            .into_response(),
+    };
+    (StatusCode::SERVICE_UNAVAILABLE, unavailable).into_response()
+}
+
+/// Middleware indentant les réponses JSON si `pretty_json` est activé.
+/// Les clés sont réécrites dans l'ordre alphabétique, ce qui garde une sortie déterministe.
+pub async fn pretty_json(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if !config::get().pretty_json || !json_content_type(response.headers()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let pretty = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_vec_pretty(&value));
+    let body = match pretty {
+        Ok(pretty) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            pretty
+        }
+        // Corps qui n'est pas du JSON valide : renvoyé tel quel
+        Err(_) => bytes.to_vec(),
+    };
+    Response::from_parts(parts, axum::body::Body::from(body))
+}
+
+/// Middleware des cérémonies WebAuthn : une requête envoyée depuis une autre origine que celle
+/// du relying party est refusée, que le navigateur ait appliqué CORS ou non. Les requêtes sans
+/// en-tête `Origin` (clients hors navigateur) et les pages (`GET`) ne sont pas concernées.
+pub async fn webauthn_origin(request: Request, next: Next) -> Response {
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD);
+    let origin = request.headers().get(header::ORIGIN).map(|origin| origin.as_bytes().to_vec());
+    let Some(origin) = origin.filter(|_| !safe) else {
+        return next.run(request).await;
+    };
+
+    let allowed = url::Url::parse(&config::get().rp_origin)
+        .is_ok_and(|rp_origin| rp_origin.origin().ascii_serialization().as_bytes() == origin.as_slice());
+    if !allowed {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Cross-origin request refused", "code": "CROSS_ORIGIN"})),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Middleware attribuant un `X-Request-Id` à chaque requête (celui du client s'il est valide,
+/// sinon un nouveau). L'identifiant est ajouté au span de la requête, renvoyé en en-tête
+/// et inclus dans les erreurs JSON pour retrouver la requête dans les logs.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid_request_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!("request", request_id = %id, method = %request.method(), path = %request.uri().path());
+    let response = next.run(request).instrument(span).await;
+    if response.status().is_server_error() {
+        tracing::warn!(request_id = %id, status = response.status().as_u16(), "Request failed");
+    }
+
+    let mut response = with_request_id(response, &id).await;
+    if let Ok(value) = header::HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Un identifiant fourni par le client est recopié dans les logs : on se limite à un format sûr
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// Ajoute `request_id` aux erreurs JSON de la forme `{"error": ...}`
+async fn with_request_id(response: Response, id: &str) -> Response {
+    if !(response.status().is_client_error() || response.status().is_server_error())
+        || !json_content_type(response.headers())
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut error)) if error.contains_key("error") => {
+            error.insert("request_id".to_string(), id.into());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            serde_json::to_vec(&error).unwrap_or_else(|_| bytes.to_vec())
+        }
+        _ => bytes.to_vec(),
+    };
+    Response::from_parts(parts, axum::body::Body::from(body))
+}
+
+/// Parcourt le document sans le désérialiser et refuse une imbrication trop profonde
+/// ou un trop grand nombre d'éléments
+fn check_json_shape(bytes: &[u8], max_depth: usize, max_elements: usize) -> Result<(), &'static str> {
+    let (mut depth, mut elements) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for &byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                elements += 1;
+                if depth > max_depth {
+                    return Err("JSON nesting is too deep");
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            b',' => elements += 1,
+            _ => {}
+        }
+        if elements > max_elements {
+            return Err("JSON document has too many elements");
+        }
+    }
+    Ok(())
+}
+
+/// Extracteur JSON dont les erreurs (type de contenu, syntaxe) sont renvoyées en JSON ;
+/// un corps sans `Content-Type: application/json` est refusé avec 415, un document trop
+/// imbriqué ou trop volumineux avec 400, avant toute désérialisation
+pub struct ApiJson<T>(pub T);
+
+#[async_trait::async_trait]
+impl<S, T> FromRequest<S> for ApiJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !json_content_type(req.headers()) {
+            return Err(json_rejection(MissingJsonContentType::default().into()));
+        }
+
+        let bytes = Bytes::from_request(req, state).await.map_err(IntoResponse::into_response)?;
+
+        let config = config::get();
+        check_json_shape(&bytes, config.max_json_depth, config.max_json_elements)
+            .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({"error": message}))).into_response())?;
+
+        let Json(value) = Json::<T>::from_bytes(&bytes).map_err(json_rejection)?;
+        Ok(ApiJson(value))
+    }
+}
+
+/// Extracteur JSON qui désérialise puis valide le contenu en une seule étape
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait::async_trait]
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let ApiJson(value) = ApiJson::<T>::from_request(req, state).await?;
+
+        value.validate().map_err(|e| {
+            (StatusCode::BAD_REQUEST, Json(json!({"error": e.errors()}))).into_response()
+        })?;
+
+        Ok(ValidatedJson(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::models::{LoginCompleteRequest, RegisterCompleteRequest};
+    use axum::body::{to_bytes, Body};
+    use uuid::Uuid;
+
+    async fn session_user(session: &Session) -> Result<String, StatusCode> {
+        let mut request = Request::builder().body(Body::empty()).unwrap();
+        request.extensions_mut().insert(session.clone());
+        let (mut parts, _) = request.into_parts();
+        SessionUser::from_request_parts(&mut parts, &())
+            .await
+            .map(|user| user.email)
+            .map_err(|(status, _)| status)
+    }
+
+    #[test]
+    fn test_response_format_from_accept() {
+        assert_eq!(ResponseFormat::from_accept("application/json"), ResponseFormat::Json);
+        assert_eq!(ResponseFormat::from_accept("text/html,application/xhtml+xml,*/*;q=0.8"), ResponseFormat::Html);
+        assert_eq!(ResponseFormat::from_accept("text/html;q=0.5, application/json"), ResponseFormat::Json);
+        assert_eq!(ResponseFormat::from_accept("*/*"), ResponseFormat::Html);
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let trusted: Vec<config::IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "192.0.2.1".parse().unwrap()];
+
+        // Pair non fiable : l'en-tête est ignoré
+        assert_eq!(forwarded_client(ip("203.0.113.7"), &["198.51.100.1"], &trusted), ip("203.0.113.7"));
+        // Aucun proxy configuré : l'en-tête est toujours ignoré
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["198.51.100.1"], &[]), ip("10.0.0.1"));
+
+        // Pair de confiance : la dernière adresse ajoutée est celle du client
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["198.51.100.1"], &trusted), ip("198.51.100.1"));
+        // Plusieurs sauts : on ignore les proxies de confiance et les valeurs forgées à gauche
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &["1.2.3.4, 198.51.100.1, 192.0.2.1"], &trusted),
+            ip("198.51.100.1")
+        );
+        // Plusieurs en-têtes : ils forment une seule chaîne
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &["1.2.3.4", "198.51.100.1, 10.2.0.1"], &trusted),
+            ip("198.51.100.1")
+        );
+        // Uniquement des proxies de confiance : l'adresse la plus à gauche
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["10.3.0.1, 10.2.0.1"], &trusted), ip("10.3.0.1"));
+        // Entrée illisible : le dernier proxy de confiance est retenu
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["1.2.3.4, garbage, 10.2.0.1"], &trusted), ip("10.2.0.1"));
+        // Pas d'en-tête : l'adresse du pair
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &[], &trusted), ip("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_honours_trusted_proxy_only() {
+        let extract = |peer: &str| {
+            let mut request = Request::builder()
+                .header("x-forwarded-for", "198.51.100.1")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::new(ip(peer), 443)));
+            let (mut parts, _) = request.into_parts();
+            async move { ClientIp::from_request_parts(&mut parts, &()).await.unwrap().0 }
+        };
+        let config = config::Config {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+
+        config::scope(config, async {
+            assert_eq!(extract("10.0.0.1").await, Some(ip("198.51.100.1")));
+            assert_eq!(extract("203.0.113.7").await, Some(ip("203.0.113.7")));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_bumping_generation_logs_out_sessions() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let session = Session::new(None);
+        start_session(&session, &email).unwrap();
+        assert_eq!(session_user(&session).await, Ok(email.clone()));
+
+        user::bump_session_generation(&email).unwrap();
+        assert_eq!(session_user(&session).await, Err(StatusCode::UNAUTHORIZED));
+
+        // Une nouvelle connexion reprend la génération courante
+        let fresh = Session::new(None);
+        start_session(&fresh, &email).unwrap();
+        assert_eq!(session_user(&fresh).await, Ok(email));
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_actions_require_recent_authentication() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let session = Session::new(None);
+        start_session(&session, &email).unwrap();
+
+        let recently_authenticated = || async {
+            let mut request = Request::builder().body(Body::empty()).unwrap();
+            request.extensions_mut().insert(session.clone());
+            let (mut parts, _) = request.into_parts();
+            RecentlyAuthenticatedUser::from_request_parts(&mut parts, &())
+                .await
+                .map(|user| user.email)
+                .map_err(|response| response.status())
+        };
+
+        // Connexion récente
+        assert_eq!(recently_authenticated().await, Ok(email.clone()));
+
+        // Connexion ancienne : une vérification de passkey est demandée
+        let long_ago = now() - config::get().reauth_window_secs - 1;
+        session.insert("authenticated_at", long_ago).unwrap();
+        assert_eq!(recently_authenticated().await, Err(StatusCode::FORBIDDEN));
+
+        mark_reauthenticated(&session).unwrap();
+        assert_eq!(recently_authenticated().await, Ok(email));
+    }
+
+    #[tokio::test]
+    async fn test_session_lifetime_is_capped() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let session = Session::new(None);
+        start_session(&session, &email).unwrap();
+        assert_eq!(session_user(&session).await, Ok(email.clone()));
+
+        // Session ouverte au-delà de la durée de vie maximale, même si elle est restée active
+        let config = config::Config {
+            session_max_lifetime_secs: 60,
+            ..Default::default()
+        };
+        session.insert("authenticated_at", now() - 61).unwrap();
+        assert_eq!(config::scope(config, session_user(&session)).await, Err(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn test_session_expiry() {
+        let config = config::Config {
+            session_idle_secs: 600,
+            remember_me_secs: 7200,
+            session_max_lifetime_secs: 3600,
+            ..Default::default()
+        };
+        let (short, remembered) = config::scope(config, async { (session_expiry(false, 1000), session_expiry(true, 1000)) }).await;
+        assert_eq!(short, Expiry::OnInactivity(time::Duration::seconds(600)));
+        // Plafonnée à la durée de vie maximale
+        assert_eq!(
+            remembered,
+            Expiry::AtDateTime(time::OffsetDateTime::from_unix_timestamp(1000 + 3600).unwrap())
+        );
+    }
+
+    async fn extract<T: DeserializeOwned + Validate>(body: serde_json::Value) -> Result<T, (StatusCode, serde_json::Value)> {
+        let request = Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        match ValidatedJson::<T>::from_request(request, &()).await {
+            Ok(ValidatedJson(value)) => Ok(value),
+            Err(response) => {
+                let status = response.status();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                Err((status, serde_json::from_slice(&bytes).unwrap()))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validated_json_accepts_valid_payload() {
+        let request = extract::<RegisterCompleteRequest>(json!({
+            "email": "jean@example.com",
+            "first_name": "Jean",
+            "last_name": "Dupont",
+            "state_id": "abc",
+            "response": {},
+        }))
+        .await
+        .unwrap();
+
+        assert_eq!(request.registration.email, "jean@example.com");
+        assert!(!request.reset_mode);
+        assert!(request.invite_code.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_validated_json_missing_field() {
+        let (status, body) = extract::<LoginCompleteRequest>(json!({ "response": {} }))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("state_id"));
+    }
+
+    #[test]
+    fn test_check_json_shape() {
+        assert!(check_json_shape(br#"{"a": [1, 2, {"b": []}]}"#, 4, 10).is_ok());
+        assert!(check_json_shape(br#"{"a": [1, 2, {"b": []}]}"#, 3, 10).is_err());
+        assert!(check_json_shape(b"[1, 2, 3, 4]", 1, 3).is_err());
+
+        // Les crochets et virgules des chaînes ne comptent pas
+        assert!(check_json_shape(br#"{"a": "[[[,,,\"]]]"}"#, 1, 1).is_ok());
+    }
+
+    #[test]
+    fn test_json_content_type() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, value.parse().unwrap());
+            headers
+        };
+        assert!(json_content_type(&headers("application/json")));
+        assert!(json_content_type(&headers("Application/JSON; charset=utf-8")));
+        assert!(json_content_type(&headers("application/cbor+json")));
+        assert!(!json_content_type(&headers("text/plain")));
+        assert!(!json_content_type(&headers("application/jsonp")));
+        assert!(!json_content_type(&HeaderMap::new()));
+    }
+
+    /// Corps de la réponse d'une route JSON passant par `pretty_json`
+    async fn json_body(pretty: bool) -> String {
+        use tower::ServiceExt;
+
+        let router = axum::Router::new()
+            .route("/", axum::routing::get(|| async { Json(json!({"b": [1, 2], "a": {"c": true}})) }))
+            .layer(axum::middleware::from_fn(pretty_json));
+        let config = config::Config {
+            pretty_json: pretty,
+            ..Default::default()
+        };
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = config::scope(config, router.oneshot(request)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pretty_json_toggle() {
+        let compact = json_body(false).await;
+        assert_eq!(compact, r#"{"a":{"c":true},"b":[1,2]}"#);
+
+        let pretty = json_body(true).await;
+        assert!(pretty.contains("\n  \"a\": {\n    \"c\": true"));
+        // Mêmes données, dans le même ordre
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+        assert!(pretty.find("\"a\"") < pretty.find("\"b\""));
+    }
+
+    #[tokio::test]
+    async fn test_deeply_nested_payload_is_rejected() {
+        let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        let body: serde_json::Value = json!({
+            "email": "jean@example.com",
+            "first_name": "Jean",
+            "last_name": "Dupont",
+            "state_id": "abc",
+            "response": "RESPONSE",
+        });
+        let body = body.to_string().replace("\"RESPONSE\"", &nested);
+
+        let request = Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = match ApiJson::<RegisterCompleteRequest>::from_request(request, &()).await {
+            Ok(_) => panic!("nested payload accepted"),
+            Err(response) => response,
+        };
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "JSON nesting is too deep");
+
+        // La limite est configurable
+        let config = config::Config {
+            max_json_depth: 2,
+            ..Default::default()
+        };
+        let result = config::scope(config, extract::<RegisterCompleteRequest>(json!({
+            "email": "jean@example.com",
+            "first_name": "Jean",
+            "last_name": "Dupont",
+            "state_id": "abc",
+            "response": { "nested": { "too": "deep" } },
+        })))
+        .await;
+        assert_eq!(result.err().unwrap().0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_validated_json_validation_failure() {
+        let (status, body) = extract::<RegisterCompleteRequest>(json!({
+            "email": "invalid-email",
+            "first_name": "Jean123",
+            "last_name": "Dupont",
+            "state_id": "abc",
+            "response": {},
+        }))
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]["email"].is_array());
+        assert!(body["error"]["first_name"].is_array());
+        assert!(body["error"].get("last_name").is_none());
+    }
+}
@@ -1,11 +1,143 @@
 //! Définitions des structures pour les interactions avec l'API.
 //! Contient les structures pour l'enregistrement, l'authentification et la récupération.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationErrors};
+use webauthn_rs::prelude::CredentialID;
+use uuid::Uuid;
+use crate::utils::i18n::Locale;
+use crate::utils::input::{validate_description, UserRegistration};
 
 /// Structure pour représenter les réponses aux défis WebAuthn
 #[derive(Serialize)]
-pub struct WebAuthnChallenge {
-    pub challenge: serde_json::Value, // Données du défi
+pub struct WebAuthnChallenge<T = serde_json::Value> {
+    #[serde(rename = "publicKey")]
+    pub challenge: T, // Données du défi
     pub state_id: String,            // Identifiant d'état du défi
-}
\ No newline at end of file
+}
+/// Requête de fin d'enregistrement WebAuthn
+#[derive(Deserialize)]
+pub struct RegisterCompleteRequest {
+    #[serde(flatten)]
+    pub registration: UserRegistration, // Email, prénom et nom
+    pub state_id: String,               // Identifiant d'état retourné au début
+    pub response: serde_json::Value,    // Réponse du navigateur
+    #[serde(default)]
+    pub reset_mode: bool,
+    #[serde(default)]
+    pub invite_code: Option<String>,
+    #[serde(default)]
+    pub recovery_token: Option<String>, // Exigé en mode reset
+}
+
+// Les règles de validation restent centralisées dans `UserRegistration`
+impl Validate for RegisterCompleteRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.registration.validate_profile()
+    }
+}
+
+/// Requête de fin d'ajout d'une passkey à un compte connecté
+#[derive(Deserialize)]
+pub struct PasskeyAddRequest {
+    pub state_id: String,            // Identifiant d'état retourné au début
+    pub response: serde_json::Value, // Réponse du navigateur
+}
+
+/// Requête de début de vérification d'une passkey précise du compte connecté
+#[derive(Deserialize)]
+pub struct PasskeyVerifyRequest {
+    pub credential_id: CredentialID, // Identifiant de la passkey, en base64url
+}
+
+/// Signalement d'un post par un utilisateur connecté
+#[derive(Deserialize, Validate)]
+pub struct FlagRequest {
+    pub post_id: Uuid,
+    #[validate(length(min = 1, max = 200))]
+    #[validate(custom(function = "validate_description"))]
+    pub reason: String,
+}
+
+/// Décision d'un administrateur sur un post signalé
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagAction {
+    Hide,   // Masquer le post
+    Delete, // Supprimer le post et son image
+    Dismiss, // Rejeter les signalements
+}
+
+#[derive(Deserialize)]
+pub struct FlagResolution {
+    pub post_id: Uuid,
+    pub action: FlagAction,
+}
+
+/// Préférences modifiables du profil
+#[derive(Deserialize)]
+pub struct ProfileUpdate {
+    #[serde(default)]
+    pub locale: Option<Locale>,
+}
+
+/// Modification partielle des préférences du compte : les champs absents sont inchangés,
+/// `locale: null` revient à la langue du navigateur
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<Locale>>,
+    #[serde(default)]
+    pub login_notifications: Option<bool>,
+    #[serde(default)]
+    pub recovery_notifications: Option<bool>,
+    #[serde(default)]
+    pub remember_me_default: Option<bool>,
+}
+
+/// Distingue un champ présent (même `null`) d'un champ absent
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Mode de remise d'un lien de récupération émis par un administrateur
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryDelivery {
+    /// Envoyé à l'adresse du compte
+    #[default]
+    Email,
+    /// Retourné à l'administrateur, qui le transmet par un autre canal
+    OutOfBand,
+}
+
+/// Émission d'un lien de récupération pour un autre compte
+#[derive(Deserialize)]
+pub struct AdminRecoveryRequest {
+    pub email: String,
+    #[serde(default)]
+    pub delivery: RecoveryDelivery,
+}
+
+/// Activation ou désactivation du mode maintenance
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+}
+
+/// Requête de fin d'authentification WebAuthn
+#[derive(Deserialize, Validate)]
+pub struct LoginCompleteRequest {
+    #[validate(length(min = 1))]
+    pub state_id: String,            // Identifiant d'état retourné au début
+    pub response: serde_json::Value, // Réponse du navigateur
+    #[serde(default)]
+    pub remember_me: Option<bool>,   // Session longue sur cet appareil ; à défaut, la préférence du compte
+    #[serde(default)]
+    pub next: Option<String>,        // Page à ouvrir ensuite, chemin relatif au site ; à défaut /home
+}
@@ -0,0 +1,127 @@
+//! Contextes des templates Handlebars, un type par page.
+//! Une clé mal nommée côté Rust ne compile plus, au lieu de produire une page vide.
+
+use serde::Serialize;
+use crate::backend::handlers_auth::Post;
+use crate::utils::i18n::Locale;
+
+/// Page d'accueil (`index.hbs`)
+#[derive(Serialize)]
+pub struct IndexPage {
+    pub logged_in: bool,
+    pub lang: &'static str,
+    pub t: serde_json::Value,
+}
+
+impl IndexPage {
+    pub fn new(logged_in: bool, locale: Locale) -> Self {
+        IndexPage {
+            logged_in,
+            lang: locale.code(),
+            t: locale.page_texts(),
+        }
+    }
+}
+
+/// Page principale avec les posts (`home.hbs`)
+#[derive(Serialize)]
+pub struct HomePage {
+    pub user: String,
+    pub posts: Vec<Post>,
+    pub lang: &'static str,
+    pub t: serde_json::Value,
+}
+
+/// Page de connexion (`login.hbs`)
+#[derive(Default, Serialize)]
+pub struct LoginPage {
+    pub validated: bool,
+    pub magic_link: bool,
+    pub error_message: Option<&'static str>,
+}
+
+/// Page d'inscription (`register.hbs`)
+#[derive(Default, Serialize)]
+pub struct RegisterPage {
+    pub success_message: Option<&'static str>,
+    pub error_message: Option<&'static str>,
+    pub required: RequiredFields,
+}
+
+/// Champs du formulaire d'inscription marqués obligatoires
+#[derive(Default, Serialize)]
+pub struct RequiredFields {
+    pub first_name: bool,
+    pub last_name: bool,
+}
+
+/// Page de récupération de compte (`recover.hbs`)
+#[derive(Default, Serialize)]
+pub struct RecoverPage {
+    pub message: Option<&'static str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render<T: Serialize>(name: &str, data: &T) -> String {
+        crate::HBS.render(name, data).unwrap()
+    }
+
+    #[test]
+    fn test_index_page() {
+        let page = render("index", &IndexPage::new(true, Locale::Fr));
+        assert!(page.contains(r#"<html lang="fr">"#));
+        assert!(page.contains("Bienvenue"));
+        assert!(page.contains(r#"href="/logout""#));
+        assert!(!page.contains(r#"href="/login""#));
+
+        let page = render("index", &IndexPage::new(false, Locale::En));
+        assert!(page.contains(r#"href="/login""#));
+        assert!(!page.contains(r#"href="/logout""#));
+    }
+
+    #[test]
+    fn test_home_page() {
+        let page = render("home", &HomePage {
+            user: "Jean".to_string(),
+            posts: Vec::new(),
+            lang: Locale::En.code(),
+            t: Locale::En.page_texts(),
+        });
+        assert!(page.contains("Create a Post"));
+        assert!(page.contains("Add a passkey"));
+    }
+
+    #[test]
+    fn test_login_page() {
+        let page = render("login", &LoginPage {
+            validated: true,
+            error_message: Some("Invalid login link."),
+            ..Default::default()
+        });
+        assert!(page.contains("Your account has been validated"));
+        assert!(page.contains("Invalid login link."));
+        assert!(!render("login", &LoginPage::default()).contains("alert-danger"));
+    }
+
+    #[test]
+    fn test_register_page() {
+        let page = render("register", &RegisterPage {
+            success_message: Some("Account recovery successful."),
+            required: RequiredFields { first_name: true, last_name: false },
+            ..Default::default()
+        });
+        assert!(page.contains("Account recovery successful."));
+        assert!(page.contains("First Name</label>"));
+        assert!(page.contains("Last Name (optional)</label>"));
+    }
+
+    #[test]
+    fn test_recover_page() {
+        let page = render("recover", &RecoverPage { message: Some("Recovery email sent.") });
+        assert!(page.contains("Recovery email sent."));
+        assert!(!render("recover", &RecoverPage::default()).contains("Recovery email sent."));
+    }
+}
@@ -1,37 +1,55 @@
 //! Configuration des routes pour l'application.
 //! Définit les routes accessibles avec ou sans authentification et configure les middlewares.
 
-use axum::{Router, routing::{get, post}, BoxError};
+use axum::{Router, routing::{delete, get, post}, BoxError};
+use axum::extract::DefaultBodyLimit;
 use axum::error_handling::HandleErrorLayer;
 use http::StatusCode;
 use tower_sessions::{SessionManagerLayer, MemoryStore};
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower::{ServiceBuilder};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::LoadShedLayer;
 use tower_http::services::{ServeDir};
 
 use crate::backend::handlers_unauth::{
     register_begin, register_complete, login_begin, login_complete,
     index, login_page, register_page, validate_account, logout,
-    recover_page, recover_account, reset_account,
+    recover_page, recover_account, reset_account, pow_challenge, validate_registration,
+    magic_link_request, magic_link_login, health, debug_session,
 };
-use crate::backend::handlers_auth::{create_post, home, like_post};
-use crate::consts;
+use crate::backend::handlers_auth::{
+    create_post, delete_post, flag_post, home, like_post, list_posts, passkey_add_begin, passkey_add_complete,
+    list_passkeys, passkey_verify_begin, passkey_verify_complete, upload_image, export_data,
+    update_profile, delete_passkey, logout_all, list_uploads, settings, update_settings,
+};
+use crate::backend::handlers_admin::{
+    create_invite, email_available, issue_recovery, list_flags, metrics, resolve_flag, send_test_email, set_maintenance,
+};
+use crate::backend::middlewares::{maintenance, pretty_json, request_id, webauthn_origin, IpRateLimit};
+use axum::middleware::FromExtractorLayer;
+use crate::backend::session_store::{AppSessionStore, FileStore};
+use crate::config::{self, SessionBackend};
+use crate::{consts, database};
 
 /// Initialisation du routeur principal et des middlewares
 pub fn get_router() -> Router {
-    // Configuration CORS pour permettre les requêtes de n'importe quelle origine (en mode debug uniquement)
-    let router = if cfg!(debug_assertions) {
-        let cors = CorsLayer::new()
-            .allow_methods(tower_http::cors::AllowMethods::any())
-            .allow_origin(Any);
-        Router::new().layer(cors)
-    } else {
-        Router::new()
+    // Configuration du stockage des sessions
+    let store = match config::get().session_backend {
+        SessionBackend::Memory => AppSessionStore::Memory(MemoryStore::default()),
+        SessionBackend::File => AppSessionStore::File(
+            FileStore::open(database::resolve(consts::SESSIONS_DB_PATH))
+                .expect("Failed to open session store"),
+        ),
     };
-
-    // Configuration des sessions en mémoire
-    let store = MemoryStore::default(); // Initialisation du MemoryStore
-    let session_manager = SessionManagerLayer::new(store).with_http_only(true);
+    // Le navigateur n'accepte un cookie `__Host-` que `Secure`, sur `/` et sans `Domain`
+    let config = config::get();
+    let host_only = config.session_cookie_name.starts_with("__Host-");
+    let session_manager = SessionManagerLayer::new(store)
+        .with_name(&config.session_cookie_name)
+        .with_path(if host_only { "/".to_string() } else { config.session_cookie_path.clone() })
+        .with_http_only(true)
+        .with_secure(config.secure_cookies || host_only);
 
     let service = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|_e: BoxError| async move {
@@ -39,24 +57,88 @@ pub fn get_router() -> Router {
         }))
         .layer(session_manager);
 
-    router
+    let router = Router::new()
+        .merge(webauthn_routes())
         .merge(unauth_routes())
         .merge(auth_routes())
+        .merge(admin_routes());
+
+    // Connexion simulée, compilée uniquement avec la feature `test-auth`
+    #[cfg(feature = "test-auth")]
+    let router = router.route(
+        "/test/login",
+        post(crate::backend::handlers_test_auth::test_login),
+    );
+
+    // Le health check reste disponible quand le serveur est saturé ou en maintenance
+    health_routes()
+        .merge(limit_concurrency(router.layer(axum::middleware::from_fn(maintenance))))
+        .layer(axum::middleware::from_fn(request_id))
+        .layer(axum::middleware::from_fn(pretty_json))
         .layer(service)
 }
 
+/// Routes exemptées de la limite de requêtes simultanées
+fn health_routes() -> Router {
+    Router::new().route("/health", get(health)) // Le serveur répond
+}
+
+/// Limite le nombre de requêtes traitées en même temps par les routes de `router`.
+/// La limite est partagée entre toutes les routes ; les requêtes en trop reçoivent 503 au lieu d'attendre.
+fn limit_concurrency(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_e: BoxError| async move {
+                StatusCode::SERVICE_UNAVAILABLE
+            }))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(config::get().max_concurrent_requests.max(1))),
+    )
+}
+
 /// Routes accessibles sans authentification
 fn unauth_routes() -> Router {
     Router::new()
         .route("/", get(index)) // Page d'accueil
         .route("/validate/:token", get(validate_account)) // Validation d'un compte
-        .route("/register", get(register_page).post(register_begin)) // Début de l'enregistrement WebAuthn
-        .route("/register/complete", post(register_complete)) // Fin de l'enregistrement WebAuthn
-        .route("/login", get(login_page).post(login_begin)) // Page de connexion
-        .route("/login/complete", post(login_complete)) // Fin de l'authentification WebAuthn
+        .route("/register/pow", get(pow_challenge).route_layer(rate_limited())) // Challenge de preuve de travail
+        .route("/login/magic", post(magic_link_request).route_layer(rate_limited())) // Envoi d'un lien de connexion (si activé)
+        .route("/login/magic/:token", get(magic_link_login)) // Connexion par lien
         .route("/logout", get(logout)) // Déconnexion
-        .route("/recover", get(recover_page).post(recover_account)) // Page et handler de récupération
+        .route("/recover", get(recover_page).merge(post(recover_account).route_layer(rate_limited()))) // Page et handler de récupération
         .route("/recover/:token", get(reset_account)) // Lien pour la récupération de compte
+        .route("/api/validate/registration", post(validate_registration).route_layer(rate_limited())) // Validation à blanc des champs d'inscription
+        .route("/debug/session", get(debug_session)) // Contenu de la session (mode développement uniquement)
+}
+
+/// Cérémonies WebAuthn d'enregistrement et de connexion, liées au cookie de session et à l'origine
+/// du relying party. Avec CORS activé, seule cette origine est autorisée, cookies compris.
+fn webauthn_routes() -> Router {
+    let router = Router::new()
+        .route("/register", get(register_page).merge(post(register_begin).route_layer(rate_limited()))) // Début de l'enregistrement WebAuthn
+        .route("/register/complete", post(register_complete)) // Fin de l'enregistrement WebAuthn
+        .route("/login", get(login_page).merge(post(login_begin).route_layer(rate_limited()))) // Page de connexion
+        .route("/login/complete", post(login_complete)) // Fin de l'authentification WebAuthn
+        .layer(axum::middleware::from_fn(webauthn_origin));
+
+    let config = config::get();
+    if !config.cors_enabled {
+        return router;
+    }
+    // Une origine illisible n'autorise personne ; elle est de toute façon refusée au démarrage
+    let origin = AllowOrigin::list(http::HeaderValue::from_str(config.rp_origin.trim_end_matches('/')));
+    router.layer(
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_credentials(true)
+            .allow_methods([http::Method::GET, http::Method::POST])
+            .allow_headers([http::header::CONTENT_TYPE, http::header::ACCEPT]),
+    )
+}
+
+/// Middleware de limitation par IP des requêtes coûteuses ou sensibles à la force brute
+fn rate_limited() -> FromExtractorLayer<IpRateLimit, ()> {
+    axum::middleware::from_extractor::<IpRateLimit>()
 }
 
 /// Routes nécessitant une authentification
@@ -64,7 +146,418 @@ fn auth_routes() -> Router {
     Router::new()
         .route("/home", get(home)) // Page principale
         .route("/post/like", post(like_post)) // Ajout d'un like à un post
-        .route("/post/create", post(create_post)) // Ajout d'un post
-        .nest_service("/data/uploads", ServeDir::new(consts::UPLOADS_DIR)) // Serveur de fichiers statiques
-        .layer(axum::middleware::from_extractor::<crate::backend::middlewares::SessionUser>()) // Middleware pour vérifier l'utilisateur connecté
+        .route("/post/create", post(create_post).layer(DefaultBodyLimit::max(consts::MAX_UPLOAD_BODY_SIZE))) // Ajout d'un post
+        .route("/post/delete", post(delete_post)) // Suppression d'un post et de son image
+        .route("/post/flag", post(flag_post)) // Signalement d'un post inapproprié
+        .route("/upload", post(upload_image).layer(DefaultBodyLimit::max(consts::MAX_UPLOAD_BODY_SIZE))) // Envoi d'une image à associer à un post
+        .route("/api/posts", get(list_posts)) // Liste paginée des posts en JSON
+        .route("/api/me/uploads", get(list_uploads)) // Liste paginée des uploads du compte
+        .route("/logout-all", post(logout_all)) // Déconnexion de toutes les sessions du compte
+        .route("/passkeys", get(list_passkeys)) // Liste des passkeys du compte
+        .route("/account/profile", post(update_profile)) // Préférences du profil (langue)
+        .route("/api/me/settings", get(settings).put(update_settings)) // Préférences du compte
+        .route("/account/export", get(export_data)) // Export des données du compte (réauthentification récente exigée)
+        .route("/passkeys/begin", post(passkey_add_begin)) // Début de l'ajout d'une passkey
+        .route("/passkeys/complete", post(passkey_add_complete)) // Fin de l'ajout d'une passkey
+        .route("/passkeys/:credential_id", delete(delete_passkey)) // Suppression d'une passkey (réauthentification récente exigée)
+        .route("/passkeys/verify/begin", post(passkey_verify_begin)) // Début de la vérification d'une passkey précise
+        .route("/passkeys/verify/complete", post(passkey_verify_complete)) // Fin de la vérification d'une passkey précise
+        .nest_service(consts::UPLOADS_URL, ServeDir::new(database::resolve(consts::UPLOADS_DIR))) // Serveur de fichiers statiques
+        .route_layer(axum::middleware::from_extractor::<crate::backend::middlewares::SessionUser>()) // Middleware pour vérifier l'utilisateur connecté
+}
+
+/// Routes réservées aux administrateurs
+fn admin_routes() -> Router {
+    Router::new()
+        .route("/admin/invites", post(create_invite)) // Génération d'un code d'invitation
+        .route("/admin/email-available", get(email_available).route_layer(rate_limited())) // Disponibilité d'un email
+        .route("/admin/flags", get(list_flags)) // Posts signalés
+        .route("/admin/flags/resolve", post(resolve_flag)) // Traitement des signalements d'un post
+        .route("/admin/maintenance", post(set_maintenance)) // Activation du mode maintenance
+        .route("/admin/metrics", get(metrics)) // Taille des stockages de challenges
+        .route("/admin/test-email", post(send_test_email).route_layer(rate_limited())) // Vérification de l'envoi des emails
+        .route("/admin/recovery", post(issue_recovery)) // Lien de récupération pour un autre compte (réauthentification récente exigée)
+        .route_layer(axum::middleware::from_extractor::<crate::backend::middlewares::AdminUser>()) // Middleware pour vérifier le rôle administrateur
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http::Request;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_requests_beyond_limit_are_shed() {
+        use std::sync::Arc;
+        use tokio::sync::Notify;
+
+        // Route qui reste occupée jusqu'à ce que le test la libère
+        let release = Arc::new(Notify::new());
+        let held = release.clone();
+        let slow = Router::new().route(
+            "/slow",
+            get(move || async move {
+                held.notified().await;
+                StatusCode::OK
+            }),
+        );
+        let config = config::Config {
+            max_concurrent_requests: 1,
+            ..Default::default()
+        };
+        let app = config::scope(config, async { health_routes().merge(limit_concurrency(slow)) }).await;
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let first = tokio::spawn(app.clone().oneshot(get("/slow")));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let response = app.clone().oneshot(get("/slow")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = app.clone().oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        let response = app.oneshot(get("/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_route_is_not_found() {
+        let request = Request::builder().uri("/does-not-exist").body(Body::empty()).unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_propagated() {
+        let response = get_router()
+            .oneshot(Request::builder().uri("/health").header("X-Request-Id", "abc-123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+        // Absent ou invalide : un nouvel identifiant est généré
+        for request in [
+            Request::builder().uri("/health").body(Body::empty()).unwrap(),
+            Request::builder().uri("/health").header("X-Request-Id", "<script> x").body(Body::empty()).unwrap(),
+        ] {
+            let response = get_router().oneshot(request).await.unwrap();
+            let id = response.headers()["x-request-id"].to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(id).is_ok(), "{id}");
+        }
+
+        // L'identifiant figure aussi dans les erreurs JSON
+        let request = Request::builder()
+            .method("POST")
+            .uri("/login")
+            .header("Content-Type", "application/json")
+            .header("X-Request-Id", "failed-login")
+            .body(Body::from("{"))
+            .unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-request-id"], "failed-login");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["request_id"], "failed-login");
+        assert!(body["error"].is_string());
+    }
+
+    /// Crée un administrateur et un utilisateur ordinaire ; retourne leurs emails
+    fn admin_and_member() -> (String, String) {
+        use crate::database::user::{self, Role};
+
+        let admin = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let member = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        for email in [&admin, &member] {
+            user::create(email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        }
+        user::set_role(&admin, Role::Admin).unwrap();
+        (admin, member)
+    }
+
+    /// Appelle les routes d'administration avec une session authentifiée pour `email`,
+    /// éventuellement avec un corps JSON et une date de dernière authentification
+    async fn admin_request(
+        email: &str,
+        method: http::Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+        authenticated_at: Option<u64>,
+    ) -> (StatusCode, serde_json::Value) {
+        let session = tower_sessions::Session::new(None);
+        crate::backend::middlewares::start_session(&session, email).unwrap();
+        if let Some(authenticated_at) = authenticated_at {
+            session.insert("authenticated_at", authenticated_at).unwrap();
+        }
+        let builder = Request::builder().method(method).uri(uri);
+        let mut request = match body {
+            Some(body) => builder.header("Content-Type", "application/json").body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        }
+        .unwrap();
+        request.extensions_mut().insert(session);
+
+        let response = admin_routes().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_email_available_admin_only() {
+        let (admin, member) = admin_and_member();
+
+        let uri = format!("/admin/email-available?email={}", member);
+        let (status, body) = admin_request(&admin, http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["available"], serde_json::Value::Bool(false));
+
+        let (status, body) = admin_request(&admin, http::Method::GET, "/admin/email-available?email=free%40example.com", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["available"], serde_json::Value::Bool(true));
+
+        let (status, _) = admin_request(&admin, http::Method::GET, "/admin/email-available?email=not-an-email", None, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = admin_request(&member, http::Method::GET, &uri, None, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = admin_request(&member, http::Method::GET, "/admin/flags", None, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = admin_request(&admin, http::Method::GET, "/admin/flags", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.is_array());
+    }
+
+    #[tokio::test]
+    async fn test_admin_recovery_requires_fresh_admin() {
+        use crate::database::token::{self, TokenKind};
+        use crate::database::email;
+        use serde_json::json;
+
+        let (admin, member) = admin_and_member();
+        async fn recovery(caller: &str, body: serde_json::Value, authenticated_at: Option<u64>) -> (StatusCode, serde_json::Value) {
+            admin_request(caller, http::Method::POST, "/admin/recovery", Some(body), authenticated_at).await
+        }
+
+        // Lien retourné pour une remise hors bande, utilisable par le titulaire du compte
+        let (status, body) = recovery(&admin, json!({ "email": member, "delivery": "out_of_band" }), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let recovery_token = body["link"].as_str().unwrap().rsplit('/').next().unwrap().to_string();
+        assert_eq!(token::peek(&recovery_token, TokenKind::Recovery).unwrap(), member);
+
+        // Par défaut, le lien est envoyé par email
+        let (status, _) = recovery(&admin, json!({ "email": member }), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(email::sent_to(&member).iter().any(|mail| mail.body.contains("/recover/")));
+
+        let (status, _) = recovery(&admin, json!({ "email": "missing@example.com" }), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Un utilisateur ordinaire ne peut pas émettre de lien
+        let issued = token::issued_to(&admin).len();
+        let (status, _) = recovery(&member, json!({ "email": admin, "delivery": "out_of_band" }), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(token::issued_to(&admin).len(), issued);
+
+        // Une connexion ancienne doit être confirmée avec une passkey
+        let long_ago = crate::database::now() - config::get().reauth_window_secs - 1;
+        let (status, body) = recovery(&admin, json!({ "email": member }), Some(long_ago)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "REAUTH_REQUIRED");
+    }
+
+    #[tokio::test]
+    async fn test_test_email_is_sent_through_send_mail() {
+        use crate::database::email;
+
+        let (admin, member) = admin_and_member();
+        async fn send(caller: &str, to: &str) -> (StatusCode, serde_json::Value) {
+            let body = serde_json::json!({ "email": to });
+            admin_request(caller, http::Method::POST, "/admin/test-email", Some(body), None).await
+        }
+
+        let recipient = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let (status, body) = send(&admin, &recipient).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["sent"], true);
+        let sent = email::sent_to(&recipient);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "Test email");
+
+        let (status, _) = send(&admin, "not-an-email").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send(&member, &recipient).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(email::sent_to(&recipient).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cross_origin_ceremony_is_blocked() {
+        let config = config::Config {
+            cors_enabled: true,
+            ..Default::default()
+        };
+        let rp_origin = config.rp_origin.clone();
+        let app = config::scope(config.clone(), async { webauthn_routes() }).await;
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method("OPTIONS")
+                .uri("/login")
+                .header("Origin", origin)
+                .header("Access-Control-Request-Method", "POST")
+                .header("Access-Control-Request-Headers", "content-type")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let begin = |origin: &str| {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/login")
+                .header("Origin", origin)
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({ "email": "nobody@example.com" }).to_string()))
+                .unwrap();
+            request.extensions_mut().insert(tower_sessions::Session::new(None));
+            request
+        };
+
+        // Une autre origine n'obtient pas d'autorisation CORS et la requête est refusée par le serveur
+        config::scope(config.clone(), async {
+            let response = app.clone().oneshot(preflight("https://evil.example")).await.unwrap();
+            assert!(!response.headers().contains_key("access-control-allow-origin"));
+            let response = app.clone().oneshot(begin("https://evil.example")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["code"], "CROSS_ORIGIN");
+
+            // L'origine du relying party est autorisée, cookies compris
+            let response = app.clone().oneshot(preflight(&rp_origin)).await.unwrap();
+            assert_eq!(response.headers()["access-control-allow-origin"], rp_origin.as_str());
+            assert_eq!(response.headers()["access-control-allow-credentials"], "true");
+            let response = app.clone().oneshot(begin(&rp_origin)).await.unwrap();
+            assert_ne!(response.status(), StatusCode::FORBIDDEN);
+        })
+        .await;
+
+        // Sans CORS, même l'origine du relying party ne reçoit pas d'en-têtes CORS
+        let app = webauthn_routes();
+        let response = app.oneshot(preflight(&rp_origin)).await.unwrap();
+        assert!(!response.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let (admin, member) = admin_and_member();
+
+        let app = health_routes()
+            .merge(auth_routes().merge(admin_routes()).layer(axum::middleware::from_fn(maintenance)));
+        let request = |email: Option<&str>, uri: &str, accept: &str| {
+            let session = tower_sessions::Session::new(None);
+            if let Some(email) = email {
+                crate::backend::middlewares::start_session(&session, email).unwrap();
+            }
+            let mut request = Request::builder().uri(uri).header("Accept", accept).body(Body::empty()).unwrap();
+            request.extensions_mut().insert(session);
+            request
+        };
+
+        // Hors maintenance, rien ne change
+        let response = app.clone().oneshot(request(Some(&member), "/api/posts", "text/html")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = config::Config {
+            maintenance_mode: true,
+            ..Default::default()
+        };
+        config::scope(config, async {
+            let response = app.clone().oneshot(request(Some(&member), "/api/posts", "text/html")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+
+            let response = app.clone().oneshot(request(None, "/api/posts", "application/json")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["code"], "MAINTENANCE");
+
+            // Les administrateurs et le health check passent
+            let response = app.clone().oneshot(request(Some(&admin), "/api/posts", "text/html")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = app.clone().oneshot(request(Some(&admin), "/admin/flags", "application/json")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = app.clone().oneshot(request(None, "/health", "application/json")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_email_available_not_public() {
+        let request = Request::builder()
+            .uri("/admin/email-available?email=jean%40example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_burst_from_one_ip_is_throttled() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let config = crate::config::Config {
+            rate_limit_per_minute: 3,
+            ..Default::default()
+        };
+        let addr = SocketAddr::from(([198, 51, 100, 23], 4242));
+        let statuses = crate::config::scope(config, async {
+            let router = get_router();
+            let mut statuses = Vec::new();
+            for _ in 0..5 {
+                let mut request = Request::builder()
+                    .method("POST")
+                    .uri("/login")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"email":"nobody@example.com"}"#))
+                    .unwrap();
+                request.extensions_mut().insert(ConnectInfo(addr));
+                let response = router.clone().oneshot(request).await.unwrap();
+                statuses.push((response.status(), response.headers().get(http::header::RETRY_AFTER).cloned()));
+            }
+            statuses
+        })
+        .await;
+
+        assert!(statuses[..3].iter().all(|(status, _)| *status == StatusCode::BAD_REQUEST));
+        for (status, retry_after) in &statuses[3..] {
+            assert_eq!(*status, StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(retry_after.as_ref().unwrap(), "20");
+        }
+
+        // Les pages restent accessibles
+        let mut request = Request::builder().uri("/login").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        assert_eq!(get_router().oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[cfg(not(feature = "test-auth"))]
+    #[tokio::test]
+    async fn test_stub_login_absent_without_feature() {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/test/login")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"email":"jean@example.com","secret":"s3cret"}"#))
+            .unwrap();
+
+        let response = get_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
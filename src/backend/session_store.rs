@@ -0,0 +1,160 @@
+//! Stockage des sessions : en mémoire pour le développement, ou dans un fichier YAML
+//! du dossier de données pour survivre aux redémarrages.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tower_sessions::session::{Id, Session};
+use tower_sessions::{MemoryStore, SessionStore};
+use crate::database::{now, read_yaml, save, LoadError};
+
+/// Backend de sessions choisi via la configuration
+#[derive(Clone, Debug)]
+pub enum AppSessionStore {
+    Memory(MemoryStore),
+    File(FileStore),
+}
+
+#[async_trait::async_trait]
+impl SessionStore for AppSessionStore {
+    type Error = std::io::Error;
+
+    async fn save(&self, session: &Session) -> Result<(), Self::Error> {
+        match self {
+            AppSessionStore::Memory(store) => store.save(session).await.map_err(|never| match never {}),
+            AppSessionStore::File(store) => store.save(session).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Session>, Self::Error> {
+        match self {
+            AppSessionStore::Memory(store) => store.load(session_id).await.map_err(|never| match never {}),
+            AppSessionStore::File(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<(), Self::Error> {
+        match self {
+            AppSessionStore::Memory(store) => store.delete(session_id).await.map_err(|never| match never {}),
+            AppSessionStore::File(store) => store.delete(session_id).await,
+        }
+    }
+}
+
+/// Sessions persistées dans un fichier YAML, réécrit à chaque modification
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    sessions: Arc<Mutex<HashMap<Id, Session>>>,
+}
+
+impl FileStore {
+    /// Ouvre le fichier de sessions, en ignorant les sessions expirées.
+    /// Un fichier corrompu est mis de côté : les sessions repartent de zéro.
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        let mut sessions: HashMap<Id, Session> = match read_yaml(&path) {
+            Ok(sessions) => sessions,
+            Err(LoadError::Corrupt { .. }) => HashMap::new(),
+            Err(LoadError::Io(e)) => return Err(e),
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+        };
+        sessions.retain(|_, session| is_active(session));
+
+        Ok(Self {
+            path,
+            sessions: Arc::new(Mutex::new(sessions)),
+        })
+    }
+
+    /// Réécrit le fichier de façon atomique, comme les bases de données
+    fn write(&self, sessions: &HashMap<Id, Session>) -> std::io::Result<()> {
+        save(sessions, &self.path).map_err(std::io::Error::other)
+    }
+
+    fn lock(&self) -> std::io::Result<std::sync::MutexGuard<'_, HashMap<Id, Session>>> {
+        self.sessions
+            .lock()
+            .map_err(|_| std::io::Error::other("Session store poisoned"))
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for FileStore {
+    type Error = std::io::Error;
+
+    async fn save(&self, session: &Session) -> Result<(), Self::Error> {
+        let mut sessions = self.lock()?;
+        sessions.retain(|_, session| is_active(session));
+        sessions.insert(*session.id(), session.clone());
+        self.write(&sessions)
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Session>, Self::Error> {
+        Ok(self
+            .lock()?
+            .get(session_id)
+            .filter(|session| is_active(session))
+            .cloned())
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<(), Self::Error> {
+        let mut sessions = self.lock()?;
+        if sessions.remove(session_id).is_some() {
+            self.write(&sessions)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_active(session: &Session) -> bool {
+    session.expiry_date().unix_timestamp() > now() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sessions_survive_restart() {
+        let path = crate::database::resolve(&format!("sessions-{}.yaml", uuid::Uuid::new_v4().simple()));
+        crate::database::init_data_dir().unwrap();
+
+        let store = AppSessionStore::File(FileStore::open(path.clone()).unwrap());
+        let session = Session::new(None);
+        session.insert("email", "jean@example.com").unwrap();
+        store.save(&session).await.unwrap();
+        drop(store);
+
+        // Redémarrage simulé : un nouveau store relit le fichier
+        let restarted = AppSessionStore::File(FileStore::open(path.clone()).unwrap());
+        let loaded = restarted.load(session.id()).await.unwrap().unwrap();
+        assert_eq!(loaded.get::<String>("email").unwrap(), Some("jean@example.com".to_string()));
+
+        restarted.delete(session.id()).await.unwrap();
+        let reopened = FileStore::open(path).unwrap();
+        assert!(reopened.load(session.id()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_session_file_is_moved_aside() {
+        let path = crate::database::resolve(&format!("sessions-{}.yaml", uuid::Uuid::new_v4().simple()));
+        crate::database::init_data_dir().unwrap();
+        std::fs::write(&path, "{ not: [valid").unwrap();
+
+        let store = FileStore::open(path.clone()).unwrap();
+        assert!(!path.exists());
+        let session = Session::new(None);
+        store.save(&session).await.unwrap();
+        assert!(FileStore::open(path.clone()).unwrap().load(session.id()).await.unwrap().is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_memory_sessions_do_not_survive_restart() {
+        let session = Session::new(None);
+        AppSessionStore::Memory(MemoryStore::default()).save(&session).await.unwrap();
+
+        let restarted = AppSessionStore::Memory(MemoryStore::default());
+        assert!(restarted.load(session.id()).await.unwrap().is_none());
+    }
+}
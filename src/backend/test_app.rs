@@ -0,0 +1,303 @@
+//! Application complète pour les tests de bout en bout, exécutée sans serveur.
+//! Chaque instance a sa propre configuration et son propre dossier de données, et garde
+//! ses cookies d'une requête à l'autre, comme un navigateur. Les bases restent des statiques
+//! partagées par tous les tests : seuls les fichiers écrits sont propres à l'instance, et
+//! uniquement pour le code exécuté dans sa portée (requêtes ou [`TestApp::scope`]).
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::{Extension, Router};
+use tower::ServiceExt;
+use crate::backend::router::get_router;
+use crate::config::{self, Config, SessionBackend};
+use crate::utils::webauthn::tests::SoftAuthenticator;
+use crate::HBS;
+
+pub(crate) struct TestApp {
+    router: Router,
+    config: Arc<Config>,
+    data_dir: PathBuf,
+    cookie: Mutex<Option<String>>,
+}
+
+impl TestApp {
+    /// Application avec la configuration par défaut
+    pub async fn new() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Application avec `config` ; le dossier de données et le stockage des sessions sont imposés
+    pub async fn with_config(config: Config) -> Self {
+        let data_dir = std::env::temp_dir().join(format!("lab02-app-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let config = Arc::new(Config {
+            data_dir: data_dir.clone(),
+            session_backend: SessionBackend::Memory,
+            ..config
+        });
+
+        // Toutes les requêtes sont traitées avec la configuration de l'application
+        let scoped = config.clone();
+        let router = config::scope((*config).clone(), async { get_router() })
+            .await
+            .layer(Extension(Arc::new(HBS.clone())))
+            .layer(axum::middleware::from_fn(move |request: Request, next: Next| {
+                config::scope((*scoped).clone(), next.run(request))
+            }));
+
+        TestApp {
+            router,
+            config,
+            data_dir,
+            cookie: Mutex::new(None),
+        }
+    }
+
+    /// Exécute `f` avec la configuration de l'application, par exemple pour préparer des données
+    pub async fn scope<F: std::future::Future>(&self, f: F) -> F::Output {
+        config::scope((*self.config).clone(), f).await
+    }
+
+    /// Routeur utilisable directement avec `ServiceExt::oneshot`, sans gestion des cookies
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Envoie `request` avec le cookie de session courant et retient celui de la réponse
+    pub async fn send(&self, mut request: Request) -> Response {
+        let cookie = self.cookie.lock().unwrap().clone();
+        if let Some(cookie) = cookie {
+            request.headers_mut().insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        }
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+            let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+            *self.cookie.lock().unwrap() = Some(cookie);
+        }
+        response
+    }
+
+    pub async fn get(&self, uri: &str) -> Response {
+        self.send(Request::builder().uri(uri).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: serde_json::Value) -> Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// Connexion de `email` avec la passkey de `authenticator` ; la session est retenue
+    pub async fn login(&self, email: &str, authenticator: &SoftAuthenticator) -> Response {
+        let challenge = json(self.post_json("/login", serde_json::json!({ "email": email })).await).await;
+        self.post_json(
+            "/login/complete",
+            serde_json::json!({
+                "state_id": challenge["state_id"],
+                "response": authenticator.authenticate(&challenge["publicKey"]),
+            }),
+        )
+        .await
+    }
+
+    /// Cookie de session courant
+    pub fn cookie(&self) -> Option<String> {
+        self.cookie.lock().unwrap().clone()
+    }
+
+    /// Remplace le cookie de session, pour alterner entre plusieurs sessions
+    pub fn set_cookie(&self, cookie: Option<String>) {
+        *self.cookie.lock().unwrap() = cookie;
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// Corps de la réponse en texte
+pub(crate) async fn text(response: Response) -> String {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+/// Corps de la réponse en JSON
+pub(crate) async fn json(response: Response) -> serde_json::Value {
+    serde_json::from_str(&text(response).await).unwrap()
+}
+
+/// Cible d'une redirection
+pub(crate) fn location(response: &Response) -> &str {
+    response.headers()[header::LOCATION].to_str().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use crate::database::{email, user};
+    use crate::utils::webauthn;
+
+    #[tokio::test]
+    async fn test_index_renders() {
+        let app = TestApp::new().await;
+        let response = app.get("/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("x-request-id"));
+        assert!(text(response).await.contains("Welcome"));
+
+        // Le routeur s'utilise aussi tel quel
+        let request = Request::builder().uri("/health").body(Body::empty()).unwrap();
+        let response = app.router().oneshot(request).await.unwrap();
+        assert_eq!(json(response).await["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn test_recover_flow() {
+        let app = TestApp::new().await;
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        app.scope(async {
+            user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+            user::verify(&email).unwrap();
+        })
+        .await;
+
+        // Demande de récupération : un lien est envoyé par email
+        let response = app.post_json("/recover", json!({ "email": email })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mail = email::sent_to(&email).pop().unwrap();
+        let token = mail.body.split("/recover/").nth(1).unwrap().trim().to_string();
+
+        // Le lien mène au formulaire d'enregistrement en mode reset
+        let response = app.get(&format!("/recover/{}", token)).await;
+        assert!(response.status().is_redirection());
+        let register_url = location(&response).to_string();
+        assert!(register_url.starts_with("/register?reset_mode=true"));
+        assert_eq!(app.get(&register_url).await.status(), StatusCode::OK);
+
+        // Enregistrement d'une nouvelle passkey avec le token
+        let authenticator = SoftAuthenticator::new();
+        let challenge = json(
+            app.post_json("/register", json!({ "email": email, "reset_mode": true, "recovery_token": token }))
+                .await,
+        )
+        .await;
+        let response = app
+            .post_json(
+                "/register/complete",
+                json!({
+                    "email": email,
+                    "first_name": "Jean",
+                    "last_name": "Dupont",
+                    "state_id": challenge["state_id"],
+                    "response": authenticator.register(&challenge["publicKey"]),
+                    "reset_mode": true,
+                    "recovery_token": token,
+                }),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // La nouvelle passkey permet de se connecter
+        let response = app.login(&email, &authenticator).await;
+        assert!(response.status().is_redirection(), "{}", response.status());
+        assert_eq!(app.get("/passkeys").await.status(), StatusCode::OK);
+
+        // Le token de récupération ne sert qu'une fois
+        let response = app.get(&format!("/recover/{}", token)).await;
+        assert!(!location(&response).starts_with("/register?reset_mode=true"));
+    }
+
+    /// Compte vérifié dont la passkey est celle de `authenticator`
+    async fn registered_user(authenticator: &SoftAuthenticator) -> String {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let (options, state) = webauthn::begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = webauthn::complete_registration(&response, &state).await.unwrap();
+        user::create(&email, Some("Jean"), Some("Dupont"), state.user_handle).unwrap();
+        user::verify(&email).unwrap();
+        user::set_passkey(&email, passkey).unwrap();
+        email
+    }
+
+    #[tokio::test]
+    async fn test_logout_all() {
+        let app = TestApp::new().await;
+        let authenticator = SoftAuthenticator::new();
+        let email = app.scope(registered_user(&authenticator)).await;
+
+        // Deux sessions ouvertes, par exemple sur deux appareils
+        assert!(app.login(&email, &authenticator).await.status().is_redirection());
+        let first = app.cookie();
+        app.set_cookie(None);
+        assert!(app.login(&email, &authenticator).await.status().is_redirection());
+        let second = app.cookie();
+        assert_ne!(first, second);
+
+        // Il faut une session valide
+        app.set_cookie(None);
+        assert_eq!(app.post_json("/logout-all", json!({})).await.status(), StatusCode::UNAUTHORIZED);
+
+        app.set_cookie(second.clone());
+        assert_eq!(app.get("/passkeys").await.status(), StatusCode::OK);
+        let response = app.post_json("/logout-all", json!({})).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Ni la session courante ni l'autre ne sont encore acceptées
+        for cookie in [first, second] {
+            app.set_cookie(cookie);
+            assert_eq!(app.get("/passkeys").await.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_cookie_name_and_path() {
+        let authenticator = SoftAuthenticator::new();
+        let email = registered_user(&authenticator).await;
+        let set_cookie = |response: &Response| {
+            let value = response.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+            value.split("; ").map(str::to_string).collect::<Vec<_>>()
+        };
+
+        // Nom et chemin configurés
+        let app = TestApp::with_config(Config {
+            session_cookie_name: "slh_session".to_string(),
+            session_cookie_path: "/app".to_string(),
+            secure_cookies: false,
+            ..Default::default()
+        })
+        .await;
+        let attributes = set_cookie(&app.login(&email, &authenticator).await);
+        assert!(attributes[0].starts_with("slh_session="));
+        assert!(attributes.contains(&"Path=/app".to_string()));
+        assert!(!attributes.contains(&"Secure".to_string()));
+
+        // Un nom `__Host-` impose Secure et Path=/, sans Domain
+        let app = TestApp::with_config(Config {
+            session_cookie_name: "__Host-slh_session".to_string(),
+            session_cookie_path: "/app".to_string(),
+            secure_cookies: false,
+            ..Default::default()
+        })
+        .await;
+        let attributes = set_cookie(&app.login(&email, &authenticator).await);
+        assert!(attributes[0].starts_with("__Host-slh_session="));
+        assert!(attributes.contains(&"Path=/".to_string()));
+        assert!(attributes.contains(&"Secure".to_string()));
+        assert!(attributes.iter().all(|attribute| !attribute.starts_with("Domain=")));
+    }
+}
@@ -0,0 +1,130 @@
+//! Terminaison TLS intégrée, pour les déploiements sans reverse proxy.
+//! Sert l'application en HTTPS et, si demandé, redirige le trafic HTTP vers HTTPS.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// Charge le certificat et la clé privée au format PEM
+pub async fn load_config(cert_path: &Path, key_path: &Path) -> std::io::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(cert_path, key_path).await
+}
+
+/// Sert l'application en HTTPS sur un listener déjà ouvert
+pub async fn serve(
+    listener: std::net::TcpListener,
+    app: Router,
+    config: RustlsConfig,
+) -> std::io::Result<()> {
+    listener.set_nonblocking(true)?;
+    axum_server::from_tcp_rustls(listener, config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+/// Routeur HTTP qui redirige chaque requête vers le port HTTPS
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move { redirect_to_https(&request, https_port) })
+}
+
+fn redirect_to_https(request: &Request, https_port: u16) -> Response {
+    let Some(host) = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| url::Url::parse(&format!("http://{}", host)).ok())
+        .and_then(|url| url.host_str().map(str::to_string))
+    else {
+        return (StatusCode::BAD_REQUEST, "Missing host").into_response();
+    };
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    Redirect::permanent(&format!("https://{}:{}{}", host, https_port, path)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use openssl::{asn1::Asn1Time, ec, hash::MessageDigest, nid::Nid, pkey::PKey, x509};
+    use std::io::{Read, Write};
+    use tower::ServiceExt;
+
+    /// Génère un certificat autosigné pour `localhost` ; retourne les chemins du certificat et de la clé
+    fn self_signed_fixture() -> (std::path::PathBuf, std::path::PathBuf) {
+        let group = ec::EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(ec::EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = x509::X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("lab02-tls-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.build().to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[tokio::test]
+    async fn test_serves_request_over_tls() {
+        let (cert_path, key_path) = self_signed_fixture();
+        let config = load_config(&cert_path, &key_path).await.unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, crate::backend::router::get_router(), config));
+
+        // Client TLS minimal qui accepte le certificat autosigné
+        let response = tokio::task::spawn_blocking(move || {
+            let mut connector = openssl::ssl::SslConnector::builder(openssl::ssl::SslMethod::tls()).unwrap();
+            connector.set_verify(openssl::ssl::SslVerifyMode::NONE);
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut stream = connector.build().connect("localhost", stream).unwrap();
+            stream
+                .write_all(b"GET /login HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).ok();
+            response
+        })
+        .await
+        .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_http_redirects_to_https() {
+        let request = axum::http::Request::builder()
+            .uri("/login?validated=true")
+            .header(header::HOST, "example.com:8080")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = redirect_router(8443).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:8443/login?validated=true"
+        );
+    }
+}
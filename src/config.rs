@@ -0,0 +1,855 @@
+//! Configuration de l'application, chargée depuis les variables d'environnement.
+//! Les valeurs par défaut correspondent au comportement historique du laboratoire.
+
+use std::{
+    env, fmt,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+use once_cell::sync::Lazy;
+use tracing::Level;
+use url::Url;
+use crate::consts;
+use webauthn_rs::prelude::{COSEAlgorithm, Uuid};
+
+/// Vérification anti-robot exigée au début de l'inscription
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BotProtection {
+    #[default]
+    Disabled,
+    /// Preuve de travail : nombre de bits à zéro exigés en tête du hash
+    ProofOfWork { difficulty: u32 },
+}
+
+/// Backend de stockage des sessions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SessionBackend {
+    /// Sessions perdues au redémarrage (développement)
+    #[default]
+    Memory,
+    /// Sessions persistées dans le dossier de données
+    File,
+}
+
+/// Nom affiché par l'authentificateur pour une nouvelle passkey ; l'email reste le nom du compte
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisplayNamePolicy {
+    /// Prénom et nom, ou l'email s'ils ne sont pas connus
+    #[default]
+    FullName,
+    /// Toujours l'email
+    Email,
+}
+
+/// Champ de profil demandé à l'inscription, en plus de l'email
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileField {
+    FirstName,
+    LastName,
+}
+
+impl ProfileField {
+    pub const ALL: [ProfileField; 2] = [ProfileField::FirstName, ProfileField::LastName];
+
+    /// Nom du champ dans les requêtes d'inscription
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileField::FirstName => "first_name",
+            ProfileField::LastName => "last_name",
+        }
+    }
+}
+
+/// Plage d'adresses IP au format CIDR (ex. `10.0.0.0/8`) ; une adresse seule vaut /32 ou /128
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Indique si `ip` appartient à la plage
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("Invalid address: {}", value))?;
+        let addr = addr.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= max),
+            None => Some(max),
+        }
+        .ok_or_else(|| format!("Invalid prefix: {}", value))?;
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+/// Paramètres modifiables au déploiement
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Dossier racine des bases YAML et des uploads
+    pub data_dir: PathBuf,
+    /// Inscription libre ; si `false`, un code d'invitation est exigé
+    pub open_registration: bool,
+    /// Durée de validité des tokens de validation et de récupération, en secondes
+    pub token_ttl_secs: u64,
+    /// Connexion par lien envoyé par email, en alternative aux passkeys (désactivée par défaut)
+    pub magic_link_login: bool,
+    /// Durée de validité des liens de connexion, en secondes
+    pub magic_link_ttl_secs: u64,
+    /// Demande de récupération par l'utilisateur lui-même ; sinon seul un administrateur
+    /// peut émettre un lien de récupération
+    pub self_service_recovery: bool,
+    /// Délai accordé pour valider un compte avant sa suppression, en secondes
+    pub unverified_grace_secs: u64,
+    /// Taille maximale en octets des prénoms et noms, en plus de la limite en caractères
+    pub max_name_bytes: usize,
+    /// Champs de profil obligatoires à l'inscription ; les autres sont facultatifs
+    pub required_fields: Vec<ProfileField>,
+    /// Noms réservés refusés à l'inscription, comparés sans casse ni accents
+    pub reserved_names: Vec<String>,
+    /// Nombre maximal de passkeys actives par compte
+    pub max_passkeys_per_user: usize,
+    /// Nombre maximal de posts par utilisateur
+    pub max_posts_per_user: usize,
+    /// Nombre d'uploads reçus en parallèle ; les suivants attendent leur tour
+    pub max_concurrent_uploads: usize,
+    /// Taille cumulée maximale des uploads conservés par compte, en octets
+    pub max_upload_bytes_per_user: u64,
+    /// Délai après lequel un upload jamais associé à un post est supprimé, en secondes
+    pub unattached_upload_ttl_secs: u64,
+    /// Nombre de requêtes traitées en parallèle ; au-delà, le serveur répond 503
+    pub max_concurrent_requests: usize,
+    /// Nombre maximal de cérémonies WebAuthn en attente, par type ; au-delà, les plus anciennes sont oubliées
+    pub max_pending_challenges: usize,
+    /// Durée pendant laquelle un challenge utilisé reste refusé, en secondes ; au moins la durée d'une cérémonie
+    pub replay_cache_secs: u64,
+    /// Mode maintenance : seuls les administrateurs et le health check sont servis
+    pub maintenance_mode: bool,
+    /// Algorithmes COSE acceptés pour les nouvelles passkeys
+    pub allowed_algorithms: Vec<COSEAlgorithm>,
+    /// AAGUID des modèles d'authentificateurs refusés (vulnérabilités connues)
+    pub denied_aaguids: Vec<Uuid>,
+    /// Nom affiché des nouvelles passkeys
+    pub display_name_policy: DisplayNamePolicy,
+    /// Niveau des logs de détection d'abus (`None` pour les désactiver)
+    pub abuse_log_level: Option<Level>,
+    /// Nombre maximal de logs d'abus émis par minute
+    pub abuse_log_per_minute: u32,
+    /// Dossier des templates Handlebars
+    pub templates_dir: PathBuf,
+    /// Relire les templates depuis le disque à chaque rendu (développement)
+    pub templates_hot_reload: bool,
+    /// Adresse et nom d'expéditeur des emails envoyés
+    pub mail_from: String,
+    pub mail_from_name: String,
+    /// Adresse de réponse ; par défaut l'adresse d'expédition
+    pub mail_reply_to: Option<String>,
+    /// Préfixe ajouté au sujet de chaque email (ex. « [MyApp] »)
+    pub mail_subject_prefix: String,
+    /// Stockage des sessions
+    pub session_backend: SessionBackend,
+    /// Expiration d'une session après inactivité, en secondes
+    pub session_idle_secs: u64,
+    /// Durée d'une session « se souvenir de cet appareil », en secondes
+    pub remember_me_secs: u64,
+    /// Durée de vie maximale d'une session depuis la connexion, quelle que soit l'activité
+    pub session_max_lifetime_secs: u64,
+    /// Délai après une connexion ou une vérification de passkey pendant lequel les actions sensibles sont permises
+    pub reauth_window_secs: u64,
+    /// Protection anti-robot de l'inscription (désactivée par défaut)
+    pub bot_protection: BotProtection,
+    /// Certificat et clé privée (PEM) ; si les deux sont définis, l'application est servie en HTTPS
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    /// Port d'écoute HTTPS
+    pub https_port: u16,
+    /// En HTTPS, garder le port HTTP ouvert pour rediriger vers HTTPS
+    pub redirect_http: bool,
+    /// Identifiant et origine du relying party WebAuthn
+    pub rp_id: String,
+    pub rp_origin: String,
+    /// Réponses CORS des cérémonies WebAuthn (`/register*`, `/login*`), cookie de session compris.
+    /// Seule `rp_origin` est autorisée : l'origine CORS doit être celle du relying party, sans quoi
+    /// le navigateur refuserait de toute façon la cérémonie.
+    pub cors_enabled: bool,
+    /// URL publique du site, base des liens envoyés par email ; par défaut `rp_origin`
+    pub public_url: Option<String>,
+    /// Hôtes acceptés pour `public_url` en plus de celui de `rp_origin`
+    pub link_hosts: Vec<String>,
+    /// Marquer le cookie de session `Secure`
+    pub secure_cookies: bool,
+    /// Nom et chemin du cookie de session, pour séparer plusieurs applications d'un même domaine.
+    /// Un nom préfixé par `__Host-` impose `Secure` et `Path=/`.
+    pub session_cookie_name: String,
+    pub session_cookie_path: String,
+    /// Indenter les réponses JSON, pour le débogage (compactes par défaut)
+    pub pretty_json: bool,
+    /// Outils de développement (`/debug/*`) ; toujours ignoré en production
+    pub dev_mode: bool,
+    /// Refuser de démarrer si la configuration n'est pas sûre (production)
+    pub strict_security: bool,
+    /// Reverse proxies dont l'en-tête `X-Forwarded-For` est pris en compte
+    pub trusted_proxies: Vec<IpNet>,
+    /// Profondeur d'imbrication et nombre d'éléments maximaux des corps JSON
+    pub max_json_depth: usize,
+    pub max_json_elements: usize,
+    /// Requêtes autorisées par minute et par IP sur les routes d'inscription, connexion et récupération
+    pub rate_limit_per_minute: u32,
+    /// Secret partagé de l'endpoint de connexion simulée
+    #[cfg(feature = "test-auth")]
+    pub test_auth_secret: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: default_data_dir(),
+            open_registration: true,
+            token_ttl_secs: 24 * 60 * 60,
+            magic_link_login: false,
+            magic_link_ttl_secs: 10 * 60,
+            self_service_recovery: true,
+            unverified_grace_secs: 72 * 60 * 60,
+            max_name_bytes: 128,
+            required_fields: ProfileField::ALL.to_vec(),
+            reserved_names: Vec::new(),
+            max_passkeys_per_user: 10,
+            max_posts_per_user: 100,
+            max_concurrent_uploads: 4,
+            max_upload_bytes_per_user: 50 * 1024 * 1024,
+            unattached_upload_ttl_secs: 24 * 60 * 60,
+            max_concurrent_requests: 256,
+            max_pending_challenges: 10_000,
+            replay_cache_secs: 10 * 60,
+            maintenance_mode: false,
+            allowed_algorithms: vec![COSEAlgorithm::ES256, COSEAlgorithm::RS256, COSEAlgorithm::EDDSA],
+            denied_aaguids: Vec::new(),
+            display_name_policy: DisplayNamePolicy::FullName,
+            abuse_log_level: Some(Level::WARN),
+            abuse_log_per_minute: 60,
+            max_json_depth: 32,
+            max_json_elements: 100_000,
+            rate_limit_per_minute: 30,
+            trusted_proxies: Vec::new(),
+            rp_id: "localhost".to_string(),
+            rp_origin: format!("http://localhost:{}", consts::HTTP_PORT),
+            cors_enabled: false,
+            public_url: None,
+            link_hosts: Vec::new(),
+            secure_cookies: true,
+            session_cookie_name: "id".to_string(),
+            session_cookie_path: "/".to_string(),
+            pretty_json: false,
+            dev_mode: false,
+            strict_security: false,
+            templates_dir: PathBuf::from("templates/"),
+            templates_hot_reload: false,
+            mail_from: format!("no-reply@{}", consts::DOMAIN),
+            mail_from_name: "SLH Lab02".to_string(),
+            mail_reply_to: None,
+            mail_subject_prefix: String::new(),
+            session_backend: SessionBackend::Memory,
+            session_idle_secs: 30 * 60,
+            remember_me_secs: 30 * 24 * 60 * 60,
+            session_max_lifetime_secs: 30 * 24 * 60 * 60,
+            reauth_window_secs: 5 * 60,
+            bot_protection: BotProtection::Disabled,
+            tls_cert_path: None,
+            tls_key_path: None,
+            https_port: 8443,
+            redirect_http: true,
+            #[cfg(feature = "test-auth")]
+            test_auth_secret: None,
+        }
+    }
+}
+
+impl Config {
+    /// Construit la configuration depuis l'environnement (et le `.env`).
+    /// Chaque valeur illisible est rapportée, avec les problèmes trouvés par `validate()`
+    /// sur le reste de la configuration, plutôt que remplacée en silence par le défaut.
+    pub fn from_env() -> Result<Self, ConfigErrors> {
+        let mut problems = Vec::new();
+        let config = Self::read_env(&mut problems);
+        if problems.is_empty() {
+            return Ok(config);
+        }
+
+        if let Err(ConfigErrors(others)) = config.validate() {
+            problems.extend(others);
+        }
+        Err(ConfigErrors(problems))
+    }
+
+    /// Lit l'environnement ; une valeur illisible garde le défaut et ajoute un problème
+    fn read_env(problems: &mut Vec<String>) -> Self {
+        let default = Self::default();
+        let pow_difficulty = env_or(problems, "POW_DIFFICULTY", 18);
+        Self {
+            data_dir: env::var("DATA_DIR").map(PathBuf::from).unwrap_or(default.data_dir),
+            open_registration: env_or(problems, "OPEN_REGISTRATION", default.open_registration),
+            token_ttl_secs: env_or(problems, "TOKEN_TTL_SECS", default.token_ttl_secs),
+            magic_link_login: env_or(problems, "MAGIC_LINK_LOGIN", default.magic_link_login),
+            magic_link_ttl_secs: env_or(problems, "MAGIC_LINK_TTL_SECS", default.magic_link_ttl_secs),
+            self_service_recovery: env_or(problems, "SELF_SERVICE_RECOVERY", default.self_service_recovery),
+            unverified_grace_secs: env_or(problems, "UNVERIFIED_GRACE_SECS", default.unverified_grace_secs),
+            max_name_bytes: env_or(problems, "MAX_NAME_BYTES", default.max_name_bytes),
+            required_fields: env_list_or(problems, "REQUIRED_FIELDS", parse_profile_field, default.required_fields),
+            reserved_names: env_list("RESERVED_NAMES").unwrap_or(default.reserved_names),
+            max_passkeys_per_user: env_or(problems, "MAX_PASSKEYS_PER_USER", default.max_passkeys_per_user),
+            max_posts_per_user: env_or(problems, "MAX_POSTS_PER_USER", default.max_posts_per_user),
+            max_concurrent_uploads: env_or(problems, "MAX_CONCURRENT_UPLOADS", default.max_concurrent_uploads),
+            max_upload_bytes_per_user: env_or(problems, "MAX_UPLOAD_BYTES_PER_USER", default.max_upload_bytes_per_user),
+            unattached_upload_ttl_secs: env_or(problems, "UNATTACHED_UPLOAD_TTL_SECS", default.unattached_upload_ttl_secs),
+            max_concurrent_requests: env_or(problems, "MAX_CONCURRENT_REQUESTS", default.max_concurrent_requests),
+            max_pending_challenges: env_or(problems, "MAX_PENDING_CHALLENGES", default.max_pending_challenges),
+            replay_cache_secs: env_or(problems, "REPLAY_CACHE_SECS", default.replay_cache_secs),
+            maintenance_mode: env_or(problems, "MAINTENANCE_MODE", default.maintenance_mode),
+            allowed_algorithms: env_list_or(problems, "WEBAUTHN_ALGORITHMS", parse_algorithm, default.allowed_algorithms),
+            // Une entrée illisible désactiverait la règle : elle empêche le démarrage
+            denied_aaguids: env_list_or(problems, "DENIED_AAGUIDS", |id| Uuid::parse_str(id).ok(), default.denied_aaguids),
+            display_name_policy: env_choice(
+                problems,
+                "WEBAUTHN_DISPLAY_NAME",
+                &[("full_name", DisplayNamePolicy::FullName), ("email", DisplayNamePolicy::Email)],
+                default.display_name_policy,
+            ),
+            abuse_log_level: env::var("ABUSE_LOG_LEVEL")
+                .map(|level| parse_level(&level))
+                .unwrap_or(default.abuse_log_level),
+            abuse_log_per_minute: env_or(problems, "ABUSE_LOG_PER_MINUTE", default.abuse_log_per_minute),
+            rate_limit_per_minute: env_or(problems, "RATE_LIMIT_PER_MINUTE", default.rate_limit_per_minute),
+            max_json_depth: env_or(problems, "MAX_JSON_DEPTH", default.max_json_depth),
+            max_json_elements: env_or(problems, "MAX_JSON_ELEMENTS", default.max_json_elements),
+            trusted_proxies: env_list_or(problems, "TRUSTED_PROXIES", |range| range.parse().ok(), default.trusted_proxies),
+            rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or(default.rp_id),
+            rp_origin: env::var("WEBAUTHN_ORIGIN").unwrap_or(default.rp_origin),
+            cors_enabled: env_or(problems, "CORS_ENABLED", default.cors_enabled),
+            public_url: env::var("PUBLIC_URL").ok().filter(|s| !s.is_empty()),
+            link_hosts: env_list("LINK_HOSTS").unwrap_or(default.link_hosts),
+            secure_cookies: env_or(problems, "SECURE_COOKIES", default.secure_cookies),
+            session_cookie_name: env::var("SESSION_COOKIE_NAME").unwrap_or(default.session_cookie_name),
+            session_cookie_path: env::var("SESSION_COOKIE_PATH").unwrap_or(default.session_cookie_path),
+            pretty_json: env_or(problems, "PRETTY_JSON", default.pretty_json),
+            dev_mode: env_or(problems, "DEV_MODE", default.dev_mode),
+            strict_security: env_or(problems, "STRICT_SECURITY", default.strict_security),
+            templates_dir: env::var("TEMPLATES_DIR").map(PathBuf::from).unwrap_or(default.templates_dir),
+            templates_hot_reload: env_or(problems, "TEMPLATES_HOT_RELOAD", default.templates_hot_reload),
+            mail_from: env::var("MAIL_FROM").unwrap_or(default.mail_from),
+            mail_from_name: env::var("MAIL_FROM_NAME").unwrap_or(default.mail_from_name),
+            mail_reply_to: env::var("MAIL_REPLY_TO").ok().filter(|s| !s.is_empty()),
+            mail_subject_prefix: env::var("MAIL_SUBJECT_PREFIX").unwrap_or(default.mail_subject_prefix),
+            session_backend: env_choice(
+                problems,
+                "SESSION_STORE",
+                &[("memory", SessionBackend::Memory), ("file", SessionBackend::File)],
+                default.session_backend,
+            ),
+            session_idle_secs: env_or(problems, "SESSION_IDLE_SECS", default.session_idle_secs),
+            remember_me_secs: env_or(problems, "REMEMBER_ME_SECS", default.remember_me_secs),
+            session_max_lifetime_secs: env_or(problems, "SESSION_MAX_LIFETIME_SECS", default.session_max_lifetime_secs),
+            reauth_window_secs: env_or(problems, "REAUTH_WINDOW_SECS", default.reauth_window_secs),
+            bot_protection: env_choice(
+                problems,
+                "BOT_PROTECTION",
+                &[
+                    ("disabled", BotProtection::Disabled),
+                    ("pow", BotProtection::ProofOfWork { difficulty: pow_difficulty }),
+                ],
+                default.bot_protection,
+            ),
+            tls_cert_path: env::var("TLS_CERT_PATH").ok().map(PathBuf::from),
+            tls_key_path: env::var("TLS_KEY_PATH").ok().map(PathBuf::from),
+            https_port: env_or(problems, "HTTPS_PORT", default.https_port),
+            redirect_http: env_or(problems, "REDIRECT_HTTP", default.redirect_http),
+            #[cfg(feature = "test-auth")]
+            test_auth_secret: env::var("TEST_AUTH_SECRET").ok().filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+impl Config {
+    /// Réglages non sûrs pour une mise en production
+    pub fn security_issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        if !self.rp_origin.starts_with("https://") {
+            issues.push(format!("WebAuthn origin {} is not served over HTTPS", self.rp_origin));
+        }
+        if !self.secure_cookies {
+            issues.push("Session cookies are not marked Secure".to_string());
+        }
+        // En développement, localhost est attendu
+        if self.production() && self.rp_id == "localhost" {
+            issues.push("WebAuthn relying party is localhost".to_string());
+        }
+        if self.production() && self.dev_mode {
+            issues.push("DEV_MODE is set but ignored in production".to_string());
+        }
+        // La connexion simulée contourne WebAuthn : elle n'a pas sa place en production
+        #[cfg(feature = "test-auth")]
+        if self.production() && self.test_auth_secret.is_some() {
+            issues.push("TEST_AUTH_SECRET enables the stub login endpoint".to_string());
+        }
+        // Les liens envoyés par email ne doivent pas mener à un autre site que celui des passkeys
+        if let Some(public_url) = &self.public_url {
+            let link_host = url_host(public_url);
+            let allowed = link_host == url_host(&self.rp_origin)
+                || link_host.as_ref().is_some_and(|host| self.link_hosts.contains(host));
+            if !allowed {
+                issues.push(format!(
+                    "PUBLIC_URL {} does not match the WebAuthn origin {} nor LINK_HOSTS",
+                    public_url, self.rp_origin
+                ));
+            }
+        }
+        issues
+    }
+
+    /// Base des liens envoyés par email, sans `/` final
+    pub fn link_base(&self) -> &str {
+        self.public_url.as_deref().unwrap_or(&self.rp_origin).trim_end_matches('/')
+    }
+
+    /// Build de release ou mode strict
+    pub fn production(&self) -> bool {
+        self.strict_security || !cfg!(debug_assertions)
+    }
+
+    /// Outils de développement disponibles : demandés et hors production
+    pub fn dev_tools(&self) -> bool {
+        self.dev_mode && !self.production()
+    }
+
+    /// En mode strict, refuse toute configuration non sûre
+    pub fn check_security(&self) -> Result<(), String> {
+        let issues = self.security_issues();
+        if self.strict_security && !issues.is_empty() {
+            return Err(issues.join("; "));
+        }
+        Ok(())
+    }
+}
+
+/// Problèmes de configuration trouvés au démarrage, rapportés ensemble
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration problem(s):", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+impl Config {
+    /// Vérifie l'ensemble de la configuration, sans s'arrêter au premier problème
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut problems = Vec::new();
+
+        if let Err(err) = crate::utils::webauthn::check_config(self) {
+            problems.push(format!("{:#}", err));
+        }
+        if let Err(errors) = crate::email::check_config(self) {
+            problems.extend(errors.iter().map(|err| err.to_string()));
+        }
+
+        // Le dossier de données est créé s'il n'existe pas ; s'il existe, il doit être lisible
+        if self.data_dir.exists() {
+            if let Err(err) = std::fs::read_dir(&self.data_dir) {
+                problems.push(format!("DATA_DIR {} is not a readable directory: {}", self.data_dir.display(), err));
+            }
+        }
+        if !self.templates_dir.is_dir() {
+            problems.push(format!("TEMPLATES_DIR {} is not a directory", self.templates_dir.display()));
+        }
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => {
+                for (name, path) in [("TLS_CERT_PATH", cert), ("TLS_KEY_PATH", key)] {
+                    if !path.is_file() {
+                        problems.push(format!("{} {} is not a readable file", name, path.display()));
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => problems.push("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        }
+
+        if let Some(public_url) = &self.public_url {
+            let valid = Url::parse(public_url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some());
+            if !valid {
+                problems.push(format!("PUBLIC_URL is not an http(s) URL: {}", public_url));
+            }
+        }
+
+        if !self.session_cookie_path.starts_with('/') {
+            problems.push(format!("SESSION_COOKIE_PATH must start with /: {}", self.session_cookie_path));
+        }
+        let cookie_name_valid = !self.session_cookie_name.is_empty()
+            && self.session_cookie_name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+        if !cookie_name_valid {
+            problems.push(format!("SESSION_COOKIE_NAME is not a valid cookie name: {:?}", self.session_cookie_name));
+        }
+
+        if self.allowed_algorithms.is_empty() {
+            problems.push("WEBAUTHN_ALGORITHMS does not name any supported algorithm".to_string());
+        }
+
+        // Une limite à zéro bloquerait la fonctionnalité correspondante
+        let limits = [
+            ("TOKEN_TTL_SECS", self.token_ttl_secs as usize),
+            ("MAGIC_LINK_TTL_SECS", self.magic_link_ttl_secs as usize),
+            ("MAX_NAME_BYTES", self.max_name_bytes),
+            ("MAX_PASSKEYS_PER_USER", self.max_passkeys_per_user),
+            ("MAX_CONCURRENT_UPLOADS", self.max_concurrent_uploads),
+            ("MAX_UPLOAD_BYTES_PER_USER", self.max_upload_bytes_per_user as usize),
+            ("UNATTACHED_UPLOAD_TTL_SECS", self.unattached_upload_ttl_secs as usize),
+            ("MAX_CONCURRENT_REQUESTS", self.max_concurrent_requests),
+            ("MAX_PENDING_CHALLENGES", self.max_pending_challenges),
+            ("RATE_LIMIT_PER_MINUTE", self.rate_limit_per_minute as usize),
+            ("ABUSE_LOG_PER_MINUTE", self.abuse_log_per_minute as usize),
+            ("REPLAY_CACHE_SECS", self.replay_cache_secs as usize),
+            ("SESSION_IDLE_SECS", self.session_idle_secs as usize),
+            ("SESSION_MAX_LIFETIME_SECS", self.session_max_lifetime_secs as usize),
+            ("MAX_JSON_DEPTH", self.max_json_depth),
+            ("MAX_JSON_ELEMENTS", self.max_json_elements),
+            ("HTTPS_PORT", self.https_port as usize),
+        ];
+        for (name, value) in limits {
+            if value == 0 {
+                problems.push(format!("{} must be greater than 0", name));
+            }
+        }
+
+        if problems.is_empty() { Ok(()) } else { Err(ConfigErrors(problems)) }
+    }
+}
+
+#[cfg(not(test))]
+fn default_data_dir() -> PathBuf {
+    PathBuf::from("./data")
+}
+
+/// Les tests écrivent dans un dossier temporaire pour ne jamais toucher `./data`
+#[cfg(test)]
+fn default_data_dir() -> PathBuf {
+    env::temp_dir().join(format!("lab02-tests-{}", std::process::id()))
+}
+
+/// Valeur non vide d'une variable d'environnement
+fn env_value(key: &str) -> Option<String> {
+    env::var(key).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+/// Lit une variable d'environnement, en gardant la valeur par défaut si absente ou invalide ;
+/// une valeur invalide est rapportée dans `problems`
+fn env_or<T: FromStr>(problems: &mut Vec<String>, key: &str, default: T) -> T {
+    let Some(value) = env_value(key) else { return default };
+    value.parse().unwrap_or_else(|_| {
+        problems.push(format!("{} has an invalid value: {:?}", key, value));
+        default
+    })
+}
+
+/// Lit une variable parmi des valeurs nommées ; une valeur inconnue est rapportée dans `problems`
+fn env_choice<T: Clone>(problems: &mut Vec<String>, key: &str, choices: &[(&str, T)], default: T) -> T {
+    let Some(value) = env_value(key) else { return default };
+    match choices.iter().find(|(name, _)| name.eq_ignore_ascii_case(&value)) {
+        Some((_, choice)) => choice.clone(),
+        None => {
+            let names: Vec<&str> = choices.iter().map(|(name, _)| *name).collect();
+            problems.push(format!("{} must be one of {}: {:?}", key, names.join(", "), value));
+            default
+        }
+    }
+}
+
+/// Lit une liste dont chaque élément est converti par `parse` ; chaque élément invalide est rapporté
+fn env_list_or<T>(problems: &mut Vec<String>, key: &str, parse: impl Fn(&str) -> Option<T>, default: Vec<T>) -> Vec<T> {
+    let Some(items) = env_list(key) else { return default };
+    items
+        .iter()
+        .filter_map(|item| {
+            let parsed = parse(item);
+            if parsed.is_none() {
+                problems.push(format!("{} has an invalid entry: {:?}", key, item));
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Lit une liste séparée par des virgules
+fn env_list(key: &str) -> Option<Vec<String>> {
+    let value = env::var(key).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect(),
+    )
+}
+
+/// Hôte d'une URL, `None` si elle est invalide
+fn url_host(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+/// Convertit un nom d'algorithme (ex. `ES256`) en `COSEAlgorithm`
+fn parse_algorithm(name: &str) -> Option<COSEAlgorithm> {
+    serde_json::from_value(serde_json::Value::String(name.to_uppercase())).ok()
+}
+
+/// Convertit un nom de champ de profil (ex. `last_name`) en `ProfileField`
+fn parse_profile_field(name: &str) -> Option<ProfileField> {
+    serde_json::from_value(serde_json::Value::String(name.to_lowercase())).ok()
+}
+
+/// Convertit un niveau de log (ex. `warn`) ; toute autre valeur, comme `off`, désactive les logs
+fn parse_level(name: &str) -> Option<Level> {
+    name.trim().parse().ok()
+}
+
+static CONFIG: Lazy<RwLock<Arc<Config>>> = Lazy::new(Default::default);
+
+tokio::task_local! {
+    // Configuration propre à une tâche, utilisée par les tests pour éviter de modifier la globale
+    static SCOPED: Arc<Config>;
+}
+
+/// Retourne la configuration courante
+pub fn get() -> Arc<Config> {
+    SCOPED
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| CONFIG.read().map(|c| c.clone()).unwrap_or_default())
+}
+
+/// Remplace la configuration globale (appelé au démarrage)
+pub fn set(config: Config) {
+    if let Ok(mut current) = CONFIG.write() {
+        *current = Arc::new(config);
+    }
+}
+
+/// Exécute `f` avec une configuration locale à la tâche
+#[cfg(test)]
+pub async fn scope<F: std::future::Future>(config: Config, f: F) -> F::Output {
+    SCOPED.scope(Arc::new(config), f).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!(parse_algorithm("ES256"), Some(COSEAlgorithm::ES256));
+        assert_eq!(parse_algorithm("eddsa"), Some(COSEAlgorithm::EDDSA));
+        assert_eq!(parse_algorithm("MD5"), None);
+    }
+
+    #[test]
+    fn test_ip_net() {
+        let net: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+
+        let single: IpNet = "192.0.2.1".parse().unwrap();
+        assert!(single.contains("192.0.2.1".parse().unwrap()));
+        assert!(!single.contains("192.0.2.2".parse().unwrap()));
+
+        let v6: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8::1".parse().unwrap()));
+        assert!(!v6.contains("10.1.2.3".parse().unwrap()));
+
+        assert!("0.0.0.0/0".parse::<IpNet>().unwrap().contains("203.0.113.7".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("proxy.local".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_http_origin() {
+        let config = Config {
+            rp_id: "example.com".to_string(),
+            rp_origin: "http://example.com".to_string(),
+            strict_security: true,
+            ..Default::default()
+        };
+        assert!(config.check_security().unwrap_err().contains("not served over HTTPS"));
+
+        // Sans mode strict, la configuration est acceptée mais signalée
+        let relaxed = Config { strict_security: false, ..config.clone() };
+        assert!(relaxed.check_security().is_ok());
+        assert_eq!(relaxed.security_issues().len(), 1);
+
+        let secure = Config { rp_origin: "https://example.com".to_string(), ..config };
+        assert!(secure.check_security().is_ok());
+        assert!(secure.security_issues().is_empty());
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_localhost_and_insecure_cookies() {
+        let config = Config {
+            rp_origin: "https://localhost".to_string(),
+            secure_cookies: false,
+            strict_security: true,
+            ..Default::default()
+        };
+        let issues = config.security_issues();
+        assert_eq!(issues.len(), 2);
+        assert!(config.check_security().is_err());
+    }
+
+    #[test]
+    fn test_public_url_must_match_origin_host() {
+        let config = Config {
+            rp_id: "example.com".to_string(),
+            rp_origin: "https://example.com".to_string(),
+            public_url: Some("https://example.net/".to_string()),
+            ..Default::default()
+        };
+        let issues = config.security_issues();
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("PUBLIC_URL https://example.net/"));
+        assert!(config.check_security().is_ok());
+
+        // En mode strict, le démarrage est refusé
+        let strict = Config { strict_security: true, ..config.clone() };
+        assert!(strict.check_security().unwrap_err().contains("PUBLIC_URL"));
+
+        // Même hôte que l'origine, ou hôte autorisé explicitement
+        let same_host = Config { public_url: Some("https://example.com:8443/app/".to_string()), ..strict.clone() };
+        assert!(same_host.check_security().is_ok());
+        assert_eq!(same_host.link_base(), "https://example.com:8443/app");
+        let allowed = Config { link_hosts: vec!["example.net".to_string()], ..strict };
+        assert!(allowed.check_security().is_ok());
+
+        // Sans PUBLIC_URL, les liens suivent l'origine WebAuthn
+        assert_eq!(Config::default().link_base(), "http://localhost:8080");
+        let invalid = Config { public_url: Some("mailto:admin@example.com".to_string()), ..Default::default() };
+        assert!(invalid.validate().unwrap_err().to_string().contains("PUBLIC_URL"));
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("warn"), Some(Level::WARN));
+        assert_eq!(parse_level(" DEBUG "), Some(Level::DEBUG));
+        assert_eq!(parse_level("off"), None);
+    }
+
+    #[test]
+    fn test_invalid_env_values_are_reported() {
+        // Noms propres à ce test : les autres tests peuvent lire l'environnement en parallèle
+        env::set_var("LAB02_TEST_LIMIT", "abc");
+        env::set_var("LAB02_TEST_ALGORITHMS", "ES256, MD5");
+        env::set_var("LAB02_TEST_BACKEND", "redis");
+        env::set_var("LAB02_TEST_EMPTY", " ");
+
+        let mut problems = Vec::new();
+        assert_eq!(env_or(&mut problems, "LAB02_TEST_LIMIT", 100), 100);
+        assert_eq!(env_or(&mut problems, "LAB02_TEST_EMPTY", 100), 100);
+        assert_eq!(
+            env_list_or(&mut problems, "LAB02_TEST_ALGORITHMS", parse_algorithm, Vec::new()),
+            vec![COSEAlgorithm::ES256]
+        );
+        let choices = [("memory", SessionBackend::Memory), ("file", SessionBackend::File)];
+        assert_eq!(env_choice(&mut problems, "LAB02_TEST_BACKEND", &choices, SessionBackend::Memory), SessionBackend::Memory);
+        assert_eq!(
+            problems,
+            [
+                "LAB02_TEST_LIMIT has an invalid value: \"abc\"",
+                "LAB02_TEST_ALGORITHMS has an invalid entry: \"MD5\"",
+                "LAB02_TEST_BACKEND must be one of memory, file: \"redis\"",
+            ]
+        );
+
+        // Un AAGUID ou un proxy mal saisi désactiverait une règle de sécurité
+        env::set_var("LAB02_TEST_AAGUIDS", "not-an-aaguid");
+        env::set_var("LAB02_TEST_PROXIES", "10.0.0.0/8,proxy.local");
+        let mut security = Vec::new();
+        assert!(env_list_or(&mut security, "LAB02_TEST_AAGUIDS", |id| Uuid::parse_str(id).ok(), Vec::new()).is_empty());
+        let proxies: Vec<IpNet> = env_list_or(&mut security, "LAB02_TEST_PROXIES", |range| range.parse().ok(), Vec::new());
+        assert_eq!(proxies.len(), 1);
+        assert_eq!(security.len(), 2);
+        assert!(security[1].contains("proxy.local"));
+
+        env::set_var("LAB02_TEST_BACKEND", "File");
+        assert_eq!(env_choice(&mut problems, "LAB02_TEST_BACKEND", &choices, SessionBackend::Memory), SessionBackend::File);
+        assert_eq!(problems.len(), 3);
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        assert!(Config::default().validate().is_ok());
+
+        let invalid = Config {
+            rp_origin: "not a url".to_string(),
+            mail_from: "nobody".to_string(),
+            mail_reply_to: Some("nope".to_string()),
+            templates_dir: PathBuf::from("missing-templates"),
+            tls_cert_path: Some(PathBuf::from("cert.pem")),
+            session_cookie_path: "app".to_string(),
+            max_concurrent_requests: 0,
+            rate_limit_per_minute: 0,
+            abuse_log_per_minute: 0,
+            ..Default::default()
+        };
+        let ConfigErrors(problems) = invalid.validate().unwrap_err();
+        for expected in [
+            "WEBAUTHN_ORIGIN",
+            "MAIL_FROM",
+            "MAIL_REPLY_TO",
+            "TEMPLATES_DIR",
+            "TLS_CERT_PATH and TLS_KEY_PATH",
+            "SESSION_COOKIE_PATH",
+            "MAX_CONCURRENT_REQUESTS",
+            "RATE_LIMIT_PER_MINUTE",
+            "ABUSE_LOG_PER_MINUTE",
+        ] {
+            assert!(problems.iter().any(|problem| problem.contains(expected)), "{}: {:?}", expected, problems);
+        }
+        assert_eq!(problems.len(), 9);
+
+        // L'identifiant du relying party doit correspondre à l'origine
+        let mismatched = Config {
+            rp_id: "example.com".to_string(),
+            rp_origin: "https://other.example".to_string(),
+            ..Default::default()
+        };
+        let report = mismatched.validate().unwrap_err().to_string();
+        assert!(report.starts_with("1 configuration problem(s):"), "{}", report);
+        assert!(report.contains("WEBAUTHN_RP_ID example.com"));
+    }
+}
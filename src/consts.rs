@@ -1,10 +1,24 @@
 //! Définition des constantes globales pour l'application.
 
 pub const HTTP_PORT: u16 = 8080; // Port par défaut pour le serveur HTTP.
-pub const USERS_DB_PATH: &str = "./data/users.yaml"; // Chemin de la base de données des utilisateurs.
-pub const EMAILS_DB_PATH: &str = "./data/emails.yaml"; // Chemin de la base de données des emails.
-pub const POSTS_DB_PATH: &str = "./data/posts.yaml"; // Chemin de la base de données des posts.
-pub const UPLOADS_DIR: &str = "./data/uploads"; // Dossier pour les fichiers uploadés.
+pub const USERS_DB_PATH: &str = "users.yaml"; // Chemin de la base de données des utilisateurs, relatif à DATA_DIR.
+pub const EMAILS_DB_PATH: &str = "emails.yaml"; // Chemin de la base de données des emails, relatif à DATA_DIR.
+pub const POSTS_DB_PATH: &str = "posts.yaml"; // Chemin de la base de données des posts, relatif à DATA_DIR.
+pub const TOKENS_DB_PATH: &str = "tokens.yaml"; // Chemin de la base de données des tokens, relatif à DATA_DIR.
+pub const INVITES_DB_PATH: &str = "invites.yaml"; // Chemin de la base de données des codes d'invitation, relatif à DATA_DIR.
+pub const UPLOADS_DB_PATH: &str = "uploads.yaml"; // Chemin de la base de données des images uploadées, relatif à DATA_DIR.
+pub const FLAGS_DB_PATH: &str = "flags.yaml"; // Chemin de la base de données des signalements de posts, relatif à DATA_DIR.
+pub const SESSIONS_DB_PATH: &str = "sessions.yaml"; // Chemin du fichier de sessions persistées, relatif à DATA_DIR.
+pub const UPLOADS_DIR: &str = "uploads"; // Dossier pour les fichiers uploadés, relatif à DATA_DIR.
+pub const UPLOADS_BLOBS_DIR: &str = "uploads.blobs"; // Contenu des uploads, stocké une fois par empreinte et jamais servi, relatif à DATA_DIR.
+pub const UPLOADS_TMP_DIR: &str = "uploads.tmp"; // Dossier des uploads en cours de réception, relatif à DATA_DIR.
+pub const UPLOADS_URL: &str = "/data/uploads"; // URL sous laquelle les fichiers uploadés sont servis.
 pub const DOMAIN: &str = "localhost"; // Domaine utilisé par le site.
 pub const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024; // Taille maximale des fichiers uploadés en octets.
+pub const MAX_UPLOAD_BODY_SIZE: usize = MAX_FILE_SIZE as usize + 64 * 1024; // Taille maximale d'une requête d'upload (fichier et autres champs du formulaire).
+pub const MAX_EMAIL_LENGTH: usize = 320; // Taille maximale en octets d'une adresse email, vérifiée avant toute autre validation.
+pub const MAX_FILENAME_LENGTH: usize = 255; // Nombre maximal de caractères du nom d'origine d'un fichier uploadé.
+pub const TOKEN_PURGE_INTERVAL_SECS: u64 = 60 * 60; // Intervalle de purge des tokens expirés ou consommés.
+pub const MAX_PAGE_SIZE: usize = 100; // Nombre maximal de posts renvoyés par page.
+pub const REQUIRED_TEMPLATES: [&str; 6] = ["home", "index", "login", "maintenance", "recover", "register"]; // Templates devant exister dans TEMPLATES_DIR pour démarrer.
 pub const ALLOWED_MIME_TYPES: [&str; 1] = ["image/jpeg"]; // Types MIME autorisés pour les fichiers uploadés.
\ No newline at end of file
@@ -2,98 +2,452 @@
 
 use std::{
     collections::HashMap,
-    fs::{create_dir_all, File},
-    path::Path,
-    sync::RwLock,
+    fs::create_dir_all,
+    path::{Path, PathBuf},
 };
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use serde_yaml::{self, to_writer};
-use crate::consts;
+use crate::{config, consts};
+
+mod store;
+pub use store::{LoadError, YamlStore};
+pub(crate) use store::{read_yaml, save};
 
 // Gestion des utilisateurs
 pub mod user {
     use super::*;
     use once_cell::sync::Lazy;
-    use webauthn_rs::prelude::Passkey;
+    use uuid::Uuid;
+    use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+    use crate::utils::i18n::Locale;
+
+    /// Rôle d'un utilisateur ; les administrateurs sont désignés dans `users.yaml`
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Role {
+        #[default]
+        User,
+        Admin,
+    }
 
+    /// Compte utilisateur tel qu'enregistré dans `users.yaml`
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct User {
-        pub first_name: String,
-        pub last_name: String,
+        /// Prénom et nom, absents si la configuration les rend facultatifs
+        #[serde(default)]
+        pub first_name: Option<String>,
+        #[serde(default)]
+        pub last_name: Option<String>,
         pub email: String,
-        pub passkey: Option<Passkey>,
+        /// Passkeys enregistrées ; l'ancien champ `passkey` (une seule clé) est encore accepté
+        #[serde(default, alias = "passkey", deserialize_with = "passkeys_compat")]
+        pub passkeys: Vec<Passkey>,
+        /// Dernière authentification de chaque passkey (secondes Unix), par identifiant en base64url
+        #[serde(default)]
+        pub passkey_last_used: HashMap<String, u64>,
+        /// Passkeys découvrables (resident keys) selon l'extension credProps renvoyée à l'enregistrement,
+        /// par identifiant en base64url. Indication non signée du navigateur, absente s'il ne l'a pas fournie.
+        #[serde(default)]
+        pub passkey_discoverable: HashMap<String, bool>,
         pub verified: bool,
         pub stash: Vec<String>,
         pub liked_posts: Vec<u64>,
+        #[serde(default)]
+        pub role: Role,
+        /// Identifiant WebAuthn stable, réutilisé à chaque nouvel enregistrement
+        #[serde(default)]
+        pub user_handle: Option<Uuid>,
+        /// Génération des sessions ; l'incrémenter invalide toutes les sessions existantes
+        #[serde(default)]
+        pub session_generation: u64,
+        /// Date de création (secondes Unix) ; 0 pour les comptes créés avant l'ajout du champ
+        #[serde(default)]
+        pub created_at: u64,
+        /// L'email de validation n'a pas pu être envoyé à l'inscription et doit être renvoyé
+        #[serde(default)]
+        pub validation_mail_pending: bool,
+        /// Langue choisie par l'utilisateur ; à défaut, celle du navigateur
+        #[serde(default)]
+        pub locale: Option<Locale>,
+        /// Prévenir par email à chaque nouvelle connexion
+        #[serde(default)]
+        pub login_notifications: bool,
+        /// Prévenir par email quand la passkey est remplacée via une récupération
+        #[serde(default = "enabled")]
+        pub recovery_notifications: bool,
+        /// « Se souvenir de cet appareil » quand la connexion ne le précise pas
+        #[serde(default)]
+        pub remember_me_default: bool,
+    }
+
+    /// Préférences modifiables depuis la page du compte
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    pub struct Settings {
+        pub locale: Option<Locale>,
+        pub login_notifications: bool,
+        pub recovery_notifications: bool,
+        pub remember_me_default: bool,
     }
 
-    type Db = HashMap<String, User>;
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    fn enabled() -> bool {
+        true
+    }
 
-    pub fn create(email: &str, first_name: &str, last_name: &str) -> Result<bool> {
-        let user = User {
-            first_name: first_name.to_string(),
-            last_name: last_name.to_string(),
+    impl User {
+        pub fn settings(&self) -> Settings {
+            Settings {
+                locale: self.locale,
+                login_notifications: self.login_notifications,
+                recovery_notifications: self.recovery_notifications,
+                remember_me_default: self.remember_me_default,
+            }
+        }
+    }
+
+    /// Accepte une liste de passkeys, une passkey seule ou `null`.
+    /// On passe par `serde_yaml::Value`, qui conserve les tags YAML des clés (`!EC_EC2`).
+    fn passkeys_compat<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Passkey>, D::Error> {
+        use serde::de::Error;
+
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::Null => Ok(Vec::new()),
+            value @ serde_yaml::Value::Sequence(_) => serde_yaml::from_value(value).map_err(D::Error::custom),
+            value => serde_yaml::from_value(value).map(|passkey| vec![passkey]).map_err(D::Error::custom),
+        }
+    }
+
+    static DB: Lazy<YamlStore<HashMap<String, User>>> = Lazy::new(|| YamlStore::new(consts::USERS_DB_PATH));
+
+    /// Applique `f` au compte `email`, s'il existe
+    fn update_user<R>(email: &str, f: impl FnOnce(&mut User) -> Result<R>) -> Result<R> {
+        DB.update(|db| f(db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?))
+    }
+
+    /// Crée un compte sans passkey ; les inscriptions passent par `create_with_passkey`
+    #[cfg(test)]
+    pub fn create(email: &str, first_name: Option<&str>, last_name: Option<&str>, user_handle: Uuid) -> Result<bool> {
+        let user = new_user(email, first_name, last_name, user_handle);
+
+        DB.update(|db| {
+            if db.contains_key(email) {
+                return Ok(false);
+            }
+
+            db.insert(email.to_string(), user);
+            Ok(true)
+        })
+    }
+
+    /// Crée le compte avec sa passkey en une seule écriture : soit les deux sont enregistrés, soit aucun.
+    /// Une passkey déjà associée à un autre compte est refusée.
+    pub fn create_with_passkey(
+        email: &str,
+        first_name: Option<&str>,
+        last_name: Option<&str>,
+        user_handle: Uuid,
+        passkey: Passkey,
+    ) -> Result<bool> {
+        let mut user = new_user(email, first_name, last_name, user_handle);
+
+        DB.update(|db| {
+            if db.contains_key(email) {
+                return Ok(false);
+            }
+            if is_registered(db, &passkey) {
+                return Err(anyhow!("Passkey already registered"));
+            }
+
+            user.passkeys = vec![passkey];
+            db.insert(email.to_string(), user);
+            Ok(true)
+        })
+    }
+
+    /// La passkey est-elle déjà associée à un compte, quel qu'il soit ?
+    fn is_registered(db: &HashMap<String, User>, passkey: &Passkey) -> bool {
+        db.values().flat_map(|user| &user.passkeys).any(|existing| existing.cred_id() == passkey.cred_id())
+    }
+
+    fn new_user(email: &str, first_name: Option<&str>, last_name: Option<&str>, user_handle: Uuid) -> User {
+        User {
+            first_name: first_name.map(str::to_string),
+            last_name: last_name.map(str::to_string),
             email: email.to_string(),
-            passkey: None,
+            passkeys: Vec::new(),
+            passkey_last_used: HashMap::new(),
+            passkey_discoverable: HashMap::new(),
             verified: false,
             stash: Vec::new(),
             liked_posts: Vec::new(),
-        };
+            role: Role::User,
+            user_handle: Some(user_handle),
+            session_generation: 0,
+            created_at: now(),
+            validation_mail_pending: false,
+            locale: None,
+            login_notifications: false,
+            recovery_notifications: true,
+            remember_me_default: false,
+        }
+    }
 
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+    /// Remplace toutes les passkeys du compte par `passkey`
+    pub fn set_passkey(email: &str, passkey: Passkey) -> Result<()> {
+        update_user(email, |user| {
+            user.passkeys = vec![passkey];
+            user.passkey_last_used.clear();
+            user.passkey_discoverable.clear();
+            Ok(())
+        })
+    }
 
-        if db.contains_key(email) {
-            return Ok(false);
-        }
+    /// Ajoute une passkey au compte, dans la limite de `max` passkeys
+    pub fn add_passkey(email: &str, passkey: Passkey, max: usize) -> Result<()> {
+        DB.update(|db| {
+            // Comme à l'inscription, une passkey ne peut appartenir qu'à un seul compte
+            if is_registered(db, &passkey) {
+                return Err(anyhow!("Passkey already registered"));
+            }
+            let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
+            if user.passkeys.len() >= max {
+                return Err(anyhow!("Too many passkeys"));
+            }
+            user.passkeys.push(passkey);
+            Ok(())
+        })
+    }
 
-        db.insert(email.to_string(), user);
-        save(&db)?;
-        Ok(true)
+    /// Supprime la passkey identifiée par `credential_id` (voir `credential_key`).
+    /// Retourne `false` si elle n'existe pas ; la dernière passkey du compte ne peut pas être supprimée.
+    pub fn remove_passkey(email: &str, credential_id: &str) -> Result<bool> {
+        update_user(email, |user| {
+            let Some(index) = user.passkeys.iter().position(|passkey| credential_key(passkey.cred_id()) == credential_id) else {
+                return Ok(false);
+            };
+            if user.passkeys.len() == 1 {
+                return Err(anyhow!("Cannot remove the last passkey"));
+            }
+            user.passkeys.remove(index);
+            user.passkey_last_used.remove(credential_id);
+            user.passkey_discoverable.remove(credential_id);
+            Ok(true)
+        })
     }
 
-    pub fn set_passkey(email: &str, passkey: Passkey) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        let user = db.get_mut(email).ok_or_else(|| anyhow!("User not found"))?;
-        user.passkey = Some(passkey);
-        save(&db)?;
-        Ok(())
+    /// Retient si la passkey `cred_id` du compte est découvrable
+    pub fn set_discoverable(email: &str, cred_id: &[u8], discoverable: bool) -> Result<()> {
+        update_user(email, |user| {
+            if !user.passkeys.iter().any(|passkey| passkey.cred_id().as_ref() == cred_id) {
+                return Err(anyhow!("Passkey not found"));
+            }
+            user.passkey_discoverable.insert(credential_key(cred_id), discoverable);
+            Ok(())
+        })
+    }
+
+    /// Clé d'une passkey dans `passkey_last_used` et `passkey_discoverable`
+    pub fn credential_key(cred_id: &[u8]) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(cred_id)
+    }
+
+    /// Enregistre une authentification réussie : compteur de la passkey utilisée et date d'utilisation
+    pub fn record_authentication(email: &str, result: &AuthenticationResult) -> Result<()> {
+        update_user(email, |user| {
+            let passkey = user.passkeys
+                .iter_mut()
+                .find(|passkey| passkey.cred_id() == result.cred_id())
+                .ok_or_else(|| anyhow!("Passkey not found"))?;
+            passkey.update_credential(result);
+            user.passkey_last_used.insert(credential_key(result.cred_id()), now());
+            Ok(())
+        })
     }
 
-    pub fn get_passkey(email: &str) -> Result<Option<Passkey>> {
-        let db = DB.read().or(Err(anyhow!("DB poisoned")))?;
-        let user = db.get(email).ok_or_else(|| anyhow!("User not found"))?;
-        Ok(user.passkey.clone())
+    /// Associe un identifiant WebAuthn à un compte existant qui n'en a pas encore
+    pub fn set_user_handle(email: &str, user_handle: Uuid) -> Result<()> {
+        update_user(email, |user| {
+            user.user_handle = Some(user_handle);
+            Ok(())
+        })
     }
 
-    pub fn get(email: &str) -> Option<User> {
-        DB.read().ok()?.get(email).cloned()
+    /// Invalide toutes les sessions du compte ; retourne la nouvelle génération
+    pub fn bump_session_generation(email: &str) -> Result<u64> {
+        update_user(email, |user| {
+            user.session_generation += 1;
+            Ok(user.session_generation)
+        })
+    }
+
+    /// Compte associé à `email` ; `None` s'il n'existe pas, une erreur si la base est inutilisable
+    pub fn get(email: &str) -> Result<Option<User>> {
+        DB.read(|db| db.get(email).cloned())
     }
 
     pub fn exists(email: &str) -> Result<bool> {
-        Ok(DB.read().or(Err(anyhow!("DB poisoned")))?.contains_key(email))
+        DB.contains(email)
     }
 
-    pub fn verify(email: &str) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+    /// Les rôles sont attribués dans `users.yaml` ; seuls les tests les modifient
+    #[cfg(test)]
+    pub fn set_role(email: &str, role: Role) -> Result<()> {
+        update_user(email, |user| {
+            user.role = role;
+            Ok(())
+        })
+    }
 
-        let user = db.get_mut(email).ok_or(anyhow!("User not found"))?;
-        if user.verified {
+    pub fn set_validation_mail_pending(email: &str, pending: bool) -> Result<()> {
+        update_user(email, |user| {
+            user.validation_mail_pending = pending;
+            Ok(())
+        })
+    }
+
+    pub fn set_locale(email: &str, locale: Option<Locale>) -> Result<()> {
+        update_user(email, |user| {
+            user.locale = locale;
+            Ok(())
+        })
+    }
+
+    /// Modifie les préférences du compte en une seule écriture ; retourne les préférences enregistrées
+    pub fn update_settings(email: &str, f: impl FnOnce(&mut Settings)) -> Result<Settings> {
+        update_user(email, |user| {
+            let mut settings = user.settings();
+            f(&mut settings);
+            user.locale = settings.locale;
+            user.login_notifications = settings.login_notifications;
+            user.recovery_notifications = settings.recovery_notifications;
+            user.remember_me_default = settings.remember_me_default;
+            Ok(settings)
+        })
+    }
+
+    /// Comptes non validés dont l'email de validation reste à envoyer
+    pub fn validation_mail_pending() -> Result<Vec<String>> {
+        DB.read(|db| {
+            db.values()
+                .filter(|user| user.validation_mail_pending && !user.verified)
+                .map(|user| user.email.clone())
+                .collect()
+        })
+    }
+
+    pub fn is_admin(email: &str) -> bool {
+        matches!(get(email), Ok(Some(user)) if user.role == Role::Admin)
+    }
+
+    pub fn verify(email: &str) -> Result<()> {
+        if get(email)?.is_some_and(|user| user.verified) {
             return Ok(());
         }
 
-        user.verified = true;
-        save(&db)?;
-        Ok(())
+        update_user(email, |user| {
+            user.verified = true;
+            Ok(())
+        })
+    }
+
+    /// Supprime les comptes non validés créés avant `cutoff` ; retourne leurs emails.
+    /// Les comptes sans date de création sont conservés, faute de pouvoir dater leur inscription.
+    pub fn purge_unverified(cutoff: u64) -> Result<Vec<String>> {
+        let is_stale = |user: &User| !user.verified && user.created_at != 0 && user.created_at < cutoff;
+        if !DB.read(|db| db.values().any(is_stale))? {
+            return Ok(Vec::new());
+        }
+
+        DB.update(|db| {
+            let stale: Vec<String> = db.values().filter(|user| is_stale(user)).map(|user| user.email.clone()).collect();
+            for email in &stale {
+                db.remove(email);
+            }
+            Ok(stale)
+        })
+    }
+
+    /// Antidate la création d'un compte
+    #[cfg(test)]
+    pub fn set_created_at(email: &str, created_at: u64) -> Result<()> {
+        update_user(email, |user| {
+            user.created_at = created_at;
+            Ok(())
+        })
     }
 
-    pub fn load() -> Result<()> {
-        super::load(&DB, consts::USERS_DB_PATH)
+    /// Retire l'identifiant WebAuthn, comme pour un compte créé avant son introduction
+    #[cfg(test)]
+    pub fn clear_user_handle(email: &str) -> Result<()> {
+        update_user(email, |user| {
+            user.user_handle = None;
+            Ok(())
+        })
     }
 
-    fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::USERS_DB_PATH)
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::utils::webauthn::tests::test_passkey;
+
+        fn user_yaml(passkey_field: &str, passkey: serde_yaml::Value) -> User {
+            let mut yaml = serde_yaml::to_value(User {
+                first_name: Some("Jean".to_string()),
+                last_name: Some("Dupont".to_string()),
+                email: "jean@example.com".to_string(),
+                passkeys: Vec::new(),
+                passkey_last_used: HashMap::new(),
+                passkey_discoverable: HashMap::new(),
+                verified: true,
+                stash: Vec::new(),
+                liked_posts: Vec::new(),
+                role: Role::User,
+                user_handle: None,
+                session_generation: 0,
+                created_at: 0,
+                validation_mail_pending: false,
+                locale: None,
+                login_notifications: false,
+                recovery_notifications: true,
+                remember_me_default: false,
+            })
+            .unwrap();
+            let map = yaml.as_mapping_mut().unwrap();
+            map.remove("passkeys");
+            map.insert(passkey_field.into(), passkey);
+            serde_yaml::from_value(yaml).unwrap()
+        }
+
+        #[test]
+        fn test_legacy_single_passkey_is_loaded() {
+            let passkey = test_passkey();
+            let legacy = user_yaml("passkey", serde_yaml::to_value(&passkey).unwrap());
+            assert_eq!(legacy.passkeys.len(), 1);
+            assert_eq!(legacy.passkeys[0].cred_id(), passkey.cred_id());
+
+            assert!(user_yaml("passkey", serde_yaml::Value::Null).passkeys.is_empty());
+            let many = serde_yaml::to_value(vec![test_passkey(), test_passkey()]).unwrap();
+            assert_eq!(user_yaml("passkeys", many).passkeys.len(), 2);
+        }
+
+        #[test]
+        fn test_passkey_belongs_to_one_account() {
+            let first = format!("{}@example.com", Uuid::new_v4().simple());
+            let second = format!("{}@example.com", Uuid::new_v4().simple());
+            create(&first, Some("Jean"), None, Uuid::new_v4()).unwrap();
+            create(&second, Some("Marie"), None, Uuid::new_v4()).unwrap();
+
+            let passkey = test_passkey();
+            add_passkey(&first, passkey.clone(), usize::MAX).unwrap();
+            assert!(add_passkey(&second, passkey.clone(), usize::MAX).is_err());
+            assert!(get(&second).unwrap().unwrap().passkeys.is_empty());
+            let third = format!("{}@example.com", Uuid::new_v4().simple());
+            assert!(create_with_passkey(&third, None, None, Uuid::new_v4(), passkey).is_err());
+        }
     }
 }
 
@@ -102,19 +456,228 @@ pub mod token {
     use super::*;
     use once_cell::sync::Lazy;
 
-    type Db = HashMap<String, String>;
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    /// Usage prévu d'un token ; un token ne peut servir qu'à l'usage pour lequel il a été émis
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum TokenKind {
+        #[default]
+        Validation,
+        Recovery,
+        /// Lien de connexion envoyé par email, à durée de vie courte
+        Login,
+    }
 
-    pub fn generate(email: &str) -> Result<String> {
+    impl TokenKind {
+        fn ttl_secs(self) -> u64 {
+            let config = config::get();
+            match self {
+                TokenKind::Login => config.magic_link_ttl_secs,
+                TokenKind::Validation | TokenKind::Recovery => config.token_ttl_secs,
+            }
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Token {
+        pub email: String,
+        pub expires_at: u64,
+        pub consumed: bool,
+        #[serde(default)]
+        pub kind: TokenKind,
+    }
+
+    /// Erreurs possibles lors de l'utilisation d'un token
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum TokenError {
+        NotFound,
+        Expired,
+        AlreadyConsumed,
+        KindMismatch,
+        Io(String),
+    }
+
+    impl std::fmt::Display for TokenError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                TokenError::NotFound => write!(f, "Token not found"),
+                TokenError::Expired => write!(f, "Token expired"),
+                TokenError::AlreadyConsumed => write!(f, "Token already consumed"),
+                TokenError::KindMismatch => write!(f, "Token used for the wrong purpose"),
+                TokenError::Io(err) => write!(f, "Token storage error: {}", err),
+            }
+        }
+    }
+
+    impl std::error::Error for TokenError {}
+
+    impl From<anyhow::Error> for TokenError {
+        fn from(err: anyhow::Error) -> Self {
+            TokenError::Io(err.to_string())
+        }
+    }
+
+    type Db = HashMap<String, Token>;
+    static DB: Lazy<YamlStore<Db>> = Lazy::new(|| YamlStore::new(consts::TOKENS_DB_PATH));
+
+    pub fn generate(email: &str, kind: TokenKind) -> Result<String, TokenError> {
         let token = uuid::Uuid::new_v4().to_string();
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.insert(token.clone(), email.to_string());
+        DB.insert(token.clone(), Token {
+            email: email.to_string(),
+            expires_at: now() + kind.ttl_secs(),
+            consumed: false,
+            kind,
+        })?;
         Ok(token)
     }
 
-    pub fn consume(token: &str) -> Result<String> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
-        db.remove(token).ok_or_else(|| anyhow!("Token not found"))
+    /// Retourne l'email associé à un token encore utilisable, sans le consommer
+    pub fn peek(token: &str, kind: TokenKind) -> Result<String, TokenError> {
+        let entry = DB.get(token).ok_or(TokenError::NotFound)?;
+        check(&entry, kind)?;
+        Ok(entry.email)
+    }
+
+    /// Consomme un token. La vérification et le marquage se font sous le même verrou d'écriture :
+    /// sur deux appels concurrents, un seul réussit, l'autre reçoit `AlreadyConsumed`.
+    /// Si l'écriture sur disque échoue, le token reste utilisable pour permettre un nouvel essai.
+    pub fn consume(token: &str, kind: TokenKind) -> Result<String, TokenError> {
+        DB.update(|db| {
+            let entry = db.get_mut(token).ok_or(TokenError::NotFound)?;
+            check(entry, kind)?;
+
+            entry.consumed = true;
+            Ok(entry.email.clone())
+        })
+    }
+
+    fn check(entry: &Token, kind: TokenKind) -> Result<(), TokenError> {
+        if entry.kind != kind {
+            return Err(TokenError::KindMismatch);
+        }
+        if entry.consumed {
+            return Err(TokenError::AlreadyConsumed);
+        }
+        if entry.expires_at <= now() {
+            return Err(TokenError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Supprime les tokens consommés ou expirés ; retourne le nombre de tokens supprimés
+    pub fn purge_expired() -> Result<usize> {
+        let now = now();
+        if !DB.read(|db| db.values().any(|token| token.consumed || token.expires_at <= now))? {
+            return Ok(0);
+        }
+        DB.update(|db| Ok(purge(db, now)))
+    }
+
+    /// Supprime les tokens non consommés de type `kind` émis pour l'un des `emails`
+    pub fn revoke(emails: &[String], kind: TokenKind) -> Result<usize> {
+        let targeted = |token: &Token| !token.consumed && token.kind == kind && emails.contains(&token.email);
+        if emails.is_empty() || !DB.read(|db| db.values().any(targeted))? {
+            return Ok(0);
+        }
+
+        DB.update(|db| {
+            let before = db.len();
+            db.retain(|_, token| !targeted(token));
+            Ok(before - db.len())
+        })
+    }
+
+    /// Liste les tokens émis pour `email`
+    #[cfg(test)]
+    pub fn issued_to(email: &str) -> Vec<Token> {
+        DB.read(|db| db.values().filter(|token| token.email == email).cloned().collect()).unwrap_or_default()
+    }
+
+    fn purge(db: &mut Db, now: u64) -> usize {
+        let before = db.len();
+        db.retain(|_, token| !token.consumed && token.expires_at > now);
+        before - db.len()
+    }
+
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn entry(expires_at: u64, consumed: bool) -> Token {
+            Token {
+                email: "jean@example.com".to_string(),
+                expires_at,
+                consumed,
+                kind: TokenKind::Validation,
+            }
+        }
+
+        #[test]
+        fn test_purge_removes_only_expired_and_consumed() {
+            let now = now();
+            let mut db = Db::new();
+            db.insert("live".to_string(), entry(now + 60, false));
+            db.insert("expired".to_string(), entry(now - 1, false));
+            db.insert("consumed".to_string(), entry(now + 60, true));
+
+            assert_eq!(purge(&mut db, now), 2);
+            assert_eq!(db.keys().collect::<Vec<_>>(), vec!["live"]);
+        }
+
+        #[test]
+        fn test_peek_does_not_consume() {
+            let token = generate("jean@example.com", TokenKind::Recovery).unwrap();
+            assert_eq!(peek(&token, TokenKind::Recovery).unwrap(), "jean@example.com");
+            assert_eq!(consume(&token, TokenKind::Recovery).unwrap(), "jean@example.com");
+            assert!(peek(&token, TokenKind::Recovery).is_err());
+        }
+
+        #[test]
+        fn test_consume_marks_token() {
+            let token = generate("jean@example.com", TokenKind::Validation).unwrap();
+            assert_eq!(consume(&token, TokenKind::Validation).unwrap(), "jean@example.com");
+            assert_eq!(consume(&token, TokenKind::Validation), Err(TokenError::AlreadyConsumed));
+        }
+
+        #[test]
+        fn test_concurrent_consume_has_one_winner() {
+            for _ in 0..20 {
+                let token = generate("jean@example.com", TokenKind::Validation).unwrap();
+                let barrier = std::sync::Barrier::new(2);
+
+                let results: Vec<_> = std::thread::scope(|scope| {
+                    let handles: Vec<_> = (0..2)
+                        .map(|_| {
+                            scope.spawn(|| {
+                                barrier.wait();
+                                consume(&token, TokenKind::Validation)
+                            })
+                        })
+                        .collect();
+                    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+                });
+
+                assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+                assert!(results.contains(&Err(TokenError::AlreadyConsumed)));
+            }
+        }
+
+        #[test]
+        fn test_error_variants() {
+            assert_eq!(consume("unknown", TokenKind::Validation), Err(TokenError::NotFound));
+            assert_eq!(peek("unknown", TokenKind::Recovery), Err(TokenError::NotFound));
+
+            let token = generate("jean@example.com", TokenKind::Validation).unwrap();
+            assert_eq!(peek(&token, TokenKind::Recovery), Err(TokenError::KindMismatch));
+            assert_eq!(consume(&token, TokenKind::Recovery), Err(TokenError::KindMismatch));
+
+            let expired = uuid::Uuid::new_v4().to_string();
+            DB.insert(expired.clone(), entry(now() - 1, false)).unwrap();
+            assert_eq!(consume(&expired, TokenKind::Validation), Err(TokenError::Expired));
+        }
     }
 }
 
@@ -126,82 +689,477 @@ pub mod email {
     #[derive(Clone, Serialize, Deserialize, Debug)]
     pub struct Email {
         pub pk: u64,
+        #[serde(default)]
+        pub from: String,
         pub to: String,
         pub subject: String,
         pub body: String,
+        /// En-têtes complémentaires (Message-ID, Date, ...)
+        #[serde(default)]
+        pub headers: Vec<(String, String)>,
     }
 
-    #[derive(Default, Serialize, Deserialize)]
+    #[derive(Clone, Default, Serialize, Deserialize)]
     struct Db {
         pub next_pk: u64,
         pub emails: HashMap<u64, Email>,
     }
 
-    static DB: Lazy<RwLock<Db>> = Lazy::new(Default::default);
+    static DB: Lazy<YamlStore<Db>> = Lazy::new(|| YamlStore::new(consts::EMAILS_DB_PATH));
 
-    pub fn add(to: &str, subject: &str, body: &str) -> Result<()> {
-        let mut db = DB.write().or(Err(anyhow!("DB poisoned")))?;
+    pub fn add(from: &str, to: &str, subject: &str, body: &str, headers: Vec<(String, String)>) -> Result<()> {
+        DB.update(|db| {
+            let pk = db.next_pk;
+            db.next_pk += 1;
+            let email = Email {
+                pk,
+                from: from.to_string(),
+                to: to.to_string(),
+                subject: subject.to_string(),
+                body: body.to_string(),
+                headers,
+            };
 
-        let pk = db.next_pk;
-        db.next_pk += 1;
-        let email = Email {
-            pk,
-            to: to.to_string(),
-            subject: subject.to_string(),
-            body: body.to_string(),
-        };
+            db.emails.insert(pk, email);
+            Ok(())
+        })
+    }
 
-        db.emails.insert(pk, email);
-        save(&db)?;
-        Ok(())
+    /// Emails envoyés à une adresse, du plus ancien au plus récent
+    #[cfg(test)]
+    pub fn sent_to(to: &str) -> Vec<Email> {
+        let mut emails: Vec<Email> = DB
+            .read(|db| db.emails.values().filter(|email| email.to == to).cloned().collect())
+            .unwrap();
+        emails.sort_by_key(|email| email.pk);
+        emails
+    }
+
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
     }
+}
+
+/// Gestion des codes d'invitation à usage unique
+pub mod invite {
+    use super::*;
+    use once_cell::sync::Lazy;
+
+    #[derive(Clone, Serialize, Deserialize, Debug, Default)]
+    pub struct Invite {
+        pub used_by: Option<String>,
+    }
+
+    static DB: Lazy<YamlStore<HashMap<String, Invite>>> = Lazy::new(|| YamlStore::new(consts::INVITES_DB_PATH));
 
-    pub fn load() -> Result<()> {
-        super::load(&DB, consts::EMAILS_DB_PATH)
+    pub fn generate() -> Result<String> {
+        let code = uuid::Uuid::new_v4().simple().to_string();
+        DB.insert(code.clone(), Invite::default())?;
+        Ok(code)
     }
 
-    fn save(db: &Db) -> Result<()> {
-        super::save(db, consts::EMAILS_DB_PATH)
+    /// Indique si le code existe et n'a pas encore été utilisé
+    pub fn is_valid(code: &str) -> bool {
+        DB.get(code).is_some_and(|invite| invite.used_by.is_none())
+    }
+
+    /// Marque le code comme utilisé ; échoue s'il est inconnu ou déjà consommé
+    pub fn consume(code: &str, email: &str) -> Result<()> {
+        DB.update(|db| {
+            let invite = db.get_mut(code).ok_or_else(|| anyhow!("Invite not found"))?;
+            if invite.used_by.is_some() {
+                return Err(anyhow!("Invite already used"));
+            }
+
+            invite.used_by = Some(email.to_string());
+            Ok(())
+        })
+    }
+
+    /// Rend le code réutilisable si `email` l'a consommé sans que son inscription aboutisse
+    pub fn release(code: &str, email: &str) -> Result<()> {
+        DB.update(|db| {
+            if let Some(invite) = db.get_mut(code).filter(|invite| invite.used_by.as_deref() == Some(email)) {
+                invite.used_by = None;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
     }
 }
 
-pub mod post {
+/// Signalements de posts, en attente de modération
+pub mod flag {
     use super::*;
+    use once_cell::sync::Lazy;
+    use uuid::Uuid;
 
     #[derive(Clone, Serialize, Deserialize, Debug)]
-    pub struct Post {
-        pub id: String,
-        pub text: String,
-        pub image_path: String,
-        pub likes: i32,
+    pub struct Flag {
+        pub reporter: String,
+        pub reason: String,
+        pub created_at: u64,
+    }
+
+    static DB: Lazy<YamlStore<HashMap<Uuid, Vec<Flag>>>> = Lazy::new(|| YamlStore::new(consts::FLAGS_DB_PATH));
+
+    /// Signale un post ; un même utilisateur ne compte qu'une fois par post.
+    /// Retourne `false` si `reporter` avait déjà signalé ce post.
+    pub fn add(post_id: Uuid, reporter: &str, reason: &str) -> Result<bool> {
+        DB.update(|db| {
+            let flags = db.entry(post_id).or_default();
+            if flags.iter().any(|flag| flag.reporter == reporter) {
+                return Ok(false);
+            }
+
+            flags.push(Flag {
+                reporter: reporter.to_string(),
+                reason: reason.to_string(),
+                created_at: now(),
+            });
+            Ok(true)
+        })
+    }
+
+    /// Signalements en attente, par post
+    pub fn pending() -> Result<HashMap<Uuid, Vec<Flag>>> {
+        DB.read(|db| db.clone())
+    }
+
+    /// Retire les signalements d'un post, une fois traités
+    pub fn clear(post_id: &Uuid) -> Result<()> {
+        DB.remove(post_id).map(|_| ())
+    }
+
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
     }
 }
 
-/// Fonctions de sauvegarde et chargement YAML
-fn save<T: Serialize>(db: &T, path: &str) -> Result<()> {
-    let path_obj = Path::new(path);
+/// Images uploadées, rattachées au compte qui les a envoyées
+pub mod upload {
+    use super::*;
+    use once_cell::sync::Lazy;
+    use uuid::Uuid;
+
+    #[derive(Clone, Serialize, Deserialize, Debug)]
+    pub struct Upload {
+        pub owner: String,
+        /// Nom public, aléatoire, du fichier servi depuis le dossier des uploads : l'URL ne révèle
+        /// rien du contenu. Les anciens uploads gardent leur nom d'origine.
+        pub filename: String,
+        /// SHA-256 du contenu, stocké une seule fois hors du dossier servi (`None` pour les anciens uploads)
+        #[serde(default)]
+        pub digest: Option<String>,
+        /// Nom d'origine, validé, uniquement pour l'affichage
+        pub name: Option<String>,
+        /// Taille en octets (0 pour les anciens uploads)
+        #[serde(default)]
+        pub size: u64,
+        /// Date de l'upload, en secondes depuis l'epoch Unix (0 pour les anciens uploads)
+        #[serde(default)]
+        pub created_at: u64,
+    }
 
-    // Crée le dossier parent s'il n'existe pas
-    if let Some(parent_dir) = path_obj.parent() {
-        if !parent_dir.exists() {
-            create_dir_all(parent_dir).or(Err(anyhow!("Failed to create directory")))?;
+    static DB: Lazy<YamlStore<HashMap<Uuid, Upload>>> = Lazy::new(|| YamlStore::new(consts::UPLOADS_DB_PATH));
+
+    /// Enregistre un nouvel upload de `size` octets pour `owner`, dont le contenu a pour empreinte `digest`.
+    /// `source` devient le contenu partagé s'il n'est pas encore stocké ; l'upload est servi
+    /// sous un nom aléatoire, lien vers ce contenu.
+    pub fn create(owner: &str, name: Option<String>, digest: &str, size: u64, source: &Path) -> Result<(Uuid, Upload)> {
+        let id = Uuid::new_v4();
+        let upload = Upload {
+            owner: owner.to_string(),
+            filename: format!("{}.jpg", Uuid::new_v4().simple()),
+            digest: Some(digest.to_string()),
+            name,
+            size,
+            created_at: now(),
+        };
+
+        // Les fichiers sont mis en place sous le verrou de la base, pour ne pas croiser
+        // la suppression de la dernière référence au même contenu
+        let blob = blob_path(digest);
+        let public = resolve(consts::UPLOADS_DIR).join(&upload.filename);
+        let mut created_blob = false;
+        let result = DB.update(|db| {
+            if !blob.exists() {
+                create_dir_all(resolve(consts::UPLOADS_BLOBS_DIR))?;
+                std::fs::rename(source, &blob)?;
+                created_blob = true;
+            }
+            std::fs::hard_link(&blob, &public).or_else(|_| std::fs::copy(&blob, &public).map(|_| ()))?;
+            db.insert(id, upload.clone());
+            Ok((id, upload.clone()))
+        });
+
+        // Sans entrée enregistrée, aucun fichier ne doit rester
+        if result.is_err() {
+            let _ = std::fs::remove_file(&public);
+            if created_blob {
+                let _ = std::fs::remove_file(&blob);
+            }
         }
+        result
     }
 
-    let file = File::create(path_obj)?;
-    to_writer(file, db).or(Err(anyhow!("Failed to serialize DB")))?;
-    Ok(())
+    /// Emplacement du contenu d'empreinte `digest`, hors du dossier servi
+    pub fn blob_path(digest: &str) -> PathBuf {
+        resolve(consts::UPLOADS_BLOBS_DIR).join(format!("{}.jpg", digest))
+    }
+
+    pub fn get(id: &Uuid) -> Option<Upload> {
+        DB.get(id)
+    }
+
+    /// Uploads appartenant à `owner`
+    pub fn owned_by(owner: &str) -> Result<Vec<(Uuid, Upload)>> {
+        DB.read(|db| {
+            db.iter()
+                .filter(|(_, upload)| upload.owner == owner)
+                .map(|(id, upload)| (*id, upload.clone()))
+                .collect()
+        })
+    }
+
+    /// Uploads datés d'avant `cutoff` ; les anciens uploads, sans date, ne sont jamais retenus
+    pub fn created_before(cutoff: u64) -> Result<Vec<Uuid>> {
+        DB.read(|db| {
+            db.iter()
+                .filter(|(_, upload)| upload.created_at != 0 && upload.created_at < cutoff)
+                .map(|(id, _)| *id)
+                .collect()
+        })
+    }
+
+    /// Antidate un upload
+    #[cfg(test)]
+    pub fn set_created_at(id: &Uuid, created_at: u64) -> Result<()> {
+        DB.update(|db| {
+            if let Some(upload) = db.get_mut(id) {
+                upload.created_at = created_at;
+            }
+            Ok(())
+        })
+    }
+
+    /// Supprime l'upload et son fichier public ; le contenu n'est supprimé qu'avec la dernière référence.
+    /// Retourne l'entrée supprimée.
+    pub fn remove(id: &Uuid) -> Result<Option<Upload>> {
+        DB.update(|db| {
+            let Some(upload) = db.remove(id) else {
+                return Ok(None);
+            };
+
+            // Les anciens uploads d'un même contenu partagent leur nom public
+            if !db.values().any(|other| other.filename == upload.filename) {
+                remove_if_exists(&resolve(consts::UPLOADS_DIR).join(&upload.filename))?;
+            }
+            if let Some(digest) = &upload.digest {
+                if !db.values().any(|other| other.digest.as_ref() == Some(digest)) {
+                    remove_if_exists(&blob_path(digest))?;
+                }
+            }
+            Ok(Some(upload))
+        })
+    }
+
+    fn remove_if_exists(path: &Path) -> Result<()> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn load() -> Result<(), LoadError> {
+        DB.load()
+    }
+}
+
+/// Horodatage courant en secondes depuis l'epoch Unix
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Supprime les comptes restés non validés au-delà du délai de grâce, ainsi que leurs tokens
+/// de validation inutilisés ; retourne le nombre de comptes supprimés
+pub fn purge_unverified_accounts() -> Result<usize> {
+    let cutoff = now().saturating_sub(config::get().unverified_grace_secs);
+    let removed = user::purge_unverified(cutoff)?;
+    token::revoke(&removed, token::TokenKind::Validation)?;
+    Ok(removed.len())
+}
+
+/// Résout un chemin relatif au dossier de données configuré
+pub(crate) fn resolve(path: &str) -> PathBuf {
+    config::get().data_dir.join(path)
+}
+
+/// Crée le dossier de données s'il n'existe pas encore
+pub fn init_data_dir() -> Result<()> {
+    create_dir_all(config::get().data_dir.as_path())
+        .or(Err(anyhow!("Failed to create data directory")))
 }
 
-fn load<T: for<'de> Deserialize<'de> + Default>(db: &RwLock<T>, path: &str) -> Result<()> {
-    // Chargement de la base de données depuis le fichier YAML
-    if let Ok(file) = File::open(path) {
-        let db_content: T = serde_yaml::from_reader(file).unwrap_or_default();
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = db_content;
-    } else {
-        let mut db = db.write().or(Err(anyhow!("DB poisoned")))?;
-        *db = T::default();
-    }
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_data_dir_is_configurable() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-data-{}", uuid::Uuid::new_v4()));
+        let config = config::Config {
+            data_dir: data_dir.clone(),
+            ..Default::default()
+        };
+
+        config::scope(config, async {
+            init_data_dir().unwrap();
+            invite::generate().unwrap();
+        })
+        .await;
+
+        assert!(data_dir.is_dir());
+        assert!(data_dir.join(consts::INVITES_DB_PATH).is_file());
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_leaves_no_file() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-data-{}", uuid::Uuid::new_v4()));
+        let config = config::Config {
+            data_dir: data_dir.clone(),
+            ..Default::default()
+        };
+        // La base ne peut pas être écrite : son chemin est un dossier
+        std::fs::create_dir_all(data_dir.join(consts::UPLOADS_DB_PATH)).unwrap();
+        std::fs::create_dir_all(data_dir.join(consts::UPLOADS_DIR)).unwrap();
+        let source = data_dir.join("photo.part");
+        std::fs::write(&source, b"jpeg").unwrap();
+
+        let digest = format!("{:x}", uuid::Uuid::new_v4().as_u128());
+        let (result, blob) = config::scope(config, async {
+            (upload::create("owner@example.com", None, &digest, 4, &source), upload::blob_path(&digest))
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(!blob.exists());
+        assert_eq!(std::fs::read_dir(data_dir.join(consts::UPLOADS_DIR)).unwrap().count(), 0);
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_unverified_accounts_are_purged() {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let old = format!("old-{}@example.com", id);
+        let recent = format!("recent-{}@example.com", id);
+        let verified = format!("verified-{}@example.com", id);
+
+        for email in [&old, &recent, &verified] {
+            user::create(email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+            token::generate(email, token::TokenKind::Validation).unwrap();
+        }
+        let long_ago = now() - config::get().unverified_grace_secs - 60;
+        user::set_created_at(&old, long_ago).unwrap();
+        user::set_created_at(&verified, long_ago).unwrap();
+        user::verify(&verified).unwrap();
+
+        assert!(purge_unverified_accounts().unwrap() >= 1);
+
+        assert!(user::get(&old).unwrap().is_none());
+        assert!(token::issued_to(&old).is_empty());
+        assert!(user::get(&recent).unwrap().is_some());
+        assert_eq!(token::issued_to(&recent).len(), 1);
+        assert!(user::get(&verified).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_get_user() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        assert!(user::get(&email).unwrap().is_none());
+
+        let handle = uuid::Uuid::new_v4();
+        let before = now();
+        user::create(&email, Some("Jean"), None, handle).unwrap();
+        let created = user::get(&email).unwrap().unwrap();
+        assert_eq!(created.email, email);
+        assert_eq!(created.first_name.as_deref(), Some("Jean"));
+        assert_eq!(created.last_name, None);
+        assert!(!created.verified);
+        assert!(created.passkeys.is_empty());
+        assert_eq!(created.role, user::Role::User);
+        assert_eq!(created.user_handle, Some(handle));
+        assert!(created.created_at >= before);
+    }
+
+    #[test]
+    fn test_released_invite_is_reusable() {
+        let code = invite::generate().unwrap();
+        invite::consume(&code, "jean@example.com").unwrap();
+
+        // Seul le compte qui a consommé le code peut le libérer
+        invite::release(&code, "autre@example.com").unwrap();
+        assert!(!invite::is_valid(&code));
+        invite::release(&code, "jean@example.com").unwrap();
+        assert!(invite::is_valid(&code));
+    }
+
+    #[test]
+    fn test_user_yaml_round_trip() {
+        let email = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), uuid::Uuid::new_v4()).unwrap();
+        user::set_passkey(&email, crate::utils::webauthn::tests::test_passkey()).unwrap();
+        user::verify(&email).unwrap();
+        let stored = user::get(&email).unwrap().unwrap();
+
+        let yaml = serde_yaml::to_string(&stored).unwrap();
+        let loaded: user::User = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(loaded.email, stored.email);
+        assert_eq!(loaded.first_name, stored.first_name);
+        assert_eq!(loaded.last_name, stored.last_name);
+        assert!(loaded.verified);
+        assert_eq!(loaded.passkeys.len(), 1);
+        assert_eq!(loaded.passkeys[0].cred_id(), stored.passkeys[0].cred_id());
+        assert_eq!(loaded.role, stored.role);
+        assert_eq!(loaded.user_handle, stored.user_handle);
+        assert_eq!(loaded.created_at, stored.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_yaml_is_moved_aside() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-data-{}", uuid::Uuid::new_v4()));
+        let path = data_dir.join(consts::USERS_DB_PATH);
+
+        let config = config::Config {
+            data_dir: data_dir.clone(),
+            ..Default::default()
+        };
+        let db: YamlStore<HashMap<String, u64>> = YamlStore::new(consts::USERS_DB_PATH);
+        let result = config::scope(config.clone(), async {
+            db.insert("stale".to_string(), 1).unwrap();
+            std::fs::write(&path, "jean@example.com:\n  first_name: [Jean\n").unwrap();
+            db.load()
+        })
+        .await;
+
+        // La base repart vide et le fichier d'origine est conservé à côté
+        let Err(LoadError::Corrupt { backup, .. }) = result else {
+            panic!("expected a corrupt database error");
+        };
+        assert_eq!(db.read(|map| map.len()).unwrap(), 0);
+        assert!(!path.exists());
+        assert!(backup.starts_with(&data_dir));
+        assert!(backup.to_string_lossy().contains(".corrupt."));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "jean@example.com:\n  first_name: [Jean\n");
+
+        // Le chargement suivant démarre normalement
+        assert!(config::scope(config, async { db.load() }).await.is_ok());
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
 }
@@ -0,0 +1,270 @@
+//! Stockage générique d'une base en mémoire, persistée dans un fichier YAML du dossier de données.
+//! Chaque modification est appliquée sur une copie, écrite sur disque de façon atomique
+//! (fichier temporaire puis renommage), et n'est publiée en mémoire qu'une fois l'écriture réussie.
+
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, File},
+    hash::Hash,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use super::{now, resolve};
+
+/// Erreur de chargement d'une base YAML
+#[derive(Debug)]
+pub enum LoadError {
+    /// Le fichier existe mais n'a pas pu être lu
+    Io(std::io::Error),
+    /// Le contenu est invalide : le fichier a été mis de côté et la base repart vide
+    Corrupt { backup: PathBuf, reason: String },
+    Poisoned,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "Failed to read database: {}", err),
+            LoadError::Corrupt { backup, reason } => write!(
+                f,
+                "Corrupt database moved to {} ({}); starting with an empty dataset",
+                backup.display(),
+                reason
+            ),
+            LoadError::Poisoned => write!(f, "DB poisoned"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Base `T` associée à un fichier YAML, relatif au dossier de données
+pub struct YamlStore<T> {
+    path: &'static str,
+    data: RwLock<T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default + Clone> YamlStore<T> {
+    pub fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            data: RwLock::new(T::default()),
+        }
+    }
+
+    /// Recharge la base depuis le disque ; un fichier corrompu est mis de côté et la base repart vide
+    pub fn load(&self) -> Result<(), LoadError> {
+        let (content, result) = match read_yaml(&resolve(self.path)) {
+            Ok(content) => (content, Ok(())),
+            Err(err @ LoadError::Corrupt { .. }) => (T::default(), Err(err)),
+            Err(err) => return Err(err),
+        };
+
+        *self.data.write().or(Err(LoadError::Poisoned))? = content;
+        result
+    }
+
+    /// Lit la base sous le verrou de lecture
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R> {
+        let data = self.data.read().or(Err(anyhow!("DB poisoned")))?;
+        Ok(f(&data))
+    }
+
+    /// Modifie la base sous le verrou d'écriture, qui sérialise les modifications concurrentes.
+    /// Si `f` échoue ou si l'écriture sur disque échoue, la base reste inchangée.
+    pub fn update<R, E: From<anyhow::Error>>(&self, f: impl FnOnce(&mut T) -> Result<R, E>) -> Result<R, E> {
+        let mut data = self.data.write().or(Err(anyhow!("DB poisoned")))?;
+        let mut updated = data.clone();
+        let result = f(&mut updated)?;
+        save(&updated, &resolve(self.path))?;
+        *data = updated;
+        Ok(result)
+    }
+}
+
+/// Accès direct aux bases indexées par clé
+impl<K, V> YamlStore<HashMap<K, V>>
+where
+    K: Serialize + DeserializeOwned + Eq + Hash + Clone,
+    V: Serialize + DeserializeOwned + Clone,
+{
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.read(|map| map.get(key).cloned()).ok().flatten()
+    }
+
+    pub fn contains<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.read(|map| map.contains_key(key))
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<()> {
+        self.update(|map| {
+            map.insert(key, value);
+            Ok(())
+        })
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.update(|map| Ok(map.remove(key)))
+    }
+}
+
+/// Lit un fichier YAML ; un fichier absent donne une base vide.
+/// Un fichier illisible est renommé en `<nom>.corrupt.<horodatage>` pour ne pas être écrasé
+/// par la prochaine sauvegarde.
+pub(crate) fn read_yaml<T: DeserializeOwned + Default>(path: &Path) -> Result<T, LoadError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(err) => return Err(LoadError::Io(err)),
+    };
+
+    match serde_yaml::from_reader(file) {
+        Ok(content) => Ok(content),
+        Err(err) => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(format!(".corrupt.{}", now()));
+            let backup = PathBuf::from(backup);
+            std::fs::rename(path, &backup).map_err(LoadError::Io)?;
+            log::error!("Corrupt database {} moved to {}: {}", path.display(), backup.display(), err);
+            Err(LoadError::Corrupt { backup, reason: err.to_string() })
+        }
+    }
+}
+
+/// Écrit le fichier de façon atomique : un lecteur voit l'ancien ou le nouveau contenu, jamais un mélange
+pub(crate) fn save<T: Serialize>(db: &T, path: &Path) -> Result<()> {
+    // Crée le dossier parent s'il n'existe pas
+    if let Some(parent_dir) = path.parent() {
+        if !parent_dir.exists() {
+            create_dir_all(parent_dir).or(Err(anyhow!("Failed to create directory")))?;
+        }
+    }
+
+    let content = serde_yaml::to_string(db).or(Err(anyhow!("Failed to serialize DB")))?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    fn store_in(data_dir: &Path) -> (config::Config, YamlStore<HashMap<String, u32>>) {
+        let config = config::Config {
+            data_dir: data_dir.to_path_buf(),
+            ..Default::default()
+        };
+        (config, YamlStore::new("store.yaml"))
+    }
+
+    #[tokio::test]
+    async fn test_crud_persists() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-store-{}", uuid::Uuid::new_v4()));
+        let (config, store) = store_in(&data_dir);
+
+        config::scope(config.clone(), async {
+            store.insert("a".to_string(), 1).unwrap();
+            store.insert("b".to_string(), 2).unwrap();
+            store.update(|map| -> Result<()> {
+                *map.get_mut("a").unwrap() += 10;
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(store.remove("b").unwrap(), Some(2));
+            assert_eq!(store.remove("b").unwrap(), None);
+            assert_eq!(store.get("a"), Some(11));
+            assert!(!store.contains("b").unwrap());
+        })
+        .await;
+
+        // Une nouvelle instance relit le même contenu
+        let (_, reloaded) = store_in(&data_dir);
+        config::scope(config, async { reloaded.load().unwrap() }).await;
+        assert_eq!(reloaded.get("a"), Some(11));
+        assert_eq!(reloaded.read(|map| map.len()).unwrap(), 1);
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_update_changes_nothing() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-store-{}", uuid::Uuid::new_v4()));
+        let (config, store) = store_in(&data_dir);
+
+        config::scope(config, async {
+            store.insert("a".to_string(), 1).unwrap();
+
+            // Échec de la modification : rien n'est publié, même partiellement
+            let result = store.update(|map| -> Result<()> {
+                map.insert("b".to_string(), 2);
+                Err(anyhow!("refused"))
+            });
+            assert!(result.is_err());
+            assert_eq!(store.get("b"), None);
+
+            // Échec de l'écriture : la mémoire reste cohérente avec le disque
+            std::fs::remove_file(resolve("store.yaml")).unwrap();
+            std::fs::create_dir(resolve("store.yaml.tmp")).unwrap();
+            assert!(store.insert("c".to_string(), 3).is_err());
+            assert_eq!(store.get("c"), None);
+        })
+        .await;
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_updates_are_serialized() {
+        let data_dir = std::env::temp_dir().join(format!("lab02-store-{}", uuid::Uuid::new_v4()));
+        let store: YamlStore<HashMap<String, u32>> = YamlStore::new("store.yaml");
+        let config = std::sync::Arc::new(config::Config {
+            data_dir: data_dir.clone(),
+            ..Default::default()
+        });
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                let (store, config) = (&store, config.clone());
+                scope.spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+                    runtime.block_on(config::scope((*config).clone(), async {
+                        for _ in 0..10 {
+                            store
+                                .update(|map| -> Result<()> {
+                                    *map.entry("count".to_string()).or_default() += 1;
+                                    Ok(())
+                                })
+                                .unwrap();
+                        }
+                    }));
+                });
+            }
+        });
+
+        assert_eq!(store.get("count"), Some(80));
+        let on_disk: HashMap<String, u32> = read_yaml(&data_dir.join("store.yaml")).unwrap();
+        assert_eq!(on_disk.get("count"), Some(&80));
+        std::fs::remove_dir_all(data_dir).unwrap();
+    }
+}
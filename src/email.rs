@@ -1,12 +1,194 @@
 //! Gestion des fonctionnalités liées aux emails, telles que l'envoi et la création de liens de vérification.
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use log::info;
-use crate::database;
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+use validator::ValidateEmail;
+use crate::config::{self, Config};
+use crate::{consts, database};
 
 /// Envoie un email simulé en ajoutant ses détails à la base de données.
 pub fn send_mail(to: &str, subject: &str, body: &str) -> Result<()> {
     info!("Sending an email");
-    database::email::add(to, subject, body)?;
+    let config = config::get();
+    let from = from_header(&config)?;
+    let subject = subject_line(&config, subject)?;
+    database::email::add(&from, to, &subject, body, message_headers(&config)?)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Vérifie au démarrage les adresses utilisées dans les en-têtes ; retourne tous les problèmes trouvés
+pub fn check_config(config: &Config) -> Result<(), Vec<anyhow::Error>> {
+    let mut problems = Vec::new();
+    if let Err(err) = from_header(config) {
+        problems.push(err);
+    }
+    // Sans adresse de réponse, c'est l'adresse d'expédition qui sert, déjà vérifiée
+    if config.mail_reply_to.is_some() {
+        if let Err(err) = reply_to(config) {
+            problems.push(err);
+        }
+    }
+    if let Err(err) = subject_line(config, "Subject") {
+        problems.push(err);
+    }
+    if problems.is_empty() { Ok(()) } else { Err(problems) }
+}
+
+/// Sujet envoyé : le préfixe configuré suivi du sujet, qui ne peut pas être vide
+fn subject_line(config: &Config, subject: &str) -> Result<String> {
+    if subject.trim().is_empty() {
+        return Err(anyhow!("Email subject is empty"));
+    }
+    let prefix = config.mail_subject_prefix.as_str();
+    if prefix.chars().chain(subject.chars()).any(char::is_control) {
+        return Err(anyhow!("Invalid MAIL_SUBJECT_PREFIX or subject"));
+    }
+    Ok(format!("{}{}", prefix, subject))
+}
+
+/// En-têtes attendus par les serveurs de réception (Message-ID unique, Date, MIME)
+fn message_headers(config: &Config) -> Result<Vec<(String, String)>> {
+    let message_id = format!("<{}@{}>", uuid::Uuid::new_v4().simple(), consts::DOMAIN);
+    let date = OffsetDateTime::now_utc().format(&Rfc2822)?;
+
+    Ok(vec![
+        ("Message-ID".to_string(), message_id),
+        ("Date".to_string(), date),
+        ("MIME-Version".to_string(), "1.0".to_string()),
+        ("Content-Type".to_string(), "text/plain; charset=utf-8".to_string()),
+        ("Reply-To".to_string(), reply_to(config)?),
+    ])
+}
+
+/// Adresse de réponse configurée, ou à défaut l'adresse d'expédition
+fn reply_to(config: &Config) -> Result<String> {
+    let address = config.mail_reply_to.as_deref().unwrap_or(&config.mail_from);
+    if !address.validate_email() {
+        return Err(anyhow!("Invalid MAIL_REPLY_TO address: {}", address));
+    }
+    Ok(address.to_string())
+}
+
+/// Construit l'en-tête `From` à partir de la configuration, en refusant une adresse invalide
+pub fn from_header(config: &Config) -> Result<String> {
+    if !config.mail_from.validate_email() {
+        return Err(anyhow!("Invalid MAIL_FROM address: {}", config.mail_from));
+    }
+
+    let name = config.mail_from_name.trim();
+    if name.chars().any(|c| c.is_control() || c == '"') {
+        return Err(anyhow!("Invalid MAIL_FROM_NAME"));
+    }
+
+    if name.is_empty() {
+        Ok(format!("<{}>", config.mail_from))
+    } else {
+        Ok(format!("\"{}\" <{}>", name, config.mail_from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sent_message_uses_configured_from() {
+        let to = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let config = Config {
+            mail_from: "security@lab02.example".to_string(),
+            mail_from_name: "Lab02 Security".to_string(),
+            ..Default::default()
+        };
+
+        config::scope(config, async { send_mail(&to, "Hello", "Body").unwrap() }).await;
+
+        let sent = database::email::sent_to(&to);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].from, "\"Lab02 Security\" <security@lab02.example>");
+    }
+
+    #[tokio::test]
+    async fn test_subject_prefix() {
+        let to = format!("{}@example.com", uuid::Uuid::new_v4().simple());
+        let config = Config {
+            mail_subject_prefix: "[MyApp] ".to_string(),
+            ..Default::default()
+        };
+
+        config::scope(config.clone(), async {
+            send_mail(&to, "Account Validation", "Body").unwrap();
+            assert!(send_mail(&to, "  ", "Body").is_err());
+        })
+        .await;
+
+        let sent = database::email::sent_to(&to);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "[MyApp] Account Validation");
+
+        // Un préfixe sur plusieurs lignes injecterait des en-têtes
+        let injected = Config {
+            mail_subject_prefix: "[MyApp]\r\nBcc: victim@example.com ".to_string(),
+            ..Default::default()
+        };
+        assert!(check_config(&injected).is_err());
+        assert!(check_config(&config).is_ok());
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("missing {}", name))
+    }
+
+    #[test]
+    fn test_message_headers() {
+        let config = Config {
+            mail_reply_to: Some("support@lab02.example".to_string()),
+            ..Default::default()
+        };
+        let headers = message_headers(&config).unwrap();
+
+        let message_id = header(&headers, "Message-ID");
+        let id = message_id
+            .strip_prefix('<')
+            .and_then(|id| id.strip_suffix(&format!("@{}>", consts::DOMAIN)))
+            .unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(message_headers(&config).unwrap()[0].1, message_id);
+
+        assert!(OffsetDateTime::parse(header(&headers, "Date"), &Rfc2822).is_ok());
+        assert_eq!(header(&headers, "MIME-Version"), "1.0");
+        assert_eq!(header(&headers, "Reply-To"), "support@lab02.example");
+
+        // Sans configuration, les réponses vont à l'expéditeur
+        let headers = message_headers(&Config::default()).unwrap();
+        assert_eq!(header(&headers, "Reply-To"), Config::default().mail_from);
+    }
+
+    #[test]
+    fn test_invalid_from_is_rejected() {
+        let invalid = Config {
+            mail_from: "not-an-email".to_string(),
+            ..Default::default()
+        };
+        assert!(from_header(&invalid).is_err());
+
+        let injected = Config {
+            mail_from_name: "Lab02\r\nBcc: victim@example.com".to_string(),
+            ..Default::default()
+        };
+        assert!(from_header(&injected).is_err());
+
+        let invalid_reply_to = Config {
+            mail_reply_to: Some("nope".to_string()),
+            ..Default::default()
+        };
+        assert!(check_config(&invalid_reply_to).is_err());
+
+        assert!(from_header(&Config::default()).is_ok());
+    }
+}
@@ -3,30 +3,56 @@
 //! et démarre le serveur web avec Axum.
 
 mod backend;
+mod config;
 mod database;
 mod utils;
 mod email;
 mod consts;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use axum::Extension;
 use dotenv::dotenv;
 use handlebars::Handlebars;
 use log::info;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use crate::{
     consts::HTTP_PORT,
-    backend::handlers_auth::{load_posts_from_file, save_posts_to_file},
+    backend::handlers_auth::{load_posts_from_file, repair_legacy_posts, save_posts_to_file},
 };
 
-// Initialisation de Handlebars pour le rendu des templates
+/// Templates chargés par `main` avant tout rendu : un template invalide arrête le démarrage proprement
+static TEMPLATES: OnceCell<Handlebars<'static>> = OnceCell::new();
+
+// Handlebars pour le rendu des templates ; vide si `main` ne les a pas chargés,
+// ce que `check_templates` signale au démarrage
+#[cfg(not(test))]
+static HBS: Lazy<Handlebars> = Lazy::new(|| TEMPLATES.get().cloned().unwrap_or_default());
+
+// Les tests ne passent pas par `main` : les templates sont chargés à la première utilisation
+#[cfg(test)]
 static HBS: Lazy<Handlebars> = Lazy::new(|| {
-    let mut hbs = Handlebars::new();
-    hbs.register_templates_directory(".hbs", "templates/")
-        .expect("Could not register template directory");
-    hbs
+    let config = config::get();
+    load_templates(&config.templates_dir, config.templates_hot_reload).expect("Could not register template directory")
 });
 
+/// Charge les templates `.hbs` du dossier ; en mode dev, ils sont relus à chaque rendu
+fn load_templates(dir: &std::path::Path, hot_reload: bool) -> Result<Handlebars<'static>, Box<handlebars::TemplateError>> {
+    let mut hbs = Handlebars::new();
+    hbs.set_dev_mode(hot_reload);
+    hbs.register_templates_directory(".hbs", dir).map_err(Box::new)?;
+    Ok(hbs)
+}
+
+/// Vérifie que chaque template requis est enregistré ; renvoie la liste des manquants
+fn check_templates(hbs: &Handlebars, required: &[&str]) -> Result<(), Vec<String>> {
+    let missing: Vec<String> = required
+        .iter()
+        .filter(|name| !hbs.has_template(name))
+        .map(|name| name.to_string())
+        .collect();
+    if missing.is_empty() { Ok(()) } else { Err(missing) }
+}
+
 #[tokio::main]
 async fn main() {
     // Charger les variables d'environnement
@@ -34,6 +60,65 @@ async fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
         .init();
+    // Une valeur illisible dans l'environnement empêche le démarrage, avec tous les autres problèmes
+    let mut config = match config::Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration invalide, {}", e);
+            std::process::exit(1);
+        }
+    };
+    if std::env::args().any(|arg| arg == "--strict") {
+        config.strict_security = true;
+    }
+    config::set(config);
+
+    // Signaler les réglages non sûrs ; en mode strict, refuser de démarrer
+    for issue in config::get().security_issues() {
+        log::warn!("SECURITY WARNING: {}", issue);
+    }
+    if let Err(e) = config::get().check_security() {
+        eprintln!("Configuration non sûre refusée en mode strict: {}", e);
+        std::process::exit(1);
+    }
+
+    // Refuser de démarrer avec une configuration invalide, en listant tous les problèmes
+    if let Err(e) = config::get().validate() {
+        eprintln!("Configuration invalide, {}", e);
+        std::process::exit(1);
+    }
+
+    // Refuser de démarrer si un template ne se compile pas, plutôt que de paniquer au premier rendu
+    let templates_dir = config::get().templates_dir.clone();
+    match load_templates(&templates_dir, config::get().templates_hot_reload) {
+        Ok(hbs) => {
+            let _ = TEMPLATES.set(hbs);
+        }
+        Err(e) => {
+            eprintln!(
+                "Template invalide dans {}: {}: {}",
+                templates_dir.display(),
+                e.template_name.as_deref().unwrap_or("?"),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+
+    // Refuser de démarrer s'il manque des templates, plutôt que d'échouer à chaque requête
+    if let Err(missing) = check_templates(&HBS, &consts::REQUIRED_TEMPLATES) {
+        eprintln!(
+            "Templates manquants dans {}: {}",
+            config::get().templates_dir.display(),
+            missing.join(", ")
+        );
+        std::process::exit(1);
+    }
+
+    // Créer le dossier de données si nécessaire
+    if let Err(e) = database::init_data_dir() {
+        eprintln!("Erreur lors de la création du dossier de données: {}", e);
+    }
 
     // Charger les données des posts
     if let Err(e) = load_posts_from_file() {
@@ -51,10 +136,61 @@ async fn main() {
         Err(e) => eprintln!("Erreur lors du chargement de la base emails: {}", e),
     }
 
+    match database::invite::load() {
+        Ok(_) => info!("Base de données invitations chargée avec succès"),
+        Err(e) => eprintln!("Erreur lors du chargement de la base invitations: {}", e),
+    }
+
+    match database::upload::load() {
+        Ok(_) => info!("Base de données uploads chargée avec succès"),
+        Err(e) => eprintln!("Erreur lors du chargement de la base uploads: {}", e),
+    }
+
+    match database::flag::load() {
+        Ok(_) => info!("Base de données signalements chargée avec succès"),
+        Err(e) => eprintln!("Erreur lors du chargement de la base signalements: {}", e),
+    }
+
+    match database::token::load() {
+        Ok(_) => info!("Base de données tokens chargée avec succès"),
+        Err(e) => eprintln!("Erreur lors du chargement de la base tokens: {}", e),
+    }
+
+    // Les anciens posts au contenu invalide sont masqués et signalés (après le chargement des signalements)
+    match repair_legacy_posts() {
+        Ok(0) => {}
+        Ok(count) => info!("{} ancien(s) post(s) masqué(s) et signalé(s) pour revue", count),
+        Err(e) => eprintln!("Erreur lors de la vérification des posts: {}", e),
+    }
+
     // Configurer Handlebars comme extension pour le routeur
     let hbs = Arc::new(HBS.clone());
     let app = backend::router::get_router().layer(Extension(hbs));
 
+    // Purger périodiquement les tokens consommés ou expirés, les comptes jamais validés et les uploads sans post
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(Duration::from_secs(consts::TOKEN_PURGE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match database::token::purge_expired() {
+                Ok(removed) => info!("{} token(s) purgé(s)", removed),
+                Err(e) => eprintln!("Erreur lors de la purge des tokens: {}", e),
+            }
+            match database::purge_unverified_accounts() {
+                Ok(removed) => info!("{} compte(s) non validé(s) supprimé(s)", removed),
+                Err(e) => eprintln!("Erreur lors de la purge des comptes non validés: {}", e),
+            }
+            match backend::handlers_auth::purge_unattached_uploads() {
+                Ok(removed) => info!("{} upload(s) sans post supprimé(s)", removed),
+                Err(e) => eprintln!("Erreur lors de la purge des uploads: {}", e),
+            }
+            match backend::handlers_unauth::retry_validation_mails() {
+                Ok(sent) => info!("{} email(s) de validation renvoyé(s)", sent),
+                Err(e) => eprintln!("Erreur lors du renvoi des emails de validation: {}", e),
+            }
+        }
+    });
+
     // Ajouter une gestion de fin pour sauvegarder les posts
     tokio::spawn(async {
         tokio::signal::ctrl_c().await.unwrap();
@@ -65,13 +201,102 @@ async fn main() {
 
     // Démarrer le serveur web
     let addr = SocketAddr::from(([0, 0, 0, 0], HTTP_PORT));
+    let config = config::get();
+
+    // `validate()` refuse déjà une configuration TLS incomplète : ne jamais retomber sur HTTP en clair
+    let tls_paths = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => {
+            eprintln!("Configuration invalide, TLS_CERT_PATH et TLS_KEY_PATH doivent être définis ensemble");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some((cert, key)) = tls_paths {
+        let tls = backend::tls::load_config(cert, key)
+            .await
+            .expect("Failed to load TLS certificate");
+
+        // Le port HTTP ne sert plus qu'à rediriger vers HTTPS
+        if config.redirect_http {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .expect("Failed to open HTTP redirect listener");
+            let redirect = backend::tls::redirect_router(config.https_port);
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, redirect).await {
+                    eprintln!("Erreur du serveur de redirection HTTP: {}", e);
+                }
+            });
+        }
+
+        let https_addr = SocketAddr::from(([0, 0, 0, 0], config.https_port));
+        info!("Listening on {} (HTTPS)", https_addr);
+        let listener = std::net::TcpListener::bind(https_addr)
+            .expect("Failed to open HTTPS listener");
+        backend::tls::serve(listener, app, tls)
+            .await
+            .expect("Failed to serve HTTPS");
+        return;
+    }
+
     info!("Listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
         .expect("Failed to open web server listener");
 
-    axum::serve(listener, app)
+    // Conserver l'adresse du client pour les logs d'abus
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Failed to bind Axum to listener");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_after_edit(hot_reload: bool) -> String {
+        let dir = std::env::temp_dir().join(format!("lab02-templates-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let template = dir.join("page.hbs");
+        std::fs::write(&template, "Hello {{name}}").unwrap();
+
+        let hbs = load_templates(&dir, hot_reload).unwrap();
+        let data = serde_json::json!({ "name": "Jean" });
+        assert_eq!(hbs.render("page", &data).unwrap(), "Hello Jean");
+
+        std::fs::write(&template, "Bonjour {{name}}").unwrap();
+        hbs.render("page", &data).unwrap()
+    }
+
+    #[test]
+    fn test_hot_reload_picks_up_changes() {
+        assert_eq!(render_after_edit(true), "Bonjour Jean");
+        assert_eq!(render_after_edit(false), "Hello Jean");
+    }
+
+    #[test]
+    fn test_missing_template_is_reported() {
+        let dir = std::env::temp_dir().join(format!("lab02-templates-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.hbs"), "Index").unwrap();
+        std::fs::write(dir.join("login.hbs"), "Login").unwrap();
+
+        let hbs = load_templates(&dir, false).unwrap();
+        assert_eq!(
+            check_templates(&hbs, &consts::REQUIRED_TEMPLATES),
+            Err(vec!["home".to_string(), "maintenance".to_string(), "recover".to_string(), "register".to_string()])
+        );
+
+        // Un template qui ne se compile pas est signalé par son nom, sans panique
+        std::fs::write(dir.join("broken.hbs"), "{{#if}}").unwrap();
+        let err = load_templates(&dir, false).unwrap_err();
+        assert_eq!(err.template_name.as_deref(), Some("broken"));
+
+        // Les templates livrés couvrent tout ce qui est requis
+        let shipped = load_templates(std::path::Path::new("templates"), false).unwrap();
+        assert_eq!(check_templates(&shipped, &consts::REQUIRED_TEMPLATES), Ok(()));
+    }
+}
@@ -3,3 +3,10 @@
 //pub(crate) mod input;
 pub(crate) mod webauthn;
 pub(crate) mod input;
+pub(crate) mod abuse;
+pub(crate) mod rate_limit;
+pub(crate) mod pow;
+pub(crate) mod ceremony;
+pub(crate) mod challenge_store;
+pub(crate) mod i18n;
+pub(crate) mod replay_cache;
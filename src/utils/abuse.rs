@@ -0,0 +1,102 @@
+//! Journalisation des échecs suspects (credential stuffing, énumération de comptes).
+//! Les logs contiennent uniquement le type d'événement et l'IP source, jamais de secret.
+
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use tracing::Level;
+use crate::config;
+
+/// Événements pouvant indiquer une tentative d'abus
+#[derive(Clone, Copy, Debug)]
+pub enum AbuseEvent {
+    /// Échec de la vérification WebAuthn lors de la connexion
+    LoginFailed,
+    /// Demande de récupération pour un compte inexistant
+    UnknownRecovery,
+}
+
+impl AbuseEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            AbuseEvent::LoginFailed => "login_failed",
+            AbuseEvent::UnknownRecovery => "unknown_recovery",
+        }
+    }
+}
+
+/// Fenêtre d'une minute limitant le nombre de logs émis
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl Window {
+    fn allow(&mut self, now: Instant, limit: u32) -> bool {
+        if now.duration_since(self.started) >= Duration::from_secs(60) {
+            self.started = now;
+            self.count = 0;
+        }
+        if self.count >= limit {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+static WINDOW: Lazy<Mutex<Window>> = Lazy::new(|| {
+    Mutex::new(Window {
+        started: Instant::now(),
+        count: 0,
+    })
+});
+
+/// Émet un log structuré pour l'événement, selon le niveau et la limite configurés
+pub fn report(event: AbuseEvent, ip: Option<IpAddr>) {
+    let config = config::get();
+    let Some(level) = config.abuse_log_level else {
+        return;
+    };
+
+    let allowed = WINDOW
+        .lock()
+        .map(|mut window| window.allow(Instant::now(), config.abuse_log_per_minute))
+        .unwrap_or(false);
+    if !allowed {
+        return;
+    }
+
+    let ip = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+    macro_rules! emit {
+        ($log:ident) => {
+            tracing::$log!(target: "abuse", event = event.as_str(), ip = %ip, "Suspicious request")
+        };
+    }
+    match level {
+        Level::ERROR => emit!(error),
+        Level::WARN => emit!(warn),
+        Level::INFO => emit!(info),
+        Level::DEBUG => emit!(debug),
+        Level::TRACE => emit!(trace),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_limits_events_per_minute() {
+        let start = Instant::now();
+        let mut window = Window { started: start, count: 0 };
+
+        assert!(window.allow(start, 2));
+        assert!(window.allow(start, 2));
+        assert!(!window.allow(start + Duration::from_secs(59), 2));
+
+        // Une nouvelle minute réinitialise le compteur
+        assert!(window.allow(start + Duration::from_secs(60), 2));
+    }
+}
@@ -0,0 +1,88 @@
+//! Traces des cérémonies WebAuthn (début puis fin), corrélées par l'identifiant d'état.
+//! Seuls le type de cérémonie, l'identifiant d'état, la durée et le résultat sont enregistrés :
+//! jamais l'email, le challenge ou la réponse de l'authentificateur.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+use tracing::Span;
+
+/// Au-delà de cette durée, une cérémonie non terminée est oubliée
+const MAX_CEREMONY_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Type de cérémonie WebAuthn
+#[derive(Clone, Copy, Debug)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn as_str(self) -> &'static str {
+        match self {
+            Ceremony::Registration => "registration",
+            Ceremony::Authentication => "authentication",
+        }
+    }
+}
+
+/// Début des cérémonies en cours, par identifiant d'état
+static STARTED: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+
+/// Span commun au début et à la fin d'une cérémonie
+fn span(ceremony: Ceremony, state_id: &str) -> Span {
+    tracing::info_span!(target: "webauthn", "webauthn_ceremony", ceremony = ceremony.as_str(), state_id)
+}
+
+/// Note le début d'une cérémonie
+pub fn begin(ceremony: Ceremony, state_id: &str) {
+    let now = Instant::now();
+    if let Ok(mut started) = STARTED.lock() {
+        started.retain(|_, at| now.duration_since(*at) < MAX_CEREMONY_AGE);
+        started.insert(state_id.to_string(), now);
+    }
+
+    span(ceremony, state_id).in_scope(|| {
+        tracing::info!(target: "webauthn", ceremony = ceremony.as_str(), state_id, "Ceremony started")
+    });
+}
+
+/// Fin d'une cérémonie : l'événement final est émis à la destruction,
+/// en échec si `succeed` n'a pas été appelé (ex. retour anticipé sur erreur)
+pub struct Completion {
+    ceremony: Ceremony,
+    state_id: String,
+    succeeded: bool,
+}
+
+/// Commence le suivi de la fin d'une cérémonie
+pub fn complete(ceremony: Ceremony, state_id: &str) -> Completion {
+    Completion {
+        ceremony,
+        state_id: state_id.to_string(),
+        succeeded: false,
+    }
+}
+
+impl Completion {
+    /// Marque la cérémonie comme réussie
+    pub fn succeed(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        let started = STARTED.lock().ok().and_then(|mut started| started.remove(&self.state_id));
+        // Un identifiant inconnu vient du client : il n'est pas recopié dans les logs
+        let state_id = if started.is_some() { self.state_id.as_str() } else { "unknown" };
+        let duration_ms = started.map(|at| at.elapsed().as_millis() as u64);
+        let outcome = if self.succeeded { "success" } else { "failure" };
+        let ceremony = self.ceremony.as_str();
+
+        span(self.ceremony, state_id).in_scope(|| {
+            tracing::info!(target: "webauthn", ceremony, state_id, outcome, duration_ms, "Ceremony finished")
+        });
+    }
+}
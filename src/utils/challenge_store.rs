@@ -0,0 +1,88 @@
+//! Stockage borné des états de cérémonies WebAuthn en attente.
+//! Quand la limite est atteinte, les états les plus anciens sont oubliés en premier :
+//! une rafale de débuts de cérémonie ne peut pas faire grossir la mémoire sans limite.
+
+use std::collections::{HashMap, VecDeque};
+
+/// États en attente indexés par identifiant, dans l'ordre d'insertion
+pub struct ChallengeStore<T> {
+    states: HashMap<String, T>,
+    order: VecDeque<String>,
+}
+
+impl<T> Default for ChallengeStore<T> {
+    fn default() -> Self {
+        ChallengeStore {
+            states: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> ChallengeStore<T> {
+    /// Ajoute un état ; au-delà de `cap` états, les plus anciens sont évincés
+    pub fn insert(&mut self, id: String, state: T, cap: usize) {
+        let cap = cap.max(1);
+        while self.states.len() >= cap {
+            let Some(oldest) = self.order.pop_front() else { break };
+            self.states.remove(&oldest);
+        }
+
+        // Les identifiants des états déjà consommés restent dans `order` jusqu'au prochain compactage
+        if self.order.len() >= 2 * cap {
+            let states = &self.states;
+            self.order.retain(|id| states.contains_key(id));
+        }
+
+        if self.states.insert(id.clone(), state).is_none() {
+            self.order.push_back(id);
+        }
+    }
+
+    /// Retire et retourne l'état associé à `id`
+    pub fn remove(&mut self, id: &str) -> Option<T> {
+        self.states.remove(id)
+    }
+
+    #[cfg(test)]
+    pub fn contains_key(&self, id: &str) -> bool {
+        self.states.contains_key(id)
+    }
+
+    /// Nombre d'états en attente
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_states_are_evicted() {
+        let mut store = ChallengeStore::default();
+        for i in 0..5 {
+            store.insert(i.to_string(), i, 3);
+        }
+        assert_eq!(store.len(), 3);
+        assert!(!store.contains_key("0"));
+        assert!(!store.contains_key("1"));
+        assert!(store.contains_key("2"));
+        assert!(store.contains_key("4"));
+
+        // Un état consommé libère sa place sans évincer les autres
+        assert_eq!(store.remove("3"), Some(3));
+        store.insert("5".to_string(), 5, 3);
+        assert_eq!(store.len(), 3);
+        assert!(store.contains_key("2"));
+
+        // Les identifiants consommés ne s'accumulent pas
+        for i in 6..100 {
+            store.insert(i.to_string(), i, 3);
+            store.remove(&i.to_string());
+        }
+        assert!(store.order.len() <= 6);
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -0,0 +1,153 @@
+//! Langue des pages et des emails.
+//! La préférence enregistrée dans le profil l'emporte sur `Accept-Language`, puis l'anglais par défaut.
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Langue prise en charge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+/// Texte traduit
+#[derive(Clone, Copy, Debug)]
+pub enum Text {
+    ValidationSubject,
+    ValidationBody,
+    LoginLinkSubject,
+    LoginLinkBody,
+    RecoverySubject,
+    RecoveryBody,
+    LoginNoticeSubject,
+    LoginNoticeBody,
+    RecoveryNoticeSubject,
+    RecoveryNoticeBody,
+    Welcome,
+    WelcomeHint,
+    Login,
+    Register,
+    Logout,
+    AddPasskey,
+    CreatePost,
+}
+
+impl Locale {
+    /// Code de langue, utilisé dans l'attribut `lang` des pages
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+        }
+    }
+
+    /// Convertit une étiquette de langue (ex. `fr-CH`) ; seule la langue principale compte
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next().unwrap_or_default();
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Locale::En),
+            "fr" => Some(Locale::Fr),
+            _ => None,
+        }
+    }
+
+    /// Langue prise en charge la mieux notée (`q`) dans un en-tête `Accept-Language`
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut best: Option<(Locale, f32)> = None;
+        for range in header.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let Some(locale) = params.next().and_then(Locale::parse) else { continue };
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                best = Some((locale, quality));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+
+    /// Texte dans cette langue ; `{link}` est à remplacer par l'appelant
+    pub fn text(self, text: Text) -> &'static str {
+        match (self, text) {
+            (Locale::En, Text::ValidationSubject) => "Account Validation",
+            (Locale::Fr, Text::ValidationSubject) => "Validation du compte",
+            (Locale::En, Text::ValidationBody) => "Click here to validate your account: {link}",
+            (Locale::Fr, Text::ValidationBody) => "Cliquez ici pour valider votre compte : {link}",
+            (Locale::En, Text::LoginLinkSubject) => "Login link",
+            (Locale::Fr, Text::LoginLinkSubject) => "Lien de connexion",
+            (Locale::En, Text::LoginLinkBody) => "Click here to log in: {link}",
+            (Locale::Fr, Text::LoginLinkBody) => "Cliquez ici pour vous connecter : {link}",
+            (Locale::En, Text::RecoverySubject) => "Account Recovery",
+            (Locale::Fr, Text::RecoverySubject) => "Récupération du compte",
+            (Locale::En, Text::RecoveryBody) => "Click here to recover your account: {link}",
+            (Locale::Fr, Text::RecoveryBody) => "Cliquez ici pour récupérer votre compte : {link}",
+            (Locale::En, Text::LoginNoticeSubject) => "New sign-in to your account",
+            (Locale::Fr, Text::LoginNoticeSubject) => "Nouvelle connexion à votre compte",
+            (Locale::En, Text::LoginNoticeBody) => {
+                "A new sign-in to your account just happened. If it wasn't you, recover your account: {link}"
+            }
+            (Locale::Fr, Text::LoginNoticeBody) => {
+                "Une nouvelle connexion à votre compte vient d'avoir lieu. Si ce n'était pas vous, récupérez votre compte : {link}"
+            }
+            (Locale::En, Text::RecoveryNoticeSubject) => "Your account was recovered",
+            (Locale::Fr, Text::RecoveryNoticeSubject) => "Votre compte a été récupéré",
+            (Locale::En, Text::RecoveryNoticeBody) => {
+                "A new passkey was just set on your account through account recovery. If it wasn't you, recover your account: {link}"
+            }
+            (Locale::Fr, Text::RecoveryNoticeBody) => {
+                "Une nouvelle passkey vient d'être associée à votre compte par une récupération. Si ce n'était pas vous, récupérez votre compte : {link}"
+            }
+            (Locale::En, Text::Welcome) => "Welcome",
+            (Locale::Fr, Text::Welcome) => "Bienvenue",
+            (Locale::En, Text::WelcomeHint) => "Log in or sign up to continue.",
+            (Locale::Fr, Text::WelcomeHint) => "Connectez-vous ou inscrivez-vous pour continuer.",
+            (Locale::En, Text::Login) => "Login",
+            (Locale::Fr, Text::Login) => "Connexion",
+            (Locale::En, Text::Register) => "Register",
+            (Locale::Fr, Text::Register) => "Inscription",
+            (Locale::En, Text::Logout) => "Logout",
+            (Locale::Fr, Text::Logout) => "Déconnexion",
+            (Locale::En, Text::AddPasskey) => "Add a passkey",
+            (Locale::Fr, Text::AddPasskey) => "Ajouter une passkey",
+            (Locale::En, Text::CreatePost) => "Create a Post",
+            (Locale::Fr, Text::CreatePost) => "Créer un post",
+        }
+    }
+
+    /// Email dont le corps contient `link`
+    pub fn mail(self, subject: Text, body: Text, link: &str) -> (&'static str, String) {
+        (self.text(subject), self.text(body).replace("{link}", link))
+    }
+
+    /// Textes communs des pages, exposés aux templates sous `t`
+    pub fn page_texts(self) -> serde_json::Value {
+        json!({
+            "welcome": self.text(Text::Welcome),
+            "welcome_hint": self.text(Text::WelcomeHint),
+            "login": self.text(Text::Login),
+            "register": self.text(Text::Register),
+            "logout": self.text(Text::Logout),
+            "add_passkey": self.text(Text::AddPasskey),
+            "create_post": self.text(Text::CreatePost),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_language() {
+        assert_eq!(Locale::from_accept_language("fr-CH, fr;q=0.9, en;q=0.8"), Some(Locale::Fr));
+        assert_eq!(Locale::from_accept_language("de, en;q=0.5, fr;q=0.4"), Some(Locale::En));
+        assert_eq!(Locale::from_accept_language("FR"), Some(Locale::Fr));
+        assert_eq!(Locale::from_accept_language("fr;q=0, de"), None);
+        assert_eq!(Locale::from_accept_language(""), None);
+    }
+}
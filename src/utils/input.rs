@@ -1,24 +1,109 @@
 use regex::Regex;
-use serde::Deserialize;
-use validator::{Validate, ValidationError};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use serde::{Deserialize, Deserializer};
+use validator::{Validate, ValidateEmail, ValidationError, ValidationErrors};
+use crate::config::ProfileField;
+use crate::{config, consts};
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct UserRegistration {
+    #[serde(default, deserialize_with = "normalize_name")]
     #[validate(length(min = 1, max = 50))]
     #[validate(custom(function= "validate_name"))]
-    pub first_name: String,
+    #[validate(custom(function= "validate_name_bytes"))]
+    pub first_name: Option<String>,
     
+    #[serde(default, deserialize_with = "normalize_name")]
     #[validate(length(min = 1, max = 50))]
     #[validate(custom(function= "validate_name"))]
-    pub last_name: String,
+    #[validate(custom(function= "validate_name_bytes"))]
+    pub last_name: Option<String>,
 
-    #[validate(email)]
+    #[validate(custom(function = "validate_email_address"))]
     pub email: String,
 }
 
+impl UserRegistration {
+    fn field(&self, field: ProfileField) -> Option<&str> {
+        match field {
+            ProfileField::FirstName => self.first_name.as_deref(),
+            ProfileField::LastName => self.last_name.as_deref(),
+        }
+    }
+
+    /// Valide les champs présents, puis vérifie que les champs exigés par la configuration sont fournis
+    pub fn validate_profile(&self) -> Result<(), ValidationErrors> {
+        let mut errors = self.validate().err().unwrap_or_default();
+        for field in &config::get().required_fields {
+            if self.field(*field).is_none() {
+                errors.add(field.name(), ValidationError::new("required"));
+            }
+        }
+
+        // Un nom réservé pourrait servir à se faire passer pour l'équipe
+        let reserved = &config::get().reserved_names;
+        for field in ProfileField::ALL {
+            if self.field(field).is_some_and(|name| is_reserved(name, reserved)) {
+                errors.add(field.name(), ValidationError::new("name_reserved"));
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+// Les espaces en début et fin sont retirés et les suites d'espaces réduites à un seul,
+// avant validation et stockage. Un nom vide une fois normalisé est traité comme absent.
+fn normalize_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.and_then(|value| normalized(&value)))
+}
+
+fn normalized(name: &str) -> Option<String> {
+    Some(name.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|name| !name.is_empty())
+}
+
+// Les noms sont comparés sans casse, sans accents et sans espaces superflus.
+fn is_reserved(name: &str, reserved: &[String]) -> bool {
+    let name = folded(name);
+    reserved.iter().any(|reserved| folded(reserved) == name)
+}
+
+fn folded(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+        .chars()
+        // Ces lettres n'ont pas de décomposition canonique
+        .map(|c| match c {
+            'ł' => 'l',
+            'ø' => 'o',
+            c => c,
+        })
+        .collect()
+}
+
+// Nom affiché par l'authentificateur, construit à partir des noms fournis s'ils sont valides.
+pub(crate) fn display_name(first_name: Option<&str>, last_name: Option<&str>) -> Option<String> {
+    let parts: Vec<String> = [first_name, last_name]
+        .into_iter()
+        .flatten()
+        .filter_map(normalized)
+        .collect();
+    let valid = |name: &String| {
+        name.chars().count() <= 50 && validate_name(name).is_ok() && validate_name_bytes(name).is_ok()
+    };
+
+    (!parts.is_empty() && parts.iter().all(valid)).then(|| parts.join(" "))
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct MailValidation {
-    #[validate(email)]
+    #[validate(custom(function = "validate_email_address"))]
     pub email: String,
 }
 
@@ -29,6 +114,17 @@ pub struct PostValidation {
     pub content: String,
 }
 
+// Validation des emails ; une entrée démesurée est refusée avant de passer par les expressions régulières.
+pub(crate) fn validate_email_address(email: &str) -> Result<(), ValidationError> {
+    if email.len() > consts::MAX_EMAIL_LENGTH {
+        return Err(ValidationError::new("email_too_long"));
+    }
+    if !email.validate_email() {
+        return Err(ValidationError::new("email"));
+    }
+    Ok(())
+}
+
 // Validation des noms prenant en charge les caractères spéciaux et les accents.
 fn validate_name(username: &str) -> Result<(), ValidationError> {
     let re = Regex::new(r"^[a-zA-ZàáâäãåąčćęèéêëėįìíîïłńòóôöõøùúûüųūÿýżźñçčšžæÀÁÂÄÃÅĄĆČĖĘÈÉÊËÌÍÎÏĮŁŃÒÓÔÖÕØÙÚÛÜŲŪŸÝŻŹÑßÇŒÆČŠŽ∂ð ,.'-]+$").unwrap();
@@ -38,6 +134,14 @@ fn validate_name(username: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+// La limite en caractères ne borne pas la taille stockée : un caractère peut occuper jusqu'à 4 octets.
+fn validate_name_bytes(name: &str) -> Result<(), ValidationError> {
+    if name.len() > config::get().max_name_bytes {
+        return Err(ValidationError::new("name_too_many_bytes"));
+    }
+    Ok(())
+}
+
 // Validation de la description des posts en enlevant tout ce qui n'est pas des lettres, des chiffres, des espaces, ou des ponctuations.
 pub(crate) fn validate_description(description: &str) -> Result<(), ValidationError> {
     let re = Regex::new(r"^[\p{L}\p{N}\p{P}\p{Z}\n]+$").unwrap();
@@ -48,6 +152,41 @@ pub(crate) fn validate_description(description: &str) -> Result<(), ValidationEr
     Ok(())
 }
 
+// Validation du nom d'origine d'un fichier uploadé, conservé uniquement pour l'affichage.
+// Retourne le nom sans chemin, ou `None` si le client n'en a pas fourni.
+pub(crate) fn validate_filename(filename: &str) -> Result<Option<String>, ValidationError> {
+    // Ne garder que le dernier composant d'un éventuel chemin
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    // Les octets non UTF-8 sont remplacés par U+FFFD lors du décodage de l'en-tête
+    if name.contains(char::REPLACEMENT_CHARACTER) {
+        return Err(ValidationError::new("filename_invalid_utf8"));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(ValidationError::new("filename_contains_control_chars"));
+    }
+    if name.chars().count() > consts::MAX_FILENAME_LENGTH {
+        return Err(ValidationError::new("filename_too_long"));
+    }
+
+    Ok(Some(name.to_string()))
+}
+
+/// Cible de redirection après connexion : seul un chemin absolu du site est accepté.
+/// `//hôte` et `/\hôte` sont interprétés par les navigateurs comme une autre origine.
+pub(crate) fn validate_redirect_path(next: &str) -> Result<(), ValidationError> {
+    if !next.starts_with('/') || next.starts_with("//") {
+        return Err(ValidationError::new("redirect_not_relative"));
+    }
+    if next.contains('\\') || next.chars().any(char::is_control) {
+        return Err(ValidationError::new("redirect_invalid_chars"));
+    }
+    Ok(())
+}
+
 //Tests
 #[cfg(test)]
 mod tests {
@@ -74,6 +213,30 @@ mod tests {
         assert!(validate_name("@#$%").is_err());
     }
 
+    #[test]
+    fn test_validate_name_bytes() {
+        // 50 caractères de 2 octets : 100 octets, sous la limite par défaut
+        let accented = "é".repeat(50);
+        assert!(validate_name(&accented).is_ok());
+        assert!(validate_name_bytes(&accented).is_ok());
+
+        // 50 caractères de 3 octets : 150 octets, au-delà de la limite
+        let wide = "∂".repeat(50);
+        assert!(validate_name(&wide).is_ok());
+        assert_eq!(validate_name_bytes(&wide).unwrap_err().code, "name_too_many_bytes");
+
+        // Juste à la limite, puis un octet au-delà
+        assert!(validate_name_bytes(&format!("{}{}", "é".repeat(63), "ab")).is_ok());
+        assert!(validate_name_bytes(&format!("{}{}", "é".repeat(63), "abc")).is_err());
+
+        let user = UserRegistration {
+            first_name: Some(wide),
+            last_name: Some("Dupont".to_string()),
+            email: "jean.dupont@example.com".to_string(),
+        };
+        assert!(user.validate().unwrap_err().field_errors().contains_key("first_name"));
+    }
+
     #[test]
     fn test_validate_description() {
         // Tests valides
@@ -87,23 +250,155 @@ mod tests {
         assert!(validate_description("Texte avec des <script>alert('Coucou les assistants')</script> injections").is_err());
     }
 
+    #[test]
+    fn test_validate_filename() {
+        // Tests valides
+        assert_eq!(validate_filename("vacances.jpg").unwrap().as_deref(), Some("vacances.jpg"));
+        assert_eq!(validate_filename("photo d'été.jpg").unwrap().as_deref(), Some("photo d'été.jpg"));
+        assert_eq!(validate_filename("../../etc/passwd").unwrap().as_deref(), Some("passwd"));
+        assert_eq!(validate_filename("").unwrap(), None);
+
+        // Tests invalides
+        assert!(validate_filename(&format!("{}.jpg", "a".repeat(300))).is_err()); // Trop long
+        assert!(validate_filename("photo\n.jpg").is_err()); // Saut de ligne
+        assert!(validate_filename("photo\r\nX-Injected: 1.jpg").is_err());
+        assert!(validate_filename("photo\u{FFFD}.jpg").is_err()); // UTF-8 invalide
+    }
+
     #[test]
     fn test_user_registration_validation() {
         let valid_user = UserRegistration {
-            first_name: "Jean".to_string(),
-            last_name: "Dupont".to_string(),
+            first_name: Some("Jean".to_string()),
+            last_name: Some("Dupont".to_string()),
             email: "jean.dupont@example.com".to_string(),
         };
         assert!(valid_user.validate().is_ok());
 
         let invalid_user = UserRegistration {
-            first_name: "Jean123".to_string(),
-            last_name: "Dupont!".to_string(),
+            first_name: Some("Jean123".to_string()),
+            last_name: Some("Dupont!".to_string()),
             email: "invalid-email".to_string(),
         };
         assert!(invalid_user.validate().is_err());
     }
 
+    #[test]
+    fn test_names_are_normalized() {
+        let parse = |first_name: &str, last_name: &str| -> UserRegistration {
+            serde_json::from_value(serde_json::json!({
+                "first_name": first_name,
+                "last_name": last_name,
+                "email": "jean.dupont@example.com",
+            }))
+            .unwrap()
+        };
+
+        let padded = parse("  Jean ", "\tDupont\n");
+        assert_eq!(padded.first_name.as_deref(), Some("Jean"));
+        assert_eq!(padded.last_name.as_deref(), Some("Dupont"));
+        assert!(padded.validate_profile().is_ok());
+
+        let spaced = parse("Marie   Anne", "von  der \t Leyen");
+        assert_eq!(spaced.first_name.as_deref(), Some("Marie Anne"));
+        assert_eq!(spaced.last_name.as_deref(), Some("von der Leyen"));
+        assert_eq!(parse("Jean", "von der Leyen").last_name.as_deref(), Some("von der Leyen"));
+
+        // Un nom fait uniquement d'espaces est refusé
+        let blank = parse("   ", "Dupont");
+        assert_eq!(blank.first_name, None);
+        assert_eq!(blank.validate_profile().unwrap_err().field_errors()["first_name"][0].code, "required");
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(display_name(Some(" Jean "), Some("von  der Leyen")).as_deref(), Some("Jean von der Leyen"));
+        assert_eq!(display_name(Some("Jean"), None).as_deref(), Some("Jean"));
+        assert_eq!(display_name(None, Some("  ")), None);
+        assert_eq!(display_name(Some("Jean<script>"), Some("Dupont")), None);
+    }
+
+    #[tokio::test]
+    async fn test_optional_last_name() {
+        let without_last_name: UserRegistration = serde_json::from_value(serde_json::json!({
+            "first_name": "Jean",
+            "last_name": "",
+            "email": "jean.dupont@example.com",
+        }))
+        .unwrap();
+        assert_eq!(without_last_name.last_name, None);
+
+        // Par défaut, le nom reste obligatoire
+        let errors = without_last_name.validate_profile().unwrap_err();
+        assert_eq!(errors.field_errors()["last_name"][0].code, "required");
+        assert!(!errors.field_errors().contains_key("first_name"));
+
+        let config = config::Config {
+            required_fields: vec![ProfileField::FirstName],
+            ..Default::default()
+        };
+        config::scope(config, async {
+            assert!(without_last_name.validate_profile().is_ok());
+
+            // Un champ facultatif fourni est tout de même validé
+            let invalid = UserRegistration {
+                last_name: Some("Dupont!".to_string()),
+                ..without_last_name
+            };
+            assert!(invalid.validate_profile().unwrap_err().field_errors().contains_key("last_name"));
+
+            let without_first_name = UserRegistration {
+                first_name: None,
+                last_name: None,
+                email: "jean.dupont@example.com".to_string(),
+            };
+            assert!(without_first_name.validate_profile().unwrap_err().field_errors().contains_key("first_name"));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_reserved_names() {
+        let registration = |first_name: &str| UserRegistration {
+            first_name: Some(first_name.to_string()),
+            last_name: Some("Dupont".to_string()),
+            email: "jean.dupont@example.com".to_string(),
+        };
+
+        // Aucun nom n'est réservé par défaut
+        assert!(registration("Admin").validate_profile().is_ok());
+
+        let config = config::Config {
+            reserved_names: vec!["admin".to_string(), "Support".to_string()],
+            ..Default::default()
+        };
+        config::scope(config, async {
+            for name in ["admin", "ADMIN", "Ádmin", "àdmïn", "support", "SÚPPORT"] {
+                let errors = registration(name).validate_profile().unwrap_err();
+                assert_eq!(errors.field_errors()["first_name"][0].code, "name_reserved", "{name}");
+            }
+
+            let reserved_last_name = UserRegistration {
+                last_name: Some("Admin".to_string()),
+                ..registration("Jean")
+            };
+            assert!(reserved_last_name.validate_profile().unwrap_err().field_errors().contains_key("last_name"));
+
+            assert!(registration("Adminou").validate_profile().is_ok());
+            assert!(registration("José").validate_profile().is_ok());
+        })
+        .await;
+    }
+
+    #[test]
+    fn test_reserved_names_ignore_unicode_forms() {
+        let reserved = ["admin".to_string(), "Łukasz".to_string()];
+        // Accent combinant, forme précomposée et pleine chasse désignent le même nom
+        for name in ["a\u{0301}dmin", "\u{00E1}dmin", "ad\u{0308}\u{0323}min", "ＡＤＭＩＮ", "lukasz"] {
+            assert!(is_reserved(name, &reserved), "{name}");
+        }
+        assert!(!is_reserved("adminou", &reserved));
+    }
+
     #[test]
     fn test_mail_validation() {
         let valid_mail = MailValidation {
@@ -117,6 +412,26 @@ mod tests {
         assert!(invalid_mail.validate().is_err());
     }
 
+    #[test]
+    fn test_email_length_guard() {
+        let code = |email: &str| validate_email_address(email).unwrap_err().code;
+        // À la limite, l'email passe par la validation complète
+        assert_eq!(code(&format!("{}@example.com", "a".repeat(consts::MAX_EMAIL_LENGTH - 12))), "email");
+        assert_eq!(code(&format!("{}@example.com", "a".repeat(consts::MAX_EMAIL_LENGTH))), "email_too_long");
+        assert_eq!(code("invalid-email"), "email");
+        assert!(validate_email_address("test@example.com").is_ok());
+    }
+
+    #[test]
+    fn test_validate_redirect_path() {
+        assert!(validate_redirect_path("/home").is_ok());
+        assert!(validate_redirect_path("/posts?page=2#top").is_ok());
+
+        for next in ["https://evil.example", "//evil.example", "/\\evil.example", "home", "", "/\t/evil.example"] {
+            assert!(validate_redirect_path(next).is_err(), "{:?}", next);
+        }
+    }
+
     #[test]
     fn test_post_validation() {
         let valid_post = PostValidation {
@@ -0,0 +1,117 @@
+//! Preuve de travail demandée avant une inscription, pour freiner les inscriptions automatisées.
+//! Le client doit trouver un `nonce` tel que SHA-256(`challenge:nonce`) commence par
+//! `difficulty` bits à zéro.
+
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::config;
+use crate::database::now;
+use crate::utils::challenge_store::ChallengeStore;
+
+/// Durée de validité d'un challenge, en secondes
+const CHALLENGE_TTL_SECS: u64 = 5 * 60;
+
+/// Challenge envoyé au client
+#[derive(Serialize, Debug)]
+pub struct PowChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+}
+
+/// Solution renvoyée par le client avec la requête d'inscription
+#[derive(Deserialize)]
+pub struct PowSolution {
+    pub challenge: String,
+    pub nonce: String,
+}
+
+/// Challenges émis et non encore utilisés, avec leur date d'expiration.
+/// Bornés comme les états WebAuthn : au-delà de `max_pending_challenges`, les plus anciens sont évincés.
+static CHALLENGES: Lazy<Mutex<ChallengeStore<u64>>> = Lazy::new(Default::default);
+
+/// Émet un nouveau challenge à usage unique
+pub fn issue(difficulty: u32) -> PowChallenge {
+    let challenge = uuid::Uuid::new_v4().simple().to_string();
+    if let Ok(mut challenges) = CHALLENGES.lock() {
+        challenges.insert(challenge.clone(), now() + CHALLENGE_TTL_SECS, config::get().max_pending_challenges);
+    }
+    PowChallenge { challenge, difficulty }
+}
+
+/// Vérifie la solution ; le challenge est consommé même si la solution est fausse
+pub fn verify(solution: &PowSolution, difficulty: u32) -> bool {
+    let issued = CHALLENGES
+        .lock()
+        .ok()
+        .and_then(|mut challenges| challenges.remove(&solution.challenge))
+        .is_some_and(|expires_at| expires_at > now());
+
+    issued && leading_zero_bits(&hash(&solution.challenge, &solution.nonce)) >= difficulty
+}
+
+fn hash(challenge: &str, nonce: &str) -> Vec<u8> {
+    Sha256::digest(format!("{}:{}", challenge, nonce)).to_vec()
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Résout un challenge par force brute, comme le ferait le navigateur
+    pub(crate) fn solve(challenge: &PowChallenge) -> PowSolution {
+        let nonce = (0u64..)
+            .find(|nonce| {
+                leading_zero_bits(&hash(&challenge.challenge, &nonce.to_string())) >= challenge.difficulty
+            })
+            .unwrap();
+        PowSolution {
+            challenge: challenge.challenge.clone(),
+            nonce: nonce.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0x00, 0x0f]), 12);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn test_valid_solution() {
+        let challenge = issue(8);
+        let solution = solve(&challenge);
+        assert!(verify(&solution, 8));
+
+        // Un challenge ne peut servir qu'une fois
+        assert!(!verify(&solution, 8));
+    }
+
+    #[test]
+    fn test_invalid_solution() {
+        let challenge = issue(8);
+        let mut solution = solve(&challenge);
+        solution.nonce.push('x');
+        while leading_zero_bits(&hash(&solution.challenge, &solution.nonce)) >= 8 {
+            solution.nonce.push('x');
+        }
+        assert!(!verify(&solution, 8));
+
+        // Challenge jamais émis par le serveur
+        let forged = solve(&PowChallenge { challenge: "forged".to_string(), difficulty: 8 });
+        assert!(!verify(&forged, 8));
+    }
+}
@@ -0,0 +1,101 @@
+//! Limitation du nombre de requêtes par adresse IP (token bucket).
+//! Chaque IP dispose d'un seau rempli progressivement jusqu'à la limite par minute.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use once_cell::sync::Lazy;
+
+/// Au-delà de ce nombre d'IP suivies, les seaux pleins sont oubliés
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Délai annoncé quand aucun jeton ne sera jamais rendu (limite nulle ou limiteur inutilisable)
+pub const BLOCKED_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Remplit le seau selon le temps écoulé, sans dépasser la capacité
+    fn refill(&mut self, now: Instant, per_minute: u32) {
+        let rate = per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(per_minute as f64);
+        self.updated = now;
+    }
+}
+
+/// Seaux de jetons indexés par IP
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    /// Consomme un jeton pour `ip` ; en cas de dépassement, retourne le délai avant le prochain jeton
+    pub fn check(&mut self, ip: IpAddr, now: Instant, per_minute: u32) -> Result<(), Duration> {
+        // Une limite nulle refuse tout, sans calculer de délai infini
+        if per_minute == 0 {
+            return Err(BLOCKED_RETRY_AFTER);
+        }
+
+        if self.buckets.len() >= MAX_TRACKED_IPS {
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(now, per_minute);
+                bucket.tokens < per_minute as f64
+            });
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: per_minute as f64,
+            updated: now,
+        });
+        bucket.refill(now, per_minute);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let rate = per_minute as f64 / 60.0;
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+/// Limiteur partagé par les routes non authentifiées
+pub static RATE_LIMITER: Lazy<Mutex<RateLimiter>> = Lazy::new(Default::default);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let mut limiter = RateLimiter::default();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert!(limiter.check(ip, start, 2).is_ok());
+        }
+        let retry_after = limiter.check(ip, start, 2).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(30));
+
+        // Les autres IP ne sont pas affectées
+        assert!(limiter.check(IpAddr::from([192, 0, 2, 2]), start, 2).is_ok());
+
+        // Un jeton est rendu toutes les 30 secondes
+        assert!(limiter.check(ip, start + Duration::from_secs(30), 2).is_ok());
+        assert!(limiter.check(ip, start + Duration::from_secs(30), 2).is_err());
+    }
+
+    #[test]
+    fn test_zero_limit_refuses_without_panicking() {
+        let mut limiter = RateLimiter::default();
+        let ip = IpAddr::from([192, 0, 2, 3]);
+        assert_eq!(limiter.check(ip, Instant::now(), 0), Err(BLOCKED_RETRY_AFTER));
+    }
+}
@@ -0,0 +1,52 @@
+//! Challenges WebAuthn déjà utilisés, gardés pendant une durée configurable.
+//! Une réponse rejouée est refusée même si l'état de la cérémonie existe encore
+//! (soumission en double du même `state_id`, état restauré, etc.).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+use crate::config;
+use crate::database::now;
+
+/// Challenges consommés et leur date d'expiration
+#[derive(Default)]
+pub struct ReplayCache {
+    seen: HashMap<Vec<u8>, u64>,
+}
+
+impl ReplayCache {
+    /// Enregistre `challenge` pour `ttl_secs` secondes ; `false` s'il a déjà été utilisé
+    pub fn consume(&mut self, challenge: &[u8], ttl_secs: u64, now: u64) -> bool {
+        self.seen.retain(|_, expires_at| *expires_at > now);
+        if self.seen.contains_key(challenge) {
+            return false;
+        }
+        self.seen.insert(challenge.to_vec(), now.saturating_add(ttl_secs));
+        true
+    }
+}
+
+static CONSUMED: Lazy<Mutex<ReplayCache>> = Lazy::new(Default::default);
+
+/// Marque `challenge` comme utilisé ; `false` s'il l'a déjà été
+pub fn consume(challenge: &[u8]) -> bool {
+    let ttl_secs = config::get().replay_cache_secs;
+    CONSUMED.lock().map(|mut cache| cache.consume(challenge, ttl_secs, now())).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_is_consumed_once() {
+        let mut cache = ReplayCache::default();
+        assert!(cache.consume(b"challenge", 60, 1000));
+        assert!(!cache.consume(b"challenge", 60, 1030));
+        assert!(cache.consume(b"other", 60, 1030));
+
+        // Passé le délai, l'entrée est oubliée
+        assert!(cache.consume(b"challenge", 60, 1061));
+        assert_eq!(cache.seen.len(), 2);
+    }
+}
@@ -2,139 +2,982 @@
 //! Fournit des fonctions pour démarrer et compléter les processus d'enregistrement et d'authentification.
 //! Inclut également des mécanismes pour la gestion sécurisée des passkeys et des tokens de récupération.
 
-use std::collections::HashMap;
 use anyhow::{Result, Context};
+use serde::Serialize;
 use webauthn_rs::prelude::*;
 use once_cell::sync::Lazy;
 use url::Url;
-use tokio::sync::RwLock;
+use crate::config;
 use crate::database::user;
+use crate::utils::replay_cache;
 
-// Initialisation globale de WebAuthn
-static WEBAUTHN: Lazy<Webauthn> = Lazy::new(|| {
-    let rp_id = "localhost";
-    let rp_origin = Url::parse("http://localhost:8080").expect("Invalid RP origin URL");
+// Initialisation globale de WebAuthn ; la configuration est vérifiée au démarrage par `check_config`
+static WEBAUTHN: Lazy<Webauthn> = Lazy::new(|| build(&config::get()).expect("Invalid WebAuthn configuration"));
 
-    WebauthnBuilder::new(rp_id, &rp_origin)
-        .expect("Failed to initialize WebAuthn")
-        .build()
-        .expect("Failed to build WebAuthn instance")
-});
+fn build(config: &config::Config) -> Result<Webauthn> {
+    let rp_origin = Url::parse(&config.rp_origin)
+        .with_context(|| format!("Invalid WEBAUTHN_ORIGIN URL: {}", config.rp_origin))?;
+    if !matches!(rp_origin.scheme(), "http" | "https") {
+        anyhow::bail!("WEBAUTHN_ORIGIN must use http or https: {}", config.rp_origin);
+    }
+
+    WebauthnBuilder::new(&config.rp_id, &rp_origin)
+        .and_then(WebauthnBuilder::build)
+        .with_context(|| format!("WEBAUTHN_RP_ID {} is not valid for origin {}", config.rp_id, config.rp_origin))
+}
+
+/// Vérifie que l'identifiant et l'origine du relying party sont utilisables
+pub fn check_config(config: &config::Config) -> Result<()> {
+    build(config).map(|_| ())
+}
+
+/// Cause d'échec d'une cérémonie, renvoyée au client sous forme de code stable.
+/// Le détail de l'erreur de la librairie reste côté serveur.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CeremonyFailure {
+    ChallengeMismatch,
+    ChallengeReplayed,
+    OriginMismatch,
+    UserVerificationFailed,
+    CounterRegression,
+    AttestationFailed,
+    CredentialNotAllowed,
+    AuthenticatorDenied,
+    InvalidResponse,
+    Unknown,
+}
+
+impl CeremonyFailure {
+    /// Code transmis au frontend
+    pub fn code(self) -> &'static str {
+        match self {
+            CeremonyFailure::ChallengeMismatch => "CHALLENGE_MISMATCH",
+            CeremonyFailure::ChallengeReplayed => "CHALLENGE_REPLAYED",
+            CeremonyFailure::OriginMismatch => "ORIGIN_MISMATCH",
+            CeremonyFailure::UserVerificationFailed => "USER_VERIFICATION_FAILED",
+            CeremonyFailure::CounterRegression => "COUNTER_REGRESSION",
+            CeremonyFailure::AttestationFailed => "ATTESTATION_FAILED",
+            CeremonyFailure::CredentialNotAllowed => "CREDENTIAL_NOT_ALLOWED",
+            CeremonyFailure::AuthenticatorDenied => "AUTHENTICATOR_DENIED",
+            CeremonyFailure::InvalidResponse => "INVALID_RESPONSE",
+            CeremonyFailure::Unknown => "CEREMONY_FAILED",
+        }
+    }
+
+    /// Message destiné à l'utilisateur
+    pub fn message(self) -> &'static str {
+        match self {
+            CeremonyFailure::ChallengeMismatch => "This request has expired or was already used. Please try again.",
+            CeremonyFailure::ChallengeReplayed => "This request was already used. Please try again.",
+            CeremonyFailure::OriginMismatch => "The passkey was used from a different site.",
+            CeremonyFailure::UserVerificationFailed => "Your authenticator did not verify your identity (PIN or biometrics).",
+            CeremonyFailure::CounterRegression => "This passkey may have been cloned. Please contact support.",
+            CeremonyFailure::AttestationFailed => "This authenticator could not be verified.",
+            CeremonyFailure::CredentialNotAllowed => "This passkey cannot be used here.",
+            CeremonyFailure::AuthenticatorDenied => {
+                "This authenticator model has a known security issue and is not accepted. Please use another one."
+            }
+            CeremonyFailure::InvalidResponse => "The authenticator response was malformed.",
+            CeremonyFailure::Unknown => "The passkey operation failed.",
+        }
+    }
+
+    /// Classe une erreur de webauthn-rs
+    fn classify(err: &WebauthnError) -> Self {
+        use WebauthnError::*;
+        match err {
+            MismatchedChallenge | ChallengeNotFound => CeremonyFailure::ChallengeMismatch,
+            InvalidRPOrigin | InvalidRPIDHash | CredentialCrossOrigin => CeremonyFailure::OriginMismatch,
+            UserNotPresent | UserNotVerified | InconsistentUserVerificationPolicy => {
+                CeremonyFailure::UserVerificationFailed
+            }
+            CredentialPossibleCompromise => CeremonyFailure::CounterRegression,
+            AttestationNotSupported
+            | AttestationStatementMapInvalid
+            | AttestationStatementResponseInvalid
+            | AttestationStatementSigMissing
+            | AttestationStatementSigInvalid
+            | AttestationStatementAlgMismatch
+            | AttestationTrustFailure
+            | AttestationChainNotTrusted(_)
+            | AttestationUntrustedAaguid
+            | AttestationCertificateRequirementsNotMet
+            | AttestationNotVerifiable
+            | MissingAttestationCredentialData
+            | TrustFailure => CeremonyFailure::AttestationFailed,
+            CredentialExcludedFromRequest
+            | CredentialAlteredAlgFromRequest
+            | CredentialInsecureCryptography
+            | CredentialNotFound => CeremonyFailure::CredentialNotAllowed,
+            InvalidClientDataType
+            | ParseBase64Failure(_)
+            | ParseCBORFailure(_)
+            | ParseJSONFailure(_)
+            | ParseNOMFailure
+            | ParseInsufficientBytesAvailable => CeremonyFailure::InvalidResponse,
+            _ => CeremonyFailure::Unknown,
+        }
+    }
+
+    /// Retrouve la cause attachée à une erreur de `complete_registration` ou `complete_authentication`
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.downcast_ref::<CeremonyFailure>().copied().unwrap_or(CeremonyFailure::Unknown)
+    }
+}
+
+impl std::fmt::Display for CeremonyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Attache la cause classée à une erreur de la librairie, sans perdre l'erreur d'origine
+fn classified(err: WebauthnError) -> anyhow::Error {
+    let failure = CeremonyFailure::classify(&err);
+    anyhow::Error::new(err).context(failure)
+}
+
+/// Champ manquant ou mal formé dans la réponse WebAuthn envoyée par le navigateur
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseError {
+    Missing(&'static str),
+    Malformed(&'static str),
+    /// Structure conforme champ par champ mais refusée par la librairie
+    Invalid(String),
+}
+
+impl ResponseError {
+    /// Chemin du champ en cause, s'il est connu
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            ResponseError::Missing(field) | ResponseError::Malformed(field) => Some(field),
+            ResponseError::Invalid(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseError::Missing(field) => write!(f, "Missing field `{}`", field),
+            ResponseError::Malformed(field) => {
+                write!(f, "Malformed field `{}`: expected base64url data or a byte array", field)
+            }
+            ResponseError::Invalid(err) => write!(f, "Invalid response format: {}", err),
+        }
+    }
+}
+
+// Champs binaires exigés dans chaque type de réponse
+const REGISTRATION_FIELDS: &[&str] = &["rawId", "response.clientDataJSON", "response.attestationObject"];
+const AUTHENTICATION_FIELDS: &[&str] = &[
+    "rawId",
+    "response.clientDataJSON",
+    "response.authenticatorData",
+    "response.signature",
+];
+
+/// Donnée binaire acceptée par webauthn-rs : base64 (url ou standard) ou tableau d'octets
+fn is_binary(value: &serde_json::Value) -> bool {
+    use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    match value {
+        serde_json::Value::String(s) => [URL_SAFE_NO_PAD, URL_SAFE, STANDARD, STANDARD_NO_PAD]
+            .iter()
+            .any(|engine| engine.decode(s).is_ok()),
+        serde_json::Value::Array(bytes) => bytes
+            .iter()
+            .all(|byte| byte.as_u64().is_some_and(|byte| byte <= u8::MAX as u64)),
+        _ => false,
+    }
+}
+
+/// Décode un challenge base64url, avec ou sans padding, tel qu'il figure dans les options
+/// envoyées au navigateur ou dans `clientDataJSON`
+pub fn decode_challenge(challenge: &str) -> Option<Vec<u8>> {
+    use base64::engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    URL_SAFE_NO_PAD.decode(challenge).or_else(|_| URL_SAFE.decode(challenge)).ok()
+}
+
+/// Challenge signé par l'authentificateur, lu dans `clientDataJSON`
+fn client_challenge(client_data_json: &[u8]) -> Result<Vec<u8>> {
+    let client_data: serde_json::Value = serde_json::from_slice(client_data_json)
+        .context(CeremonyFailure::InvalidResponse)?;
+    client_data.get("challenge")
+        .and_then(|c| c.as_str())
+        .and_then(decode_challenge)
+        .context(CeremonyFailure::InvalidResponse)
+}
+
+/// Refuse un challenge déjà présenté, même si l'état de la cérémonie existe encore
+fn check_replay(challenge: &[u8]) -> Result<()> {
+    if !replay_cache::consume(challenge) {
+        return Err(anyhow::anyhow!(CeremonyFailure::ChallengeReplayed));
+    }
+    Ok(())
+}
+
+/// Compare deux challenges en temps constant : la durée ne dépend pas de la position
+/// du premier octet différent (seule la longueur n'est pas protégée)
+fn challenges_match(received: &[u8], expected: &[u8]) -> bool {
+    use subtle::ConstantTimeEq;
+    received.ct_eq(expected).into()
+}
+
+/// Vérifie les champs exigés avant de laisser serde convertir la réponse
+fn parse_credential<T: serde::de::DeserializeOwned>(
+    value: serde_json::Value,
+    fields: &[&'static str],
+) -> std::result::Result<T, ResponseError> {
+    if !value.get("id").is_some_and(|id| id.is_string()) {
+        return Err(ResponseError::Missing("id"));
+    }
+    if !value.get("response").is_some_and(|response| response.is_object()) {
+        return Err(ResponseError::Missing("response"));
+    }
+    for &field in fields {
+        let found = field.split('.').try_fold(&value, |node, key| node.get(key));
+        match found {
+            None | Some(serde_json::Value::Null) => return Err(ResponseError::Missing(field)),
+            Some(data) if !is_binary(data) => return Err(ResponseError::Malformed(field)),
+            Some(_) => {}
+        }
+    }
+    serde_json::from_value(value).map_err(|err| ResponseError::Invalid(err.to_string()))
+}
 
-// Store sécurisé pour les passkeys
-pub static CREDENTIAL_STORE: Lazy<RwLock<HashMap<String, Passkey>>> = Lazy::new(Default::default);
+/// Convertit la réponse du navigateur à une cérémonie d'enregistrement
+pub fn parse_registration_response(
+    mut value: serde_json::Value,
+) -> std::result::Result<RegisterPublicKeyCredential, ResponseError> {
+    // Le navigateur nomme le résultat de l'extension `credProps`, webauthn-rs attend `cred_props`
+    if let Some(extensions) = value.get_mut("extensions").and_then(|e| e.as_object_mut()) {
+        if let Some(cred_props) = extensions.remove("credProps") {
+            extensions.insert("cred_props".to_string(), cred_props);
+        }
+    }
+    parse_credential(value, REGISTRATION_FIELDS)
+}
+
+/// Convertit la réponse du navigateur à une cérémonie d'authentification
+pub fn parse_authentication_response(
+    value: serde_json::Value,
+) -> std::result::Result<PublicKeyCredential, ResponseError> {
+    parse_credential(value, AUTHENTICATION_FIELDS)
+}
 
 // Structure pour stocker l'état d'enregistrement
 pub(crate) struct StoredRegistrationState {
     pub registration_state: PasskeyRegistration,
-    pub challenge: String,
+    pub user_handle: Uuid,
+}
+
+/// Retourne l'identifiant WebAuthn stable du compte, en le créant si besoin
+fn user_handle_for(user_email: &str) -> Result<Uuid> {
+    match user::get(user_email)? {
+        Some(existing) => match existing.user_handle {
+            Some(handle) => Ok(handle),
+            None => {
+                // Compte antérieur aux identifiants stables : on en attribue un maintenant
+                let handle = Uuid::new_v4();
+                user::set_user_handle(user_email, handle)?;
+                Ok(handle)
+            }
+        },
+        // Nouveau compte : l'identifiant sera enregistré à la création de l'utilisateur
+        None => Ok(Uuid::new_v4()),
+    }
+}
+
+/// Options passées à `navigator.credentials.create`, renvoyées au navigateur sous `publicKey`.
+/// La sérialisation est celle de la librairie, déjà au format camelCase attendu.
+#[derive(Clone, Debug)]
+pub struct RegistrationOptions(CreationChallengeResponse);
+
+#[cfg(test)]
+impl RegistrationOptions {
+    /// Challenge à signer par l'authentificateur
+    pub fn challenge(&self) -> &[u8] {
+        self.0.public_key.challenge.as_ref()
+    }
+
+    /// Nom du compte et nom affiché transmis à l'authentificateur
+    pub fn user(&self) -> (&str, &str) {
+        let user = &self.0.public_key.user;
+        (&user.name, &user.display_name)
+    }
+}
+
+impl Serialize for RegistrationOptions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.0.public_key.serialize(serializer)
+    }
 }
 
 /// Démarrer l'enregistrement WebAuthn
 pub async fn begin_registration(
     user_email: &str,
     user_display_name: &str,
-) -> Result<(serde_json::Value, PasskeyRegistration)> {
-    let user_id = Uuid::new_v4();
-    
-    let (ccr,reg_state) = WEBAUTHN.start_passkey_registration(
+) -> Result<(RegistrationOptions, StoredRegistrationState)> {
+    let user_id = user_handle_for(user_email)?;
+
+    // Les authentificateurs déjà enregistrés sur le compte ne peuvent pas l'être une seconde fois
+    let exclude_credentials = user::get(user_email)?
+        .map(|existing| existing.passkeys.iter().map(|passkey| passkey.cred_id().clone()).collect::<Vec<_>>())
+        .filter(|credentials| !credentials.is_empty());
+
+    let (mut ccr,reg_state) = WEBAUTHN.start_passkey_registration(
         user_id,
         user_email,
         user_display_name,
-        None,
-    ).expect("Failed to start registration.");
+        exclude_credentials,
+    ).context("Failed to start registration")?;
 
-    let public_key = ccr.public_key;
+    // Ne proposer au navigateur que les algorithmes autorisés
+    let allowed = &config::get().allowed_algorithms;
+    ccr.public_key
+        .pub_key_cred_params
+        .retain(|param| allowed.iter().any(|alg| *alg as i64 == param.alg));
 
     Ok((
-        serde_json::json!({
-            "rp": public_key.rp,
-            "user": {
-                "id": user_id,
-                "name": user_display_name,
-                "displayName": user_display_name,
-            },
-            "challenge": public_key.challenge,
-            "pubKeyCredParams": public_key.pub_key_cred_params,
-            "timeout": public_key.timeout,
-            "authenticatorSelection": public_key.authenticator_selection,
-            "attestation": public_key.attestation,
-        }),
-        reg_state,
+        RegistrationOptions(ccr),
+        StoredRegistrationState {
+            registration_state: reg_state,
+            user_handle: user_id,
+        },
     ))
 }
 
-/// Compléter l'enregistrement WebAuthn
+/// Compléter l'enregistrement WebAuthn ; retourne la passkey créée, à enregistrer sur le compte
 pub async fn complete_registration(
-    user_email: &str,
     response: &RegisterPublicKeyCredential,
     stored_state: &StoredRegistrationState,
-) -> Result<()> {
+) -> Result<Passkey> {
+    check_replay(&client_challenge(response.response.client_data_json.as_ref())?)?;
+
     let passkey = WEBAUTHN.finish_passkey_registration(
         response,
         &stored_state.registration_state,
-    ).context("Failed to finish registration")?;
+    ).map_err(classified)?;
+
+    check_algorithm(&passkey, &config::get().allowed_algorithms)
+        .context(CeremonyFailure::CredentialNotAllowed)?;
+    check_aaguid(response, &config::get().denied_aaguids)
+        .context(CeremonyFailure::AuthenticatorDenied)?;
 
-    // Stocker la passkey
-    let mut store = CREDENTIAL_STORE.write().await;
-    store.insert(user_email.to_string(), passkey.clone());
+    // La passkey est rendue à l'appelant, qui l'enregistre sur le compte
+    Ok(passkey)
+}
+
+/// Résultat de l'extension credProps : la passkey créée est-elle découvrable (resident key) ?
+/// `None` si le navigateur ne l'a pas indiqué. La valeur n'est pas signée et ne sert qu'à l'interface,
+/// par exemple pour ne proposer la connexion sans email qu'aux comptes qui peuvent l'utiliser.
+pub fn discoverable(response: &RegisterPublicKeyCredential) -> Option<bool> {
+    response.extensions.cred_props.as_ref().map(|props| props.rk)
+}
 
+/// Refuse une passkey dont l'algorithme ne fait pas partie de la liste autorisée
+fn check_algorithm(passkey: &Passkey, allowed: &[COSEAlgorithm]) -> Result<()> {
+    if !allowed.contains(passkey.cred_algorithm()) {
+        return Err(anyhow::anyhow!("Credential algorithm not allowed"));
+    }
+    Ok(())
+}
+
+/// Refuse un authentificateur dont le modèle (AAGUID) figure dans la liste des modèles refusés
+fn check_aaguid(response: &RegisterPublicKeyCredential, denied: &[Uuid]) -> Result<()> {
+    if denied.is_empty() {
+        return Ok(());
+    }
+    let aaguid = aaguid(response.response.attestation_object.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("Missing AAGUID in attestation"))?;
+    if denied.contains(&aaguid) {
+        return Err(anyhow::anyhow!("Authenticator {} is denied", aaguid));
+    }
     Ok(())
 }
 
+/// Extrait l'AAGUID des données d'authentification de l'objet d'attestation :
+/// rpIdHash (32 octets), flags (1), compteur (4), puis l'AAGUID (16)
+fn aaguid(attestation_object: &[u8]) -> Option<Uuid> {
+    use serde_cbor_2::Value;
+    let Value::Map(map) = serde_cbor_2::from_slice(attestation_object).ok()? else {
+        return None;
+    };
+    let Some(Value::Bytes(auth_data)) = map.get(&Value::Text("authData".to_string())) else {
+        return None;
+    };
+    // Bit AT : les données de la credential attestée sont présentes
+    if auth_data.get(32)? & 0x40 == 0 {
+        return None;
+    }
+    Uuid::from_slice(auth_data.get(37..53)?).ok()
+}
+
 /// Démarrer l'authentification WebAuthn
 pub async fn begin_authentication(user_email: &str) -> Result<(serde_json::Value, PasskeyAuthentication)> {
 
-    let user_data = user::get(user_email)
+    let user_data = user::get(user_email)?
         .ok_or_else(|| anyhow::anyhow!("User not found"))?;
 
-    let passkey = user_data.passkey
-        .ok_or_else(|| anyhow::anyhow!("User has no passkey registered"))?;
-    
-    // Démarrer l'authentification
-    let (rcr,passkey_auth) = WEBAUTHN.start_passkey_authentication(
-        std::slice::from_ref(&passkey)
-    ).context("Failed to start authentication")?;
+    if user_data.passkeys.is_empty() {
+        return Err(anyhow::anyhow!("No credentials registered"));
+    }
 
-    let public_key = rcr.public_key;
+    // Démarrer l'authentification avec toutes les passkeys du compte
+    start_authentication(&user_data.passkeys)
+}
 
-    Ok((
-        serde_json::json!({
-            "challenge": public_key.challenge,
-            "timeout": public_key.timeout,
-            "rpId": public_key.rp_id,
-            "allowCredentials": public_key.allow_credentials,
-         }),
-        passkey_auth,
-    ))
+/// Travail comparable à `begin_authentication` pour un compte absent ou non vérifié :
+/// le temps de réponse ne doit pas révéler l'état du compte. Le résultat est ignoré.
+pub async fn simulate_authentication(user_email: &str) {
+    let _ = user::get(user_email);
+
+    // Même génération de challenge et même sérialisation que pour un vrai début d'authentification
+    if let Ok((ccr, _)) = WEBAUTHN.start_passkey_registration(Uuid::new_v4(), user_email, user_email, None) {
+        let _ = serde_json::to_value(&ccr.public_key);
+    }
+}
+
+/// Débuter une authentification limitée à une seule passkey du compte,
+/// pour prouver la possession d'un authentificateur précis
+pub async fn begin_authentication_with(
+    user_email: &str,
+    credential_id: &CredentialID,
+) -> Result<(serde_json::Value, PasskeyAuthentication)> {
+    let user_data = user::get(user_email)?
+        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+
+    let passkey = user_data.passkeys
+        .into_iter()
+        .find(|passkey| passkey.cred_id() == credential_id)
+        .ok_or_else(|| anyhow::anyhow!("Passkey not found"))?;
+
+    start_authentication(&[passkey])
+}
+
+fn start_authentication(passkeys: &[Passkey]) -> Result<(serde_json::Value, PasskeyAuthentication)> {
+    let (rcr,passkey_auth) = WEBAUTHN.start_passkey_authentication(passkeys)
+        .context("Failed to start authentication")?;
+
+    let public_key = serde_json::to_value(&rcr.public_key)
+        .context("Failed to serialize authentication options")?;
+
+    Ok((public_key, passkey_auth))
 }
 
-/// Compléter l'authentification WebAuthn
+/// Compléter l'authentification WebAuthn ; la passkey utilisée est mise à jour sur le compte
 pub async fn complete_authentication(
+    user_email: &str,
     response: &PublicKeyCredential,
     state: &PasskeyAuthentication,
-    server_challenge: &str,
+    server_challenge: &[u8],
 ) -> Result<()> {
-    let client_data_bytes = response.response.client_data_json.as_ref();
-    let client_data_json = String::from_utf8(client_data_bytes.to_vec())
-        .context("Failed to decode client_data_json")?;
-
-    let client_data: serde_json::Value = serde_json::from_str(&client_data_json)
-        .context("Failed to parse client_data_json")?;
-
     // Vérification du challenge
-    let challenge = client_data.get("challenge")
-        .and_then(|c| c.as_str())
-        .context("Missing challenge")?;
-
-    if challenge != server_challenge {
-        return Err(anyhow::anyhow!("Invalid challenge"));
+    let challenge = client_challenge(response.response.client_data_json.as_ref())?;
+    if !challenges_match(&challenge, server_challenge) {
+        return Err(anyhow::anyhow!(CeremonyFailure::ChallengeMismatch));
     }
-    
-    WEBAUTHN.finish_passkey_authentication(
+    check_replay(&challenge)?;
+
+    let result = WEBAUTHN.finish_passkey_authentication(
         response,
         state
-    ).context("Failed to finish authentication")?;
+    ).map_err(classified)?;
+
+    user::record_authentication(user_email, &result)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Passkey de test avec un identifiant unique (clé publique ES256 sans clé privée associée)
+    pub(crate) fn test_passkey() -> Passkey {
+        let cred_id = Base64UrlSafeData::from(Uuid::new_v4().as_bytes().to_vec());
+        serde_json::from_value(serde_json::json!({
+            "cred": {
+                "cred_id": cred_id,
+                "cred": {
+                    "type_": "ES256",
+                    "key": { "EC_EC2": {
+                        "curve": "SECP256R1",
+                        "x": "2Lm2kpmYecstu26lDsWMU7gPDpsLxV_VCJdibCUKkmQ",
+                        "y": "zYiUG_NMgTU9XK3MR9Euii6qa3MyfFfcvDtR0KNVTPc",
+                    }},
+                },
+                "counter": 0,
+                "transports": null,
+                "user_verified": true,
+                "backup_eligible": true,
+                "backup_state": true,
+                "registration_policy": "required",
+                "extensions": {
+                    "cred_protect": "Ignored",
+                    "hmac_create_secret": "NotRequested",
+                    "appid": "NotRequested",
+                    "cred_props": "Ignored",
+                },
+                "attestation": { "data": "None", "metadata": "None" },
+                "attestation_format": "none",
+            }
+        }))
+        .expect("Invalid test passkey")
+    }
+
+    /// Authentificateur logiciel ES256 pour simuler un navigateur dans les tests
+    pub(crate) struct SoftAuthenticator {
+        key: openssl::ec::EcKey<openssl::pkey::Private>,
+        pub cred_id: Vec<u8>,
+    }
+
+    fn b64(bytes: &[u8]) -> serde_json::Value {
+        serde_json::to_value(Base64UrlSafeData::from(bytes.to_vec())).unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        openssl::sha::sha256(data).to_vec()
+    }
+
+    impl SoftAuthenticator {
+        pub(crate) fn new() -> Self {
+            let group = openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap();
+            Self {
+                key: openssl::ec::EcKey::generate(&group).unwrap(),
+                cred_id: Uuid::new_v4().as_bytes().to_vec(),
+            }
+        }
+
+        fn client_data(kind: &str, options: &serde_json::Value) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({
+                "type": kind,
+                "challenge": options["challenge"],
+                "origin": "http://localhost:8080",
+                "crossOrigin": false,
+            }))
+            .unwrap()
+        }
+
+        /// En-tête des données d'authentificateur : hash du RP, flags UP | UV (| AT) et compteur
+        fn auth_data(flags: u8) -> Vec<u8> {
+            let mut data = sha256(b"localhost");
+            data.push(flags);
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            use serde_cbor_2::Value;
+            let group = self.key.group();
+            let mut ctx = openssl::bn::BigNumContext::new().unwrap();
+            let (mut x, mut y) = (openssl::bn::BigNum::new().unwrap(), openssl::bn::BigNum::new().unwrap());
+            self.key
+                .public_key()
+                .affine_coordinates(group, &mut x, &mut y, &mut ctx)
+                .unwrap();
+
+            let map = [
+                (1, Value::Integer(2)),
+                (3, Value::Integer(-7)),
+                (-1, Value::Integer(1)),
+                (-2, Value::Bytes(x.to_vec_padded(32).unwrap())),
+                (-3, Value::Bytes(y.to_vec_padded(32).unwrap())),
+            ]
+            .into_iter()
+            .map(|(k, v)| (Value::Integer(k), v))
+            .collect();
+            serde_cbor_2::to_vec(&Value::Map(map)).unwrap()
+        }
+
+        /// Répond aux options de `navigator.credentials.create`
+        pub(crate) fn register(&self, options: &impl Serialize) -> serde_json::Value {
+            use serde_cbor_2::Value;
+            let options = serde_json::to_value(options).unwrap();
+            let mut auth_data = Self::auth_data(0x45);
+            auth_data.extend_from_slice(&[0u8; 16]); // AAGUID
+            auth_data.extend_from_slice(&(self.cred_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.cred_id);
+            auth_data.extend_from_slice(&self.cose_key());
+
+            let attestation = Value::Map(
+                [
+                    ("fmt", Value::Text("none".to_string())),
+                    ("attStmt", Value::Map(Default::default())),
+                    ("authData", Value::Bytes(auth_data)),
+                ]
+                .into_iter()
+                .map(|(k, v)| (Value::Text(k.to_string()), v))
+                .collect(),
+            );
+
+            serde_json::json!({
+                "id": b64(&self.cred_id),
+                "rawId": b64(&self.cred_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": b64(&serde_cbor_2::to_vec(&attestation).unwrap()),
+                    "clientDataJSON": b64(&Self::client_data("webauthn.create", &options)),
+                },
+                "extensions": {},
+            })
+        }
+
+        /// Répond aux options de `navigator.credentials.get`
+        pub(crate) fn authenticate(&self, options: &serde_json::Value) -> serde_json::Value {
+            let auth_data = Self::auth_data(0x05);
+            let client_data = Self::client_data("webauthn.get", options);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&sha256(&client_data));
+            let signature = openssl::ecdsa::EcdsaSig::sign(&sha256(&signed), &self.key)
+                .and_then(|sig| sig.to_der())
+                .unwrap();
+
+            serde_json::json!({
+                "id": b64(&self.cred_id),
+                "rawId": b64(&self.cred_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": b64(&auth_data),
+                    "clientDataJSON": b64(&client_data),
+                    "signature": b64(&signature),
+                },
+                "extensions": {},
+            })
+        }
+    }
+
+    fn keys(value: &serde_json::Value) -> Vec<&str> {
+        value.as_object().unwrap().keys().map(String::as_str).collect()
+    }
+
+    #[tokio::test]
+    async fn test_registration_options_use_webauthn_names() {
+        let (options, _) = begin_registration("jean@example.com", "Jean Dupont").await.unwrap();
+        let public_key = serde_json::to_value(&options).unwrap();
+
+        let keys = keys(&public_key);
+        for expected in ["rp", "user", "challenge", "pubKeyCredParams", "timeout", "authenticatorSelection", "attestation"] {
+            assert!(keys.contains(&expected), "missing {}", expected);
+        }
+        assert!(keys.iter().all(|key| !key.contains('_')));
+        assert!(public_key["user"]["displayName"].is_string());
+        assert!(public_key["authenticatorSelection"]["userVerification"].is_string());
+
+        // Le challenge se lit sur le type, sans passer par le JSON
+        assert_eq!(options.challenge().len(), 32);
+        assert_eq!(decode_challenge(public_key["challenge"].as_str().unwrap()).unwrap(), options.challenge());
+    }
+
+    #[tokio::test]
+    async fn test_pub_key_cred_params_is_algorithm_list() {
+        let (options, _) = begin_registration("jean@example.com", "Jean Dupont").await.unwrap();
+        let public_key = serde_json::to_value(&options).unwrap();
+
+        let params = public_key["pubKeyCredParams"].as_array().unwrap();
+        assert!(!params.is_empty());
+        for param in params {
+            assert_eq!(keys(param).len(), 2);
+            assert_eq!(param["type"], "public-key");
+            assert!(param["alg"].is_i64());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registration_reuses_user_handle() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let handle = Uuid::new_v4();
+        user::create(&email, Some("Jean"), Some("Dupont"), handle).unwrap();
+
+        // Réenregistrement (mode reset) : l'identifiant d'origine est conservé
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let expected = Base64UrlSafeData::from(handle.as_bytes().to_vec());
+        assert_eq!(serde_json::to_value(&options).unwrap()["user"]["id"], serde_json::to_value(expected).unwrap());
+        assert_eq!(state.user_handle, handle);
+
+        let (_, again) = begin_registration(&email, &email).await.unwrap();
+        assert_eq!(again.user_handle, handle);
+    }
+
+    #[tokio::test]
+    async fn test_registration_assigns_handle_to_legacy_user() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        user::clear_user_handle(&email).unwrap();
+        assert_eq!(user::get(&email).unwrap().unwrap().user_handle, None);
+
+        // Un identifiant est généré puis conservé pour les cérémonies suivantes
+        let (_, state) = begin_registration(&email, &email).await.unwrap();
+        assert_ne!(state.user_handle, Uuid::nil());
+        assert_eq!(user::get(&email).unwrap().unwrap().user_handle, Some(state.user_handle));
+        let (_, again) = begin_registration(&email, &email).await.unwrap();
+        assert_eq!(again.user_handle, state.user_handle);
+    }
+
+    #[tokio::test]
+    async fn test_soft_authenticator_ceremonies() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let authenticator = SoftAuthenticator::new();
+
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        // La passkey est rendue directement, sans passer par un état partagé
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        assert_eq!(passkey.cred_id().as_ref(), authenticator.cred_id.as_slice());
+        user::create(&email, Some("Jean"), Some("Dupont"), state.user_handle).unwrap();
+        user::set_passkey(&email, passkey).unwrap();
+
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        let challenge = decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&options)).unwrap();
+        complete_authentication(&email, &response, &auth_state, &challenge).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_completion_is_rejected() {
+        let authenticator = SoftAuthenticator::new();
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+
+        // Enregistrement : la même réponse, avec le même état, ne sert qu'une fois
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        let err = complete_registration(&response, &state).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::ChallengeReplayed);
+        user::create(&email, Some("Jean"), Some("Dupont"), state.user_handle).unwrap();
+        user::set_passkey(&email, passkey).unwrap();
+
+        // Authentification
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        let challenge = decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&options)).unwrap();
+        complete_authentication(&email, &response, &auth_state, &challenge).await.unwrap();
+        let err = complete_authentication(&email, &response, &auth_state, &challenge).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::ChallengeReplayed);
+    }
+
+    #[tokio::test]
+    async fn test_registration_excludes_existing_credentials() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let passkey = test_passkey();
+        user::set_passkey(&email, passkey.clone()).unwrap();
+
+        let (options, _) = begin_registration(&email, &email).await.unwrap();
+        let public_key = serde_json::to_value(&options).unwrap();
+        let excluded = public_key["excludeCredentials"].as_array().unwrap();
+        assert_eq!(excluded.len(), 1);
+        assert_eq!(excluded[0]["id"], serde_json::to_value(passkey.cred_id()).unwrap());
+
+        // Un nouveau compte n'a rien à exclure
+        let (options, _) = begin_registration("new@example.com", "new@example.com").await.unwrap();
+        let public_key = serde_json::to_value(&options).unwrap();
+        assert!(public_key.get("excludeCredentials").is_none_or(|value| value.is_null()));
+    }
+
+    #[tokio::test]
+    async fn test_any_registered_passkey_authenticates() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        user::set_passkey(&email, test_passkey()).unwrap();
+
+        let authenticator = SoftAuthenticator::new();
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        user::add_passkey(&email, passkey, usize::MAX).unwrap();
+
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        assert_eq!(options["allowCredentials"].as_array().unwrap().len(), 2);
+        let challenge = decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&options)).unwrap();
+        complete_authentication(&email, &response, &auth_state, &challenge).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authentication_updates_last_used() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        let other = test_passkey();
+        user::set_passkey(&email, other.clone()).unwrap();
+
+        let authenticator = SoftAuthenticator::new();
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        user::add_passkey(&email, passkey, usize::MAX).unwrap();
+        assert!(user::get(&email).unwrap().unwrap().passkey_last_used.is_empty());
+
+        let before = crate::database::now();
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        let challenge = decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&options)).unwrap();
+        complete_authentication(&email, &response, &auth_state, &challenge).await.unwrap();
+
+        // Seule la passkey utilisée est datée
+        let last_used = user::get(&email).unwrap().unwrap().passkey_last_used;
+        assert_eq!(last_used.len(), 1);
+        assert!(last_used[&user::credential_key(&authenticator.cred_id)] >= before);
+        assert!(!last_used.contains_key(&user::credential_key(other.cred_id())));
+    }
+
+    /// Copie de `passkey` dont le compteur de signatures vaut `counter`
+    fn with_counter(passkey: &Passkey, counter: u32) -> Passkey {
+        let mut value = serde_json::to_value(passkey).unwrap();
+        value["cred"]["counter"] = counter.into();
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_challenge_comparison() {
+        let challenge = decode_challenge("AAECAwQFBgcICQoLDA0ODw").unwrap();
+        assert_eq!(challenge, (0..16).collect::<Vec<u8>>());
+        // Le padding éventuel ne change pas la valeur décodée
+        assert_eq!(decode_challenge("AAECAwQFBgcICQoLDA0ODw==").unwrap(), challenge);
+        assert!(decode_challenge("not a challenge!").is_none());
+
+        assert!(challenges_match(&challenge, &challenge.clone()));
+        let mut tampered = challenge.clone();
+        tampered[15] ^= 1;
+        assert!(!challenges_match(&tampered, &challenge));
+        assert!(!challenges_match(&challenge[..8], &challenge));
+        assert!(!challenges_match(&[], &challenge));
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_classified() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let authenticator = SoftAuthenticator::new();
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let passkey = complete_registration(&response, &state).await.unwrap();
+        user::create(&email, Some("Jean"), Some("Dupont"), state.user_handle).unwrap();
+        user::set_passkey(&email, passkey.clone()).unwrap();
+
+        // Réponse à un autre challenge que celui émis
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        let (other_options, _) = begin_authentication(&email).await.unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&other_options)).unwrap();
+        let challenge = &decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let err = complete_authentication(&email, &response, &auth_state, challenge).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::ChallengeMismatch);
+
+        // Compteur inférieur à celui enregistré : clé possiblement clonée
+        user::set_passkey(&email, with_counter(&passkey, 5)).unwrap();
+        let (options, auth_state) = begin_authentication(&email).await.unwrap();
+        let response = serde_json::from_value(authenticator.authenticate(&options)).unwrap();
+        let challenge = &decode_challenge(options["challenge"].as_str().unwrap()).unwrap();
+        let err = complete_authentication(&email, &response, &auth_state, challenge).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::CounterRegression);
+
+        // Authentificateur déjà enregistré sur le compte
+        let (options, state) = begin_registration(&email, &email).await.unwrap();
+        let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+        let err = complete_registration(&response, &state).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::CredentialNotAllowed);
+
+        // Les erreurs non classées restent génériques
+        assert_eq!(CeremonyFailure::of(&anyhow::anyhow!("boom")), CeremonyFailure::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_response_fields_are_checked() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        let authenticator = SoftAuthenticator::new();
+        let (options, _) = begin_registration(&email, &email).await.unwrap();
+        let registration = authenticator.register(&options);
+        assert!(parse_registration_response(registration.clone()).is_ok());
+
+        let mut missing = registration.clone();
+        missing["response"].as_object_mut().unwrap().remove("clientDataJSON");
+        let err = parse_registration_response(missing).unwrap_err();
+        assert_eq!(err, ResponseError::Missing("response.clientDataJSON"));
+        assert_eq!(err.to_string(), "Missing field `response.clientDataJSON`");
+
+        let mut malformed = registration.clone();
+        malformed["response"]["attestationObject"] = serde_json::json!("not base64!");
+        let err = parse_registration_response(malformed).unwrap_err();
+        assert_eq!(err, ResponseError::Malformed("response.attestationObject"));
+
+        // Tableau d'octets, comme l'envoie le frontend
+        let mut bytes = registration.clone();
+        bytes["rawId"] = serde_json::json!(authenticator.cred_id);
+        assert!(parse_registration_response(bytes).is_ok());
+
+        let mut out_of_range = registration;
+        out_of_range["rawId"] = serde_json::json!([1, 2, 300]);
+        assert_eq!(parse_registration_response(out_of_range).unwrap_err(), ResponseError::Malformed("rawId"));
+
+        // Une réponse d'enregistrement n'est pas une assertion
+        let (options, _) = begin_registration(&email, &email).await.unwrap();
+        let err = parse_authentication_response(authenticator.register(&options)).unwrap_err();
+        assert_eq!(err, ResponseError::Missing("response.authenticatorData"));
+        assert_eq!(err.field(), Some("response.authenticatorData"));
+    }
+
+    #[test]
+    fn test_check_algorithm() {
+        let passkey = test_passkey(); // ES256
+        assert!(check_algorithm(&passkey, &[COSEAlgorithm::ES256, COSEAlgorithm::RS256]).is_ok());
+        assert!(check_algorithm(&passkey, &[COSEAlgorithm::RS256, COSEAlgorithm::EDDSA]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_denied_aaguid_is_rejected() {
+        // L'authentificateur logiciel annonce l'AAGUID nul
+        let authenticator = SoftAuthenticator::new();
+        let register = |denied: Vec<Uuid>| {
+            let authenticator = &authenticator;
+            let config = config::Config { denied_aaguids: denied, ..Default::default() };
+            config::scope(config, async move {
+                let (options, state) = begin_registration("jean@example.com", "Jean").await.unwrap();
+                let response = serde_json::from_value(authenticator.register(&options)).unwrap();
+                complete_registration(&response, &state).await
+            })
+        };
+
+        let err = register(vec![Uuid::nil()]).await.unwrap_err();
+        assert_eq!(CeremonyFailure::of(&err), CeremonyFailure::AuthenticatorDenied);
+
+        assert!(register(vec![Uuid::new_v4()]).await.is_ok());
+        assert!(register(Vec::new()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_registration_options_only_allowed_algorithms() {
+        let config = config::Config {
+            allowed_algorithms: vec![COSEAlgorithm::ES256],
+            ..Default::default()
+        };
+        let (options, _) = config::scope(config, begin_registration("jean@example.com", "Jean"))
+            .await
+            .unwrap();
+        let public_key = serde_json::to_value(&options).unwrap();
+
+        let algs: Vec<i64> = public_key["pubKeyCredParams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["alg"].as_i64().unwrap())
+            .collect();
+        assert_eq!(algs, vec![COSEAlgorithm::ES256 as i64]);
+    }
+
+    #[tokio::test]
+    async fn test_authentication_options_use_webauthn_names() {
+        let email = format!("{}@example.com", Uuid::new_v4().simple());
+        user::create(&email, Some("Jean"), Some("Dupont"), Uuid::new_v4()).unwrap();
+        user::set_passkey(&email, test_passkey()).unwrap();
+
+        let (public_key, _) = begin_authentication(&email).await.unwrap();
+
+        let keys = keys(&public_key);
+        for expected in ["challenge", "timeout", "rpId", "allowCredentials", "userVerification"] {
+            assert!(keys.contains(&expected), "missing {}", expected);
+        }
+        assert!(keys.iter().all(|key| !key.contains('_')));
+    }
+}